@@ -0,0 +1,205 @@
+use std::path::PathBuf;
+
+use mp3_player::{
+    audio::AudioBackend,
+    song::{Playlist, RepeatBehavior, ShuffleBehavior},
+};
+
+pub const USAGE: &str = "\
+Usage: mp3-player [options] [files, folders or .m3u playlists...]
+       mp3-player ctl <command>
+
+Remote control:
+  ctl <command>                  send a command to the running player and print its status as
+                                 JSON. Commands: play [index], pause, toggle, stop, next, prev,
+                                 seek <[+-]seconds>, volume <[+-]0-100>, add <path>,
+                                 remove <index>, clear, repeat <none|all|single>,
+                                 shuffle [on|off|smart], crossfade <seconds>, speed <0.5-3>,
+                                 sleep <minutes|song|<n> songs|off>, status
+
+Options:
+  --headless                     play in the terminal without opening a window
+  --shuffle                      play the songs in a random order
+  --repeat=single|all|none       set the repeat behavior
+  --start=<index|time>           start at the n-th song (starting at 1), or at a time into the
+                                 first song (90s, 1:30 or 1:02:03)
+  --volume=<0-100>               set the volume in percent
+  --music-dir=<path>             folder the file dialogs start in, instead of the XDG music folder
+  --print-default-config         print a config file with all default settings
+  -h, --help                     print this help";
+
+pub enum StartPosition {
+    Index(usize),
+    Time(f32),
+}
+
+#[derive(Default)]
+pub struct Args {
+    pub help: bool,
+    pub print_default_config: bool,
+    pub headless: bool,
+    pub paths: Vec<PathBuf>,
+    pub shuffle: bool,
+    pub repeat: Option<RepeatBehavior>,
+    pub start: Option<StartPosition>,
+    pub volume: Option<f32>,
+    pub music_dir: Option<PathBuf>,
+}
+
+fn parse_time(value: &str) -> Option<f32> {
+    if let Some(seconds) = value.strip_suffix('s') {
+        return seconds.parse::<f32>().ok().filter(|v| *v >= 0.0);
+    }
+    // [[h:]m:]s
+    let mut seconds = 0.0;
+    for part in value.split(':') {
+        let part = part.parse::<f32>().ok().filter(|v| *v >= 0.0)?;
+        seconds = seconds * 60.0 + part;
+    }
+    Some(seconds)
+}
+
+fn parse_start(value: &str) -> Option<StartPosition> {
+    if !value.contains(':') && !value.ends_with('s') {
+        return match value.parse::<usize>() {
+            Ok(0) | Err(_) => None,
+            Ok(idx) => Some(StartPosition::Index(idx - 1)),
+        };
+    }
+    parse_time(value).map(StartPosition::Time)
+}
+
+impl Args {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut me = Self::default();
+        let mut only_paths = false;
+
+        for arg in args {
+            if only_paths || !arg.starts_with('-') || arg == "-" {
+                me.paths.push(PathBuf::from(arg));
+                continue;
+            }
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (arg.as_str(), None),
+            };
+            match (name, value) {
+                ("--", None) => only_paths = true,
+                ("-h" | "--help", None) => me.help = true,
+                ("--print-default-config", None) => me.print_default_config = true,
+                ("--headless", None) => me.headless = true,
+                ("--shuffle", None) => me.shuffle = true,
+                ("--repeat", Some(value)) => me.repeat = Some(value.parse()?),
+                ("--start", Some(value)) => {
+                    me.start = Some(parse_start(value).ok_or_else(|| {
+                        format!("invalid start '{value}', expected an index (starting at 1) or a time")
+                    })?)
+                }
+                ("--volume", Some(value)) => {
+                    let volume = value
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|v| (0.0..=100.0).contains(v))
+                        .ok_or_else(|| {
+                            format!("invalid volume '{value}', expected a number from 0 to 100")
+                        })?;
+                    me.volume = Some(volume / 100.0);
+                }
+                ("--music-dir", Some(value)) => me.music_dir = Some(PathBuf::from(value)),
+                ("--repeat" | "--start" | "--volume" | "--music-dir", None) => {
+                    return Err(format!("{name} expects a value ({name}=...)"))
+                }
+                _ => return Err(format!("unknown option '{arg}'")),
+            }
+        }
+
+        Ok(me)
+    }
+
+    /// Adds the paths to the playlist and starts playing according to the options. Without paths
+    /// or `--start`, whatever is already playing keeps playing.
+    pub fn apply<B: AudioBackend>(
+        &self,
+        playlist: &mut Playlist<B>,
+        audio: &mut B,
+        screen_height: i32,
+    ) {
+        for path in &self.paths {
+            if let Err(err) = playlist.add_song_by_path(path) {
+                eprintln!("Failed to add {}: {err}", path.display());
+            }
+        }
+        if self.shuffle {
+            playlist.set_shuffle_behavior(ShuffleBehavior::Shuffle);
+        }
+        if let Some(repeat) = self.repeat {
+            playlist.repeat_behavior = repeat;
+        }
+        if let Some(volume) = self.volume {
+            audio.set_master_volume(volume);
+        }
+
+        if self.paths.is_empty() && self.start.is_none() {
+            return;
+        }
+        match self.start {
+            Some(StartPosition::Index(idx)) if idx >= playlist.len() => {
+                eprintln!(
+                    "Cannot start at song #{}, there are only {}",
+                    idx + 1,
+                    playlist.len()
+                );
+                playlist.play_ignore_err(playlist.first_song(), audio, screen_height);
+            }
+            Some(StartPosition::Index(idx)) => {
+                playlist.play_ignore_err(idx, audio, screen_height);
+                // a new order that starts with this song, instead of skipping what came before it
+                playlist.set_shuffle_behavior(playlist.shuffle_behavior());
+            }
+            Some(StartPosition::Time(time)) => {
+                playlist.play_ignore_err(playlist.first_song(), audio, screen_height);
+                playlist.seek(time, audio);
+            }
+            None => playlist.play_ignore_err(playlist.first_song(), audio, screen_height),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use mp3_player::audio_null::NullBackend;
+
+    use super::*;
+
+    fn start_at(name: &str, start: &str) -> Option<usize> {
+        let dir =
+            std::env::temp_dir().join(format!("mp3-player-test-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut args = vec![format!("--start={start}")];
+        for idx in 0..3 {
+            let path = dir.join(format!("song {idx}.mp3"));
+            fs::write(&path, "").unwrap();
+            args.push(path.display().to_string());
+        }
+
+        let args = Args::parse(args).unwrap();
+        let mut playlist: Playlist<NullBackend> = Playlist::default();
+        let mut audio = NullBackend::new();
+        args.apply(&mut playlist, &mut audio, 0);
+        _ = fs::remove_dir_all(&dir);
+        playlist.currently_playing_id()
+    }
+
+    #[test]
+    fn starts_at_the_given_song() {
+        assert_eq!(start_at("start-index", "2"), Some(1));
+        assert_eq!(start_at("start-time", "0:05"), Some(0));
+    }
+
+    #[test]
+    fn starts_at_the_first_song_if_there_are_fewer() {
+        assert_eq!(start_at("start-too-far", "4"), Some(0));
+    }
+}
@@ -0,0 +1,79 @@
+use std::{fmt::Display, path::Path};
+
+use crate::{channels::ChannelSettings, equalizer::EqualizerBands};
+
+/// Everything the playlist needs from an audio output.
+///
+/// A backend owns the device state (master volume etc.), while the streams it hands out are owned
+/// by whoever loaded them (usually a `PlayingSong`) and passed back in for every operation.
+pub trait AudioBackend {
+    type Stream;
+
+    /// Loads the file at `path` as a stream. The stream is not playing yet.
+    fn load_stream(&mut self, path: &Path) -> Result<Self::Stream, PlayError>;
+
+    fn play(&mut self, stream: &mut Self::Stream);
+    fn pause(&mut self, stream: &mut Self::Stream);
+    fn resume(&mut self, stream: &mut Self::Stream);
+    fn is_playing(&self, stream: &Self::Stream) -> bool;
+    /// Whether the stream stopped by itself because it played to the end. False while it's paused
+    /// or before `play` was called.
+    fn has_ended(&self, stream: &Self::Stream) -> bool;
+
+    /// Volume of this stream alone from 0 to 1, on top of the master volume.
+    fn set_volume(&mut self, stream: &mut Self::Stream, volume: f32);
+
+    /// Plays the stream `speed` times as fast without changing its pitch. Times stay in seconds of
+    /// the stream itself, so `time_played` advances faster than the wall clock.
+    fn set_speed(&mut self, stream: &mut Self::Stream, speed: f32);
+
+    /// Seeks to `position` seconds from the start of the stream.
+    fn seek(&mut self, stream: &mut Self::Stream, position: f32);
+
+    /// Seconds played since the start of the stream.
+    fn time_played(&self, stream: &Self::Stream) -> f32;
+    /// Length of the stream in seconds.
+    fn time_length(&self, stream: &Self::Stream) -> f32;
+
+    /// Has to be called regularly (once a frame) to keep the stream buffers filled.
+    fn update(&mut self, stream: &mut Self::Stream);
+
+    fn master_volume(&self) -> f32;
+    fn set_master_volume(&mut self, volume: f32);
+
+    /// Filters everything that is played with the bands, `None` turns the equalizer off. This
+    /// applies to all streams at once, so songs that crossfade share it.
+    fn set_equalizer(&mut self, bands: Option<EqualizerBands>);
+
+    /// Balance, mono and so on for everything that is played, after the equalizer.
+    fn set_channels(&mut self, channels: ChannelSettings);
+
+    /// Decodes the whole file into `sink`, independent of any playing streams. This is called
+    /// from other threads, so it can't use the backend itself.
+    fn decode(path: &Path, sink: &mut dyn SampleSink) -> Result<(), PlayError>;
+}
+
+/// Receives decoded audio, see `AudioBackend::decode`.
+pub trait SampleSink {
+    /// Called once before any samples.
+    fn format(&mut self, sample_rate: u32, channels: u32);
+    /// Interleaved samples from -1 to 1, in as many chunks as the backend likes.
+    fn samples(&mut self, samples: &[f32]);
+}
+
+#[derive(Debug)]
+pub enum PlayError {
+    IoError(String),
+    FileNameInvalid,
+    Unsupported,
+}
+
+impl Display for PlayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IoError(err) => f.write_str(err),
+            Self::FileNameInvalid => f.write_str("the file name contains invalid characters"),
+            Self::Unsupported => f.write_str("not supported by this audio backend"),
+        }
+    }
+}
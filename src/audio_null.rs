@@ -0,0 +1,187 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use crate::{
+    audio::{AudioBackend, PlayError, SampleSink},
+    channels::ChannelSettings,
+    equalizer::EqualizerBands,
+};
+
+/// A backend that doesn't output anything. Streams only keep track of their position, which
+/// advances either by wall-clock time or by a fixed step on every `update` call.
+pub struct NullBackend {
+    master_volume: f32,
+    default_length: f32,
+    lengths: HashMap<PathBuf, f32>,
+    step: Option<f32>,
+    equalizer: Option<EqualizerBands>,
+    channels: ChannelSettings,
+}
+
+pub struct NullStream {
+    length: f32,
+    position: f32,
+    playing: bool,
+    volume: f32,
+    speed: f32,
+    last_update: Option<Instant>,
+}
+
+impl Default for NullBackend {
+    fn default() -> Self {
+        Self {
+            master_volume: 1.0,
+            default_length: 180.0,
+            lengths: HashMap::new(),
+            step: None,
+            equalizer: None,
+            channels: ChannelSettings::default(),
+        }
+    }
+}
+
+impl NullBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advance playing streams by `step` seconds per `update` call instead of by elapsed time.
+    pub fn with_step(mut self, step: f32) -> Self {
+        self.step = Some(step);
+        self
+    }
+
+    /// Length reported for every stream that has no length set via `set_length`.
+    pub fn with_default_length(mut self, length: f32) -> Self {
+        self.default_length = length;
+        self
+    }
+
+    /// The bands last passed to `set_equalizer`.
+    pub fn equalizer(&self) -> Option<EqualizerBands> {
+        self.equalizer
+    }
+
+    /// The settings last passed to `set_channels`.
+    pub fn channels(&self) -> ChannelSettings {
+        self.channels
+    }
+
+    pub fn set_length<P: AsRef<Path>>(&mut self, path: P, length: f32) {
+        self.lengths.insert(path.as_ref().to_path_buf(), length);
+    }
+}
+
+impl AudioBackend for NullBackend {
+    type Stream = NullStream;
+
+    fn load_stream(&mut self, path: &Path) -> Result<NullStream, PlayError> {
+        if path.to_str().is_none() {
+            return Err(PlayError::FileNameInvalid);
+        }
+        if let Err(err) = fs::metadata(path) {
+            return Err(PlayError::IoError(err.to_string()));
+        }
+
+        Ok(NullStream {
+            length: self
+                .lengths
+                .get(path)
+                .copied()
+                .unwrap_or(self.default_length),
+            position: 0.0,
+            playing: false,
+            volume: 1.0,
+            speed: 1.0,
+            last_update: None,
+        })
+    }
+
+    fn play(&mut self, stream: &mut NullStream) {
+        stream.position = 0.0;
+        stream.playing = true;
+        stream.last_update = Some(Instant::now());
+    }
+
+    fn pause(&mut self, stream: &mut NullStream) {
+        stream.playing = false;
+    }
+
+    fn resume(&mut self, stream: &mut NullStream) {
+        if stream.position < stream.length {
+            stream.playing = true;
+            stream.last_update = Some(Instant::now());
+        }
+    }
+
+    fn is_playing(&self, stream: &NullStream) -> bool {
+        stream.playing
+    }
+
+    fn has_ended(&self, stream: &NullStream) -> bool {
+        !stream.playing && stream.position >= stream.length
+    }
+
+    fn set_volume(&mut self, stream: &mut NullStream, volume: f32) {
+        stream.volume = volume;
+    }
+
+    fn set_speed(&mut self, stream: &mut NullStream, speed: f32) {
+        stream.speed = speed;
+    }
+
+    fn seek(&mut self, stream: &mut NullStream, position: f32) {
+        stream.position = position.clamp(0.0, stream.length);
+    }
+
+    fn time_played(&self, stream: &NullStream) -> f32 {
+        stream.position
+    }
+
+    fn time_length(&self, stream: &NullStream) -> f32 {
+        stream.length
+    }
+
+    fn update(&mut self, stream: &mut NullStream) {
+        if !stream.playing {
+            return;
+        }
+        let now = Instant::now();
+        let elapsed = match (self.step, stream.last_update) {
+            (Some(step), _) => step,
+            (None, Some(last)) => (now - last).as_secs_f32(),
+            (None, None) => 0.0,
+        };
+        stream.last_update = Some(now);
+        stream.position += elapsed * stream.speed;
+        if stream.position >= stream.length {
+            // like a non-looping raylib stream, stop at the end
+            stream.position = stream.length;
+            stream.playing = false;
+        }
+    }
+
+    fn master_volume(&self) -> f32 {
+        self.master_volume
+    }
+
+    fn set_master_volume(&mut self, volume: f32) {
+        self.master_volume = volume;
+    }
+
+    fn set_equalizer(&mut self, bands: Option<EqualizerBands>) {
+        self.equalizer = bands;
+    }
+
+    fn set_channels(&mut self, channels: ChannelSettings) {
+        self.channels = channels;
+    }
+
+    fn decode(_path: &Path, _sink: &mut dyn SampleSink) -> Result<(), PlayError> {
+        Err(PlayError::Unsupported)
+    }
+}
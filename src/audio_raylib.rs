@@ -0,0 +1,369 @@
+use std::{
+    ffi::{c_int, c_uint, c_void, CStr, CString},
+    path::Path,
+    sync::{Mutex, PoisonError},
+    time::Instant,
+};
+
+use raylib::audio::{Music, RaylibAudio};
+
+use crate::{
+    audio::{AudioBackend, PlayError, SampleSink},
+    channels::ChannelSettings,
+    equalizer::{Equalizer, EqualizerBands},
+    speed::PitchCorrector,
+};
+
+/// raylib mixes everything into stereo floats before the mixed processor sees it.
+const DEVICE_CHANNELS: usize = 2;
+const SAMPLE_RATES: [u32; 8] = [22050, 32000, 44100, 48000, 88200, 96000, 176400, 192000];
+
+/// How many frames `decode` reads at once.
+const DECODE_FRAMES: usize = 4096;
+
+// `MusicContextType` of raylib, which decoder a music stream uses
+const MUSIC_AUDIO_WAV: c_int = 1;
+const MUSIC_AUDIO_OGG: c_int = 2;
+const MUSIC_AUDIO_MP3: c_int = 4;
+const MUSIC_AUDIO_QOA: c_int = 5;
+
+// The decoders raylib reads music streams with, they are compiled into it. FLAC isn't enabled in
+// every raylib build, so it isn't linked here.
+extern "C" {
+    fn drwav_read_pcm_frames_f32(wav: *mut c_void, frames: u64, out: *mut f32) -> u64;
+    fn drmp3_read_pcm_frames_f32(mp3: *mut c_void, frames: u64, out: *mut f32) -> u64;
+    fn stb_vorbis_get_samples_float_interleaved(
+        vorbis: *mut c_void,
+        channels: c_int,
+        out: *mut f32,
+        len: c_int,
+    ) -> c_int;
+    fn qoaplay_decode(qoa: *mut c_void, out: *mut f32, frames: c_int) -> c_uint;
+}
+
+/// Processors don't get any userdata, so the effects live here.
+static DSP: Mutex<DeviceDsp> = Mutex::new(DeviceDsp::new());
+
+/// Plays through the raylib audio device. Only the audio device is opened, so this works without
+/// a window as well.
+pub struct RaylibBackend {
+    audio: RaylibAudio,
+    dsp_attached: bool,
+}
+
+/// raylib doesn't tell paused and finished streams apart, so that is tracked here.
+pub struct RaylibStream {
+    music: Music,
+    started: bool,
+    paused: bool,
+}
+
+impl RaylibBackend {
+    pub fn init() -> Self {
+        Self {
+            audio: RaylibAudio::init_audio_device(),
+            dsp_attached: false,
+        }
+    }
+
+    /// The processor only runs while there is something to do.
+    fn attach_dsp(&mut self, dsp: &mut DeviceDsp) {
+        let active =
+            dsp.equalizer.is_some() || dsp.pitch_corrector.is_some() || dsp.channels.is_some();
+        if active && !self.dsp_attached {
+            unsafe { raylib::ffi::AttachAudioMixedProcessor(Some(process_dsp)) };
+        } else if !active && self.dsp_attached {
+            unsafe { raylib::ffi::DetachAudioMixedProcessor(Some(process_dsp)) };
+            // the sample rate estimate is kept for the next time
+            dsp.counting_since = None;
+        }
+        self.dsp_attached = active;
+    }
+}
+
+impl AudioBackend for RaylibBackend {
+    type Stream = RaylibStream;
+
+    fn load_stream(&mut self, path: &Path) -> Result<RaylibStream, PlayError> {
+        let (path_str, c_path) = c_path(path)?;
+        // same as Music::load_music_stream, which needs a RaylibThread that we don't have when
+        // running without a window
+        let music = unsafe { raylib::ffi::LoadMusicStream(c_path.as_ptr()) };
+        if music.stream.buffer.is_null() {
+            return Err(PlayError::IoError(format!(
+                "music could not be loaded from file {path_str}"
+            )));
+        }
+        let mut music = unsafe { Music::from_raw(music) };
+        music.looping = false;
+
+        Ok(RaylibStream {
+            music,
+            started: false,
+            paused: false,
+        })
+    }
+
+    fn play(&mut self, stream: &mut RaylibStream) {
+        self.audio.play_music_stream(&mut stream.music);
+        stream.started = true;
+        stream.paused = false;
+    }
+
+    fn pause(&mut self, stream: &mut RaylibStream) {
+        self.audio.pause_music_stream(&mut stream.music);
+        stream.paused = true;
+    }
+
+    fn resume(&mut self, stream: &mut RaylibStream) {
+        self.audio.resume_music_stream(&mut stream.music);
+        stream.paused = false;
+    }
+
+    fn is_playing(&self, stream: &RaylibStream) -> bool {
+        self.audio.is_music_stream_playing(&stream.music)
+    }
+
+    fn has_ended(&self, stream: &RaylibStream) -> bool {
+        // non-looping streams get stopped by `UpdateMusicStream` once the last frames are queued
+        stream.started && !stream.paused && !self.audio.is_music_stream_playing(&stream.music)
+    }
+
+    fn set_volume(&mut self, stream: &mut RaylibStream, volume: f32) {
+        self.audio.set_music_volume(&mut stream.music, volume)
+    }
+
+    fn set_speed(&mut self, stream: &mut RaylibStream, speed: f32) {
+        // resampling keeps the time of the stream itself in `GetMusicTimePlayed`, only the pitch
+        // needs to be fixed
+        self.audio.set_music_pitch(&mut stream.music, speed);
+
+        let mut dsp = DSP.lock().unwrap_or_else(PoisonError::into_inner);
+        if speed == 1.0 {
+            dsp.pitch_corrector = None;
+        } else if let Some(corrector) = dsp.pitch_corrector.as_mut() {
+            corrector.set_speed(speed);
+        } else {
+            let sample_rate = dsp.sample_rate;
+            // all streams play at the same speed, so correcting the mixed output works for
+            // crossfades as well
+            drop(dsp);
+            let corrector = PitchCorrector::new(speed, sample_rate, DEVICE_CHANNELS);
+            dsp = DSP.lock().unwrap_or_else(PoisonError::into_inner);
+            dsp.pitch_corrector = Some(corrector);
+        }
+        self.attach_dsp(&mut dsp);
+    }
+
+    fn seek(&mut self, stream: &mut RaylibStream, position: f32) {
+        unsafe { raylib::ffi::SeekMusicStream(*stream.music, position) }
+    }
+
+    fn time_played(&self, stream: &RaylibStream) -> f32 {
+        self.audio.get_music_time_played(&stream.music)
+    }
+
+    fn time_length(&self, stream: &RaylibStream) -> f32 {
+        self.audio.get_music_time_length(&stream.music)
+    }
+
+    fn update(&mut self, stream: &mut RaylibStream) {
+        self.audio.update_music_stream(&mut stream.music)
+    }
+
+    fn master_volume(&self) -> f32 {
+        unsafe { raylib::ffi::GetMasterVolume() }
+    }
+
+    fn set_master_volume(&mut self, volume: f32) {
+        self.audio.set_master_volume(volume)
+    }
+
+    fn set_equalizer(&mut self, bands: Option<EqualizerBands>) {
+        let mut dsp = DSP.lock().unwrap_or_else(PoisonError::into_inner);
+        match (bands, dsp.equalizer.as_mut()) {
+            (Some(bands), Some(equalizer)) => equalizer.set_bands(bands),
+            (Some(bands), None) => dsp.equalizer = Some(Equalizer::new(bands, dsp.sample_rate)),
+            (None, _) => dsp.equalizer = None,
+        }
+        self.attach_dsp(&mut dsp);
+    }
+
+    fn set_channels(&mut self, channels: ChannelSettings) {
+        let mut dsp = DSP.lock().unwrap_or_else(PoisonError::into_inner);
+        dsp.channels = Some(channels).filter(|channels| !channels.is_neutral());
+        self.attach_dsp(&mut dsp);
+    }
+
+    fn decode(path: &Path, sink: &mut dyn SampleSink) -> Result<(), PlayError> {
+        let (path_str, c_path) = c_path(path)?;
+        // a music stream only opens the decoder, so long files don't end up in memory as a whole.
+        // Its audio buffer is never played.
+        let music = unsafe { raylib::ffi::LoadMusicStream(c_path.as_ptr()) };
+        let streamed = decode_music(&music, sink);
+        unsafe { raylib::ffi::UnloadMusicStream(music) };
+        if streamed {
+            Ok(())
+        } else {
+            decode_wave(path_str, &c_path, sink)
+        }
+    }
+}
+
+/// Reads the music stream chunk by chunk into `sink`. Returns false without reading anything if
+/// the decoder can't be read from here.
+fn decode_music(music: &raylib::ffi::Music, sink: &mut dyn SampleSink) -> bool {
+    let channels = music.stream.channels as usize;
+    let supported = matches!(
+        music.ctxType,
+        MUSIC_AUDIO_WAV | MUSIC_AUDIO_OGG | MUSIC_AUDIO_MP3 | MUSIC_AUDIO_QOA
+    );
+    if !supported || music.ctxData.is_null() || channels == 0 {
+        return false;
+    }
+
+    sink.format(music.stream.sampleRate, music.stream.channels);
+    let mut buffer = vec![0.0f32; DECODE_FRAMES * channels];
+    let out = buffer.as_mut_ptr();
+    loop {
+        let frames = unsafe {
+            match music.ctxType {
+                MUSIC_AUDIO_WAV => {
+                    drwav_read_pcm_frames_f32(music.ctxData, DECODE_FRAMES as u64, out) as usize
+                }
+                MUSIC_AUDIO_MP3 => {
+                    drmp3_read_pcm_frames_f32(music.ctxData, DECODE_FRAMES as u64, out) as usize
+                }
+                MUSIC_AUDIO_OGG => stb_vorbis_get_samples_float_interleaved(
+                    music.ctxData,
+                    channels as c_int,
+                    out,
+                    buffer.len() as c_int,
+                )
+                .max(0) as usize,
+                _ => qoaplay_decode(music.ctxData, out, DECODE_FRAMES as c_int) as usize,
+            }
+        };
+        if frames == 0 {
+            return true;
+        }
+        sink.samples(&buffer[..frames.min(DECODE_FRAMES) * channels]);
+    }
+}
+
+/// Decodes the whole file at once, for the formats `decode_music` can't read.
+fn decode_wave(path_str: &str, c_path: &CStr, sink: &mut dyn SampleSink) -> Result<(), PlayError> {
+    // LoadWave only decodes, it doesn't need the audio device
+    let wave = unsafe { raylib::ffi::LoadWave(c_path.as_ptr()) };
+    if wave.data.is_null() || wave.frameCount == 0 || wave.channels == 0 {
+        unsafe { raylib::ffi::UnloadWave(wave) };
+        return Err(PlayError::IoError(format!(
+            "audio could not be decoded from file {path_str}"
+        )));
+    }
+
+    sink.format(wave.sampleRate, wave.channels);
+    let len = wave.frameCount as usize * wave.channels as usize;
+    match wave.sampleSize {
+        32 => {
+            let samples = unsafe { std::slice::from_raw_parts(wave.data as *const f32, len) };
+            sink.samples(samples);
+        }
+        16 => {
+            let data = unsafe { std::slice::from_raw_parts(wave.data as *const i16, len) };
+            for chunk in data.chunks(4096) {
+                let samples: Vec<f32> = chunk.iter().map(|s| *s as f32 / 32768.0).collect();
+                sink.samples(&samples);
+            }
+        }
+        _ => {
+            let data = unsafe { std::slice::from_raw_parts(wave.data as *const u8, len) };
+            for chunk in data.chunks(4096) {
+                let samples: Vec<f32> =
+                    chunk.iter().map(|s| (*s as f32 - 128.0) / 128.0).collect();
+                sink.samples(&samples);
+            }
+        }
+    }
+    unsafe { raylib::ffi::UnloadWave(wave) };
+    Ok(())
+}
+
+/// Effects on the mixed output of the device. raylib mixes at the native rate of the device
+/// without telling which one that is, so the rate is estimated from the number of frames processed
+/// over time. Until then it's assumed to be 48kHz.
+struct DeviceDsp {
+    sample_rate: u32,
+    counting_since: Option<Instant>,
+    frames: u64,
+    /// undoes the pitch change of `SetMusicPitch`, before the equalizer so the bands stay where
+    /// they belong
+    pitch_corrector: Option<PitchCorrector>,
+    equalizer: Option<Equalizer>,
+    /// `None` while it wouldn't change anything
+    channels: Option<ChannelSettings>,
+}
+
+impl DeviceDsp {
+    const fn new() -> Self {
+        Self {
+            sample_rate: 48000,
+            counting_since: None,
+            frames: 0,
+            pitch_corrector: None,
+            equalizer: None,
+            channels: None,
+        }
+    }
+
+    fn count_frames(&mut self, frames: u32) {
+        let Some(since) = self.counting_since else {
+            self.counting_since = Some(Instant::now());
+            self.frames = 0;
+            return;
+        };
+        self.frames += frames as u64;
+        let elapsed = since.elapsed().as_secs_f64();
+        if elapsed >= 2.0 {
+            let rate = self.frames as f64 / elapsed;
+            let nearest = SAMPLE_RATES
+                .into_iter()
+                .min_by_key(|known| (*known as f64 - rate).abs() as u64)
+                .unwrap_or(48000);
+            self.sample_rate = nearest;
+            if let Some(equalizer) = &mut self.equalizer {
+                equalizer.set_sample_rate(nearest);
+            }
+            if let Some(corrector) = &mut self.pitch_corrector {
+                if corrector.sample_rate() != nearest {
+                    *corrector = PitchCorrector::new(corrector.speed(), nearest, DEVICE_CHANNELS);
+                }
+            }
+            self.counting_since = Some(Instant::now());
+            self.frames = 0;
+        }
+    }
+}
+
+/// Runs on the audio thread for every buffer the device plays.
+unsafe extern "C" fn process_dsp(buffer: *mut c_void, frames: c_uint) {
+    let mut dsp = DSP.lock().unwrap_or_else(PoisonError::into_inner);
+    let samples =
+        std::slice::from_raw_parts_mut(buffer as *mut f32, frames as usize * DEVICE_CHANNELS);
+    dsp.count_frames(frames);
+    if let Some(corrector) = &mut dsp.pitch_corrector {
+        corrector.process(samples);
+    }
+    if let Some(equalizer) = &mut dsp.equalizer {
+        equalizer.process(samples, DEVICE_CHANNELS);
+    }
+    if let Some(channels) = &dsp.channels {
+        channels.process(samples);
+    }
+}
+
+fn c_path(path: &Path) -> Result<(&str, CString), PlayError> {
+    let path_str = path.to_str().ok_or(PlayError::FileNameInvalid)?;
+    let c_path = CString::new(path_str).map_err(|_| PlayError::FileNameInvalid)?;
+    Ok((path_str, c_path))
+}
@@ -0,0 +1,127 @@
+//! Stereo adjustments for listening with one earbud or fixing badly mastered recordings: balance,
+//! mono downmix, swapping the channels and inverting their polarity.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChannelSettings {
+    /// -1 is only the left channel, 1 only the right one
+    pub balance: f32,
+    /// both channels get the mix of both, after swapping and inverting
+    pub mono: bool,
+    pub swap: bool,
+    pub invert_left: bool,
+    pub invert_right: bool,
+}
+
+impl ChannelSettings {
+    /// Whether processing leaves the samples the way they are.
+    pub fn is_neutral(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Processes interleaved stereo samples.
+    pub fn process(&self, samples: &mut [f32]) {
+        let balance = self.balance.clamp(-1.0, 1.0);
+        // the louder side stays at full volume
+        let left_gain = (1.0 - balance).min(1.0);
+        let right_gain = (1.0 + balance).min(1.0);
+        let left_sign = if self.invert_left { -1.0 } else { 1.0 };
+        let right_sign = if self.invert_right { -1.0 } else { 1.0 };
+
+        for frame in samples.chunks_exact_mut(2) {
+            let (mut left, mut right) = (frame[0], frame[1]);
+            if self.swap {
+                (left, right) = (right, left);
+            }
+            left *= left_sign;
+            right *= right_sign;
+            if self.mono {
+                let mid = (left + right) * 0.5;
+                (left, right) = (mid, mid);
+            }
+            frame[0] = left * left_gain;
+            frame[1] = right * right_gain;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Processes two frames, `[left, right]` and the same negated.
+    fn process(settings: ChannelSettings, left: f32, right: f32) -> [f32; 2] {
+        let mut samples = [left, right, -left, -right];
+        settings.process(&mut samples);
+        assert_eq!(samples[2..], [-samples[0], -samples[1]]);
+        [samples[0], samples[1]]
+    }
+
+    #[test]
+    fn neutral_settings_do_nothing() {
+        assert!(ChannelSettings::default().is_neutral());
+        assert_eq!(process(ChannelSettings::default(), 0.8, 0.2), [0.8, 0.2]);
+    }
+
+    #[test]
+    fn balance_turns_down_the_other_side() {
+        let left = ChannelSettings {
+            balance: -0.5,
+            ..Default::default()
+        };
+        assert_eq!(process(left, 0.8, 0.2), [0.8, 0.1]);
+        let right = ChannelSettings {
+            balance: 2.0,
+            ..Default::default()
+        };
+        assert_eq!(process(right, 0.8, 0.2), [0.0, 0.2]);
+    }
+
+    #[test]
+    fn mono_mixes_both_channels() {
+        let settings = ChannelSettings {
+            mono: true,
+            ..Default::default()
+        };
+        assert_eq!(process(settings, 0.8, 0.2), [0.5, 0.5]);
+    }
+
+    #[test]
+    fn swap_exchanges_the_channels() {
+        let settings = ChannelSettings {
+            swap: true,
+            ..Default::default()
+        };
+        assert_eq!(process(settings, 0.8, 0.2), [0.2, 0.8]);
+    }
+
+    #[test]
+    fn invert_flips_one_channel() {
+        let left = ChannelSettings {
+            invert_left: true,
+            ..Default::default()
+        };
+        assert_eq!(process(left, 0.8, 0.2), [-0.8, 0.2]);
+        let right = ChannelSettings {
+            invert_right: true,
+            ..Default::default()
+        };
+        assert_eq!(process(right, 0.8, 0.2), [0.8, -0.2]);
+    }
+
+    #[test]
+    fn mono_mixes_after_swapping_and_inverting() {
+        let settings = ChannelSettings {
+            mono: true,
+            swap: true,
+            ..Default::default()
+        };
+        assert_eq!(process(settings, 0.8, 0.2), [0.5, 0.5]);
+        // the inverted channel is the one that ends up on the left
+        let settings = ChannelSettings {
+            invert_left: true,
+            ..settings
+        };
+        let [left, right] = process(settings, 0.8, 0.2);
+        assert!((left - 0.3).abs() < 1e-6 && left == right, "{left} {right}");
+    }
+}
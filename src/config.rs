@@ -0,0 +1,554 @@
+use std::{
+    fmt::Display,
+    fs, io,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+};
+
+use mp3_player::{
+    replaygain::{ReplayGainMode, ReplayGainOptions},
+    song::{LibraryOptions, ARBITRARY_DIRS, SUPPORTED_FORMATS},
+};
+
+use crate::dirs;
+
+/// Settings from `$XDG_CONFIG_HOME/mp3-player/config.toml`.
+///
+/// Only the subset of TOML needed for this is understood: `[sections]`, `key = value` pairs and
+/// strings, numbers, booleans as well as single-line arrays of those as values.
+/// ```toml
+/// music_dir = "~/Music"
+///
+/// [window]
+/// width = 350
+/// height = 500
+/// undecorated = true
+/// fps = 60
+///
+/// [controls]
+/// seek_step = 5.0
+/// volume_step = 0.05
+///
+/// [playback]
+/// crossfade = 0.0
+/// replaygain = "off" # or "track", "album"
+/// replaygain_preamp = 0.0
+/// replaygain_prevent_clipping = true
+/// replaygain_scan = false
+/// replaygain_scan_write_tags = false
+/// resume_min_length = 20.0
+/// smart_shuffle_favorites = false
+/// skip_silence = false
+/// silence_threshold = -50.0
+///
+/// [library]
+/// supported_formats = ["mp3", "ogg", "wav", "qoa", "flac", "xm", "mod"]
+/// arbitrary_dirs = ["_", "unordered", "any", "unknown", "random"]
+///
+/// [mpd]
+/// enabled = false
+/// address = "127.0.0.1"
+/// port = 6600
+/// ```
+pub struct Config {
+    pub music_dir: Option<PathBuf>,
+    pub window_width: i32,
+    pub window_height: i32,
+    pub window_undecorated: bool,
+    pub fps: u32,
+    /// seconds to seek with the arrow keys
+    pub seek_step: f32,
+    /// volume change with the arrow keys, volume goes from 0 to 1
+    pub volume_step: f32,
+    /// seconds songs overlap when switching between them, 0 turns it off
+    pub crossfade: f32,
+    pub replay_gain: ReplayGainOptions,
+    /// measure the loudness of songs without ReplayGain tags in the background
+    pub replay_gain_scan: bool,
+    /// write the measured loudness into the files as ReplayGain tags
+    pub replay_gain_scan_write_tags: bool,
+    /// minutes a file has to be long to continue where it stopped, 0 turns it off
+    pub resume_min_length: f32,
+    /// smart shuffle plays often played songs earlier
+    pub smart_shuffle_favorites: bool,
+    /// skip silence at the start and end of songs
+    pub skip_silence: bool,
+    /// dB relative to full scale, anything quieter counts as silence
+    pub silence_threshold: f32,
+    pub library: LibraryOptions,
+    /// whether to run the MPD protocol server
+    pub mpd_enabled: bool,
+    /// 127.0.0.1 only accepts connections from this machine, 0.0.0.0 from anywhere
+    pub mpd_address: IpAddr,
+    pub mpd_port: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            music_dir: None,
+            window_width: 350,
+            window_height: 500,
+            window_undecorated: true,
+            fps: 60,
+            seek_step: 5.0,
+            volume_step: 0.05,
+            crossfade: 0.0,
+            replay_gain: ReplayGainOptions::default(),
+            replay_gain_scan: false,
+            replay_gain_scan_write_tags: false,
+            resume_min_length: 20.0,
+            smart_shuffle_favorites: false,
+            skip_silence: false,
+            silence_threshold: -50.0,
+            library: LibraryOptions::default(),
+            mpd_enabled: false,
+            mpd_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            mpd_port: 6600,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.path.display(), self.message)
+        } else {
+            write!(f, "{}:{}: {}", self.path.display(), self.line, self.message)
+        }
+    }
+}
+
+enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "a string",
+            Self::Integer(_) => "an integer",
+            Self::Float(_) => "a number",
+            Self::Boolean(_) => "a boolean",
+            Self::Array(_) => "an array",
+        }
+    }
+}
+
+/// Parses a single value at the start of `str`, returns the value and the rest of the string.
+fn parse_value(str: &str) -> Result<(Value, &str), String> {
+    let str = str.trim_start();
+
+    if let Some(rest) = str.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::String(value), &rest[i + 1..])),
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, '"')) => value.push('"'),
+                    Some((_, '\\')) => value.push('\\'),
+                    Some((_, c)) => return Err(format!("unknown escape sequence '\\{c}'")),
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        return Err("unterminated string".to_string());
+    }
+
+    if let Some(mut rest) = str.strip_prefix('[') {
+        let mut values = vec![];
+        loop {
+            rest = rest.trim_start();
+            if let Some(rest) = rest.strip_prefix(']') {
+                return Ok((Value::Array(values), rest));
+            }
+            let (value, new_rest) = parse_value(rest)?;
+            values.push(value);
+            rest = new_rest.trim_start();
+            if let Some(new_rest) = rest.strip_prefix(',') {
+                rest = new_rest;
+            } else if !rest.starts_with(']') {
+                return Err("expected ',' or ']' in array".to_string());
+            }
+        }
+    }
+
+    let end = str
+        .find(|c: char| c == ',' || c == ']' || c == '#' || c.is_whitespace())
+        .unwrap_or(str.len());
+    let (word, rest) = str.split_at(end);
+    let value = match word {
+        "" => return Err("expected a value".to_string()),
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        _ => {
+            let number = word.replace('_', "");
+            if let Ok(v) = number.parse::<i64>() {
+                Value::Integer(v)
+            } else if let Ok(v) = number.parse::<f64>() {
+                Value::Float(v)
+            } else {
+                return Err(format!(
+                    "invalid value '{word}' (strings have to be in double quotes)"
+                ));
+            }
+        }
+    };
+    Ok((value, rest))
+}
+
+fn expect_string(value: Value) -> Result<String, String> {
+    match value {
+        Value::String(v) => Ok(v),
+        v => Err(format!("expected a string, found {}", v.type_name())),
+    }
+}
+
+fn expect_bool(value: Value) -> Result<bool, String> {
+    match value {
+        Value::Boolean(v) => Ok(v),
+        v => Err(format!("expected true or false, found {}", v.type_name())),
+    }
+}
+
+fn expect_integer(value: Value, min: i64, max: i64) -> Result<i64, String> {
+    match value {
+        Value::Integer(v) if v >= min && v <= max => Ok(v),
+        Value::Integer(v) => Err(format!("{v} is out of range, expected {min} to {max}")),
+        v => Err(format!("expected an integer, found {}", v.type_name())),
+    }
+}
+
+fn expect_float(value: Value, min: f64, max: f64) -> Result<f32, String> {
+    let v = match value {
+        Value::Integer(v) => v as f64,
+        Value::Float(v) => v,
+        v => return Err(format!("expected a number, found {}", v.type_name())),
+    };
+    if v < min || v > max {
+        return Err(format!("{v} is out of range, expected {min} to {max}"));
+    }
+    Ok(v as f32)
+}
+
+fn expect_string_list(value: Value) -> Result<Vec<String>, String> {
+    match value {
+        Value::Array(values) => values.into_iter().map(expect_string).collect(),
+        v => Err(format!("expected an array of strings, found {}", v.type_name())),
+    }
+}
+
+fn expand_home(path: String) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(relative), Some(home)) => home.join(relative),
+        _ => PathBuf::from(path),
+    }
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/mp3-player/config.toml`
+    pub fn path() -> Option<PathBuf> {
+        Some(dirs::config_home()?.join("mp3-player").join("config.toml"))
+    }
+
+    /// Loads the config at `path`. A missing file is not an error, the defaults are used instead.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(str) => Self::parse(&str, path),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(ConfigError {
+                path: path.to_path_buf(),
+                line: 0,
+                message: err.to_string(),
+            }),
+        }
+    }
+
+    fn parse(str: &str, path: &Path) -> Result<Self, ConfigError> {
+        let mut me = Self::default();
+        let mut section = String::new();
+
+        for (i, line) in str.lines().enumerate() {
+            let error = |message: String| ConfigError {
+                path: path.to_path_buf(),
+                line: i + 1,
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[') {
+                let Some((name, rest)) = name.split_once(']') else {
+                    return Err(error("expected ']' after the section name".to_string()));
+                };
+                let rest = rest.trim_start();
+                if !rest.is_empty() && !rest.starts_with('#') {
+                    return Err(error(format!("unexpected '{rest}' after the section")));
+                }
+                section = name.trim().to_string();
+                if !matches!(
+                    section.as_str(),
+                    "window" | "controls" | "playback" | "library" | "mpd"
+                ) {
+                    return Err(error(format!(
+                        "unknown section [{section}], expected [window], [controls], [playback], \
+                         [library] or [mpd]"
+                    )));
+                }
+                continue;
+            }
+
+            let Some((key, rest)) = line.split_once('=') else {
+                return Err(error(format!("expected 'key = value', found '{line}'")));
+            };
+            let key = key.trim();
+            let (value, rest) = parse_value(rest).map_err(|err| error(format!("{key}: {err}")))?;
+            let rest = rest.trim_start();
+            if !rest.is_empty() && !rest.starts_with('#') {
+                return Err(error(format!("unexpected '{rest}' after the value of {key}")));
+            }
+
+            me.set(&section, key, value)
+                .map_err(|err| error(format!("{key}: {err}")))?;
+        }
+
+        Ok(me)
+    }
+
+    fn set(&mut self, section: &str, key: &str, value: Value) -> Result<(), String> {
+        match (section, key) {
+            ("", "music_dir") => self.music_dir = Some(expand_home(expect_string(value)?)),
+            ("window", "width") => self.window_width = expect_integer(value, 100, 16384)? as i32,
+            ("window", "height") => self.window_height = expect_integer(value, 100, 16384)? as i32,
+            ("window", "undecorated") => self.window_undecorated = expect_bool(value)?,
+            ("window", "fps") => self.fps = expect_integer(value, 1, 1000)? as u32,
+            ("controls", "seek_step") => self.seek_step = expect_float(value, 0.1, 3600.0)?,
+            ("controls", "volume_step") => self.volume_step = expect_float(value, 0.001, 1.0)?,
+            ("playback", "crossfade") => self.crossfade = expect_float(value, 0.0, 12.0)?,
+            ("playback", "replaygain") => {
+                let mode = expect_string(value)?;
+                self.replay_gain.mode = ReplayGainMode::from_name(&mode)
+                    .ok_or_else(|| format!("invalid mode '{mode}', expected off, track or album"))?;
+            }
+            ("playback", "replaygain_preamp") => {
+                self.replay_gain.preamp = expect_float(value, -15.0, 15.0)?
+            }
+            ("playback", "replaygain_prevent_clipping") => {
+                self.replay_gain.prevent_clipping = expect_bool(value)?
+            }
+            ("playback", "replaygain_scan") => self.replay_gain_scan = expect_bool(value)?,
+            ("playback", "replaygain_scan_write_tags") => {
+                self.replay_gain_scan_write_tags = expect_bool(value)?
+            }
+            ("playback", "resume_min_length") => {
+                self.resume_min_length = expect_float(value, 0.0, 1440.0)?
+            }
+            ("playback", "smart_shuffle_favorites") => {
+                self.smart_shuffle_favorites = expect_bool(value)?
+            }
+            ("playback", "skip_silence") => self.skip_silence = expect_bool(value)?,
+            ("playback", "silence_threshold") => {
+                self.silence_threshold = expect_float(value, -90.0, -10.0)?
+            }
+            ("library", "supported_formats") => {
+                let formats = expect_string_list(value)?;
+                if formats.is_empty() {
+                    return Err("at least one format is needed".to_string());
+                }
+                self.library.supported_formats = formats
+                    .into_iter()
+                    .map(|format| format.trim_start_matches('.').to_ascii_lowercase())
+                    .collect();
+            }
+            ("library", "arbitrary_dirs") => {
+                self.library.arbitrary_dirs = expect_string_list(value)?
+            }
+            ("mpd", "enabled") => self.mpd_enabled = expect_bool(value)?,
+            ("mpd", "address") => {
+                let address = expect_string(value)?;
+                self.mpd_address = address
+                    .parse()
+                    .map_err(|_| format!("'{address}' is not an IP address"))?;
+            }
+            ("mpd", "port") => self.mpd_port = expect_integer(value, 1, u16::MAX as i64)? as u16,
+            ("", _) => return Err("unknown setting".to_string()),
+            (section, _) => return Err(format!("unknown setting in [{section}]")),
+        }
+        Ok(())
+    }
+}
+
+/// The config as it would look with all default values, used for `--print-default-config`.
+pub fn default_config_str() -> String {
+    let quote_list = |list: &[&str]| {
+        list.iter()
+            .map(|v| format!("\"{v}\""))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let default = Config::default();
+
+    format!(
+        "# music_dir = \"~/Music\"\n\
+         \n\
+         [window]\n\
+         width = {}\n\
+         height = {}\n\
+         undecorated = {}\n\
+         fps = {}\n\
+         \n\
+         [controls]\n\
+         seek_step = {:?}\n\
+         volume_step = {:?}\n\
+         \n\
+         [playback]\n\
+         crossfade = {:?}\n\
+         # \"track\" or \"album\" evens out the loudness of songs with ReplayGain tags\n\
+         replaygain = \"{}\"\n\
+         replaygain_preamp = {:?}\n\
+         replaygain_prevent_clipping = {}\n\
+         # measure the loudness of songs without tags, optionally writing the tags (mp3 and flac)\n\
+         replaygain_scan = {}\n\
+         replaygain_scan_write_tags = {}\n\
+         # files at least this many minutes long continue where they stopped, 0 turns it off\n\
+         resume_min_length = {:?}\n\
+         # smart shuffle plays often played songs earlier\n\
+         smart_shuffle_favorites = {}\n\
+         # skip silence quieter than the threshold (in dB) at the start and end of songs\n\
+         skip_silence = {}\n\
+         silence_threshold = {:?}\n\
+         \n\
+         [library]\n\
+         supported_formats = [{}]\n\
+         arbitrary_dirs = [{}]\n\
+         \n\
+         [mpd]\n\
+         enabled = {}\n\
+         # \"0.0.0.0\" lets other machines connect, there is no password\n\
+         address = \"{}\"\n\
+         port = {}\n",
+        default.window_width,
+        default.window_height,
+        default.window_undecorated,
+        default.fps,
+        default.seek_step,
+        default.volume_step,
+        default.crossfade,
+        default.replay_gain.mode.name(),
+        default.replay_gain.preamp,
+        default.replay_gain.prevent_clipping,
+        default.replay_gain_scan,
+        default.replay_gain_scan_write_tags,
+        default.resume_min_length,
+        default.smart_shuffle_favorites,
+        default.skip_silence,
+        default.silence_threshold,
+        quote_list(SUPPORTED_FORMATS),
+        quote_list(ARBITRARY_DIRS),
+        default.mpd_enabled,
+        default.mpd_address,
+        default.mpd_port,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(str: &str) -> Result<Config, String> {
+        Config::parse(str, Path::new("config.toml")).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn the_default_config_parses_to_the_defaults() {
+        let config = parse(&default_config_str()).unwrap();
+        let default = Config::default();
+        assert_eq!(config.window_width, default.window_width);
+        assert_eq!(config.crossfade, default.crossfade);
+        assert_eq!(
+            config.library.supported_formats,
+            default.library.supported_formats
+        );
+        assert_eq!(config.mpd_address, default.mpd_address);
+    }
+
+    #[test]
+    fn skips_comments() {
+        let config = parse(
+            "# a comment\n\
+             \n\
+             [window] # the window\n\
+             \x20  # indented\n\
+             width = 400 # pixels\n\
+             fps = 1_000\n",
+        )
+        .unwrap();
+        assert_eq!(config.window_width, 400);
+        assert_eq!(config.fps, 1000);
+    }
+
+    #[test]
+    fn parses_strings_and_arrays() {
+        let config = parse(
+            "music_dir = \"/music/\\\"quoted\\\" # not a comment\"\n\
+             [library]\n\
+             supported_formats = [ \".MP3\",\"flac\" , ]\n\
+             arbitrary_dirs = []\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.music_dir,
+            Some(PathBuf::from("/music/\"quoted\" # not a comment"))
+        );
+        assert_eq!(config.library.supported_formats, ["mp3", "flac"]);
+        assert!(config.library.arbitrary_dirs.is_empty());
+
+        assert!(parse("music_dir = \"unterminated\n").is_err());
+        assert!(parse("music_dir = unquoted\n").is_err());
+        assert!(parse("[library]\nsupported_formats = [\"mp3\" \"ogg\"]\n").is_err());
+        assert!(parse("[library]\nsupported_formats = [1, 2]\n").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert_eq!(
+            parse("\n[window]\nwidht = 400\n").err().as_deref(),
+            Some("config.toml:3: widht: unknown setting in [window]")
+        );
+        assert!(parse("volume = 1\n").is_err());
+        assert!(parse("[sound]\n").is_err());
+        // a key of another section
+        assert!(parse("[mpd]\nwidth = 400\n").is_err());
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        assert_eq!(
+            parse("[window]\nfps = 0\n").err().as_deref(),
+            Some("config.toml:2: fps: 0 is out of range, expected 1 to 1000")
+        );
+        assert!(parse("[playback]\ncrossfade = 12.5\n").is_err());
+        assert!(parse("[playback]\ncrossfade = 12\n").is_ok());
+        assert!(parse("[mpd]\nport = 65536\n").is_err());
+        assert!(parse("[window]\nwidth = 400.5\n").is_err());
+        assert!(parse("[playback]\nreplaygain = \"loud\"\n").is_err());
+        assert!(parse("[mpd]\naddress = \"localhost\"\n").is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+pub fn home_dir() -> Option<PathBuf> {
+    let path = if cfg!(target_os = "windows") {
+        match env::var_os("USERPROFILE") {
+            Some(path) => PathBuf::from(path),
+            None => {
+                // %HomeDrive%%HomePath% aka $HomeDrive$HomePath
+                let mut path = PathBuf::from(env::var_os("HomeDrive")?);
+                path.push(env::var_os("HomePath")?);
+                path
+            }
+        }
+    } else {
+        // $HOME
+        PathBuf::from(env::var_os("HOME")?)
+    };
+
+    if path.as_os_str().is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// `$XDG_CONFIG_HOME`, or `~/.config` if it isn't set.
+pub fn config_home() -> Option<PathBuf> {
+    xdg_dir_from_env("XDG_CONFIG_HOME").or_else(|| Some(home_dir()?.join(".config")))
+}
+
+/// `$XDG_STATE_HOME`, or `~/.local/state` if it isn't set.
+pub fn state_home() -> Option<PathBuf> {
+    xdg_dir_from_env("XDG_STATE_HOME").or_else(|| Some(home_dir()?.join(".local").join("state")))
+}
+
+/// `$XDG_CACHE_HOME`, or `~/.cache` if it isn't set.
+pub fn cache_home() -> Option<PathBuf> {
+    xdg_dir_from_env("XDG_CACHE_HOME").or_else(|| Some(home_dir()?.join(".cache")))
+}
+
+/// `$XDG_RUNTIME_DIR`. There's no sensible default for it, the caller has to pick its own fallback.
+pub fn runtime_dir() -> Option<PathBuf> {
+    xdg_dir_from_env("XDG_RUNTIME_DIR")
+}
+
+fn xdg_dir_from_env(name: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(env::var_os(name)?);
+    // the spec says relative paths are invalid and should be ignored
+    if path.is_absolute() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Parses the contents of `user-dirs.dirs`, which consists of lines like
+/// `XDG_MUSIC_DIR="$HOME/Music"`.
+fn parse_user_dirs(contents: &str, home: &Path) -> HashMap<String, PathBuf> {
+    let mut dirs = HashMap::new();
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        let Some((name, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+
+        let path = if value == "$HOME" {
+            home.to_path_buf()
+        } else if let Some(relative) = value.strip_prefix("$HOME/") {
+            home.join(relative)
+        } else if value.starts_with('/') {
+            PathBuf::from(value)
+        } else {
+            continue;
+        };
+        dirs.insert(name.trim().to_string(), path);
+    }
+
+    dirs
+}
+
+fn is_readable_dir(path: &Path) -> bool {
+    fs::read_dir(path).is_ok()
+}
+
+/// The old way of finding the music directory: a folder called "music" (in any casing) in the home
+/// directory.
+fn find_music_folder_in(home: &Path) -> Option<PathBuf> {
+    fs::read_dir(home)
+        .ok()?
+        .flatten()
+        .find(|entry| entry.file_name().eq_ignore_ascii_case("music"))
+        .map(|entry| home.join(entry.file_name()))
+}
+
+/// Figures out where the music lives. In order, this tries
+/// - the configured `music_override`
+/// - `$XDG_MUSIC_DIR`
+/// - `XDG_MUSIC_DIR` in `$XDG_CONFIG_HOME/user-dirs.dirs`
+/// - a folder called "music" in the home directory
+///
+/// and falls back to the home directory (or the current directory, if there isn't one). When
+/// falling back, the second value is a notice that should be shown to the user.
+pub fn music_dir(music_override: Option<&Path>) -> (PathBuf, Option<String>) {
+    let mut notice = None;
+
+    if let Some(path) = music_override {
+        if is_readable_dir(path) {
+            return (path.to_path_buf(), None);
+        }
+        notice = Some(format!("Cannot read the music folder {}", path.display()));
+    }
+
+    if let Some(path) = xdg_dir_from_env("XDG_MUSIC_DIR") {
+        if is_readable_dir(&path) {
+            return (path, notice);
+        }
+    }
+
+    let Some(home) = home_dir() else {
+        return (
+            PathBuf::from("."),
+            Some("Failed to get the home directory, using the current directory".to_string()),
+        );
+    };
+
+    if let Some(config_home) = config_home() {
+        if let Ok(contents) = fs::read_to_string(config_home.join("user-dirs.dirs")) {
+            if let Some(path) = parse_user_dirs(&contents, &home).remove("XDG_MUSIC_DIR") {
+                // XDG_MUSIC_DIR="$HOME" is what xdg-user-dirs writes when the folder is disabled
+                if path != home && is_readable_dir(&path) {
+                    return (path, notice);
+                }
+            }
+        }
+    }
+
+    if let Some(path) = find_music_folder_in(&home) {
+        if is_readable_dir(&path) {
+            return (path, notice);
+        }
+    }
+
+    let notice = notice.unwrap_or_else(|| "No music folder found, using the home directory".into());
+    (home, Some(notice))
+}
@@ -0,0 +1,440 @@
+//! A 10-band graphic equalizer: the filters, built-in and user presets and the settings that decide
+//! which bands a song is played with.
+
+use std::{
+    collections::HashMap,
+    f64::consts::PI,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::files::write_atomic;
+
+pub const BANDS: usize = 10;
+/// Center frequencies in Hz, an octave apart.
+pub const FREQUENCIES: [f32; BANDS] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+/// Band gains and the preamp go from -MAX_GAIN to MAX_GAIN dB.
+pub const MAX_GAIN: f32 = 12.0;
+/// About one octave wide, so neighbouring bands overlap a little.
+const Q: f64 = 1.41;
+
+pub const BUILTIN_PRESETS: [(&str, [f32; BANDS]); 8] = [
+    ("Flat", [0.0; BANDS]),
+    ("Bass boost", [6.0, 5.0, 4.0, 2.0, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0]),
+    ("Treble boost", [0.0, 0.0, 0.0, 0.0, 0.0, 0.5, 2.0, 4.0, 5.0, 6.0]),
+    ("Vocal", [-2.0, -2.0, -1.0, 1.0, 3.0, 4.0, 3.0, 1.0, 0.0, -1.0]),
+    ("Rock", [4.0, 3.0, 1.5, -0.5, -1.0, 0.0, 1.5, 3.0, 3.5, 4.0]),
+    ("Pop", [-1.0, 0.0, 1.5, 3.0, 3.5, 2.5, 1.0, 0.0, -0.5, -1.0]),
+    ("Classical", [3.0, 2.0, 1.0, 0.0, 0.0, 0.0, -1.0, -1.0, 1.0, 2.0]),
+    ("Loudness", [5.0, 3.5, 1.0, 0.0, -1.0, -1.0, 0.0, 1.0, 3.5, 5.0]),
+];
+
+/// What the filters are set to, all in dB.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EqualizerBands {
+    pub preamp: f32,
+    pub gains: [f32; BANDS],
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preset {
+    pub name: String,
+    pub gains: [f32; BANDS],
+}
+
+/// The equalizer as the user set it up. `gains` apply to the whole playlist, unless the song has a
+/// preset of its own in `song_presets`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EqualizerSettings {
+    pub enabled: bool,
+    pub preamp: f32,
+    pub gains: [f32; BANDS],
+    /// the preset `gains` were taken from, `None` once they are changed by hand
+    pub preset: Option<String>,
+    pub user_presets: Vec<Preset>,
+    /// preset names by song
+    pub song_presets: HashMap<PathBuf, String>,
+}
+
+impl Default for EqualizerSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            preamp: 0.0,
+            gains: [0.0; BANDS],
+            preset: Some(BUILTIN_PRESETS[0].0.to_string()),
+            user_presets: vec![],
+            song_presets: HashMap::new(),
+        }
+    }
+}
+
+impl EqualizerSettings {
+    /// Built-in presets first, then the user presets.
+    pub fn presets(&self) -> impl Iterator<Item = (&str, &[f32; BANDS])> {
+        BUILTIN_PRESETS
+            .iter()
+            .map(|(name, gains)| (*name, gains))
+            .chain(self.user_presets.iter().map(|preset| (preset.name.as_str(), &preset.gains)))
+    }
+
+    /// User presets shadow built-in presets with the same name.
+    pub fn preset_gains(&self, name: &str) -> Option<[f32; BANDS]> {
+        self.user_presets
+            .iter()
+            .find(|preset| preset.name == name)
+            .map(|preset| preset.gains)
+            .or_else(|| {
+                BUILTIN_PRESETS
+                    .iter()
+                    .find(|(builtin, _)| *builtin == name)
+                    .map(|(_, gains)| *gains)
+            })
+    }
+
+    pub fn select_preset(&mut self, name: &str) {
+        if let Some(gains) = self.preset_gains(name) {
+            self.gains = gains;
+            self.preset = Some(name.to_string());
+        }
+    }
+
+    /// Stores the current bands as a user preset, replacing one with the same name.
+    pub fn save_preset(&mut self, name: String) {
+        self.user_presets.retain(|preset| preset.name != name);
+        self.user_presets.push(Preset {
+            name: name.clone(),
+            gains: self.gains,
+        });
+        self.preset = Some(name);
+    }
+
+    /// Removes a user preset, songs that used it go back to the playlist bands.
+    pub fn delete_preset(&mut self, name: &str) {
+        self.user_presets.retain(|preset| preset.name != name);
+        self.song_presets.retain(|_, preset| preset != name);
+        if self.preset_gains(name).is_none() && self.preset.as_deref() == Some(name) {
+            self.preset = None;
+        }
+    }
+
+    /// The bands a song plays with, `None` when the equalizer is off.
+    pub fn bands_for(&self, song: Option<&Path>) -> Option<EqualizerBands> {
+        if !self.enabled {
+            return None;
+        }
+        let gains = song
+            .and_then(|song| self.song_presets.get(song))
+            .and_then(|name| self.preset_gains(name))
+            .unwrap_or(self.gains);
+        Some(EqualizerBands {
+            preamp: self.preamp,
+            gains,
+        })
+    }
+
+    /// Reads settings written by `save`, unknown lines are skipped.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut settings = Self::default();
+        for line in fs::read_to_string(path)?.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match key {
+                "enabled" => settings.enabled = value == "true",
+                "preamp" => settings.preamp = parse_gain(value).unwrap_or(0.0),
+                "gains" => settings.gains = parse_gains(value).unwrap_or_default(),
+                "preset" => settings.preset = Some(value.to_string()),
+                "custom" => settings.preset = None,
+                "user_preset" => {
+                    if let Some((name, gains)) = value.split_once('\t') {
+                        if let Some(gains) = parse_gains(gains) {
+                            settings.user_presets.push(Preset {
+                                name: name.to_string(),
+                                gains,
+                            });
+                        }
+                    }
+                }
+                "song" => {
+                    if let Some((name, song)) = value.split_once('\t') {
+                        settings
+                            .song_presets
+                            .insert(PathBuf::from(song), name.to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Writes the settings as `key=value` lines, user presets and song presets use one line each.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let gains = |gains: &[f32; BANDS]| {
+            gains.iter().map(f32::to_string).collect::<Vec<_>>().join(" ")
+        };
+        let mut contents = format!(
+            "enabled={}\npreamp={}\ngains={}\n",
+            self.enabled,
+            self.preamp,
+            gains(&self.gains)
+        );
+        match &self.preset {
+            Some(preset) => contents.push_str(&format!("preset={preset}\n")),
+            None => contents.push_str("custom=\n"),
+        }
+        for preset in &self.user_presets {
+            contents.push_str(&format!("user_preset={}\t{}\n", preset.name, gains(&preset.gains)));
+        }
+        for (song, preset) in &self.song_presets {
+            let Some(song) = song.to_str().filter(|song| !song.contains('\n')) else {
+                continue;
+            };
+            contents.push_str(&format!("song={preset}\t{song}\n"));
+        }
+
+        write_atomic(path, contents)
+    }
+}
+
+fn parse_gain(value: &str) -> Option<f32> {
+    value
+        .parse::<f32>()
+        .ok()
+        .filter(|gain| gain.is_finite())
+        .map(|gain| gain.clamp(-MAX_GAIN, MAX_GAIN))
+}
+
+fn parse_gains(value: &str) -> Option<[f32; BANDS]> {
+    let mut gains = [0.0; BANDS];
+    let mut values = value.split_whitespace();
+    for gain in &mut gains {
+        *gain = parse_gain(values.next()?)?;
+    }
+    Some(gains)
+}
+
+/// Peaking filter after the Audio EQ Cookbook, `[b0, b1, b2, a1, a2]` normalized by a0.
+fn peaking(frequency: f64, gain: f64, sample_rate: f64) -> [f64; 5] {
+    let a = 10f64.powf(gain / 40.0);
+    let w0 = 2.0 * PI * frequency / sample_rate;
+    let alpha = w0.sin() / (2.0 * Q);
+    let a0 = 1.0 + alpha / a;
+    [
+        (1.0 + alpha * a) / a0,
+        -2.0 * w0.cos() / a0,
+        (1.0 - alpha * a) / a0,
+        -2.0 * w0.cos() / a0,
+        (1.0 - alpha / a) / a0,
+    ]
+}
+
+/// Filters interleaved samples with a set of `EqualizerBands`.
+pub struct Equalizer {
+    bands: EqualizerBands,
+    sample_rate: u32,
+    preamp: f32,
+    /// `None` for the bands that are flat
+    filters: [Option<[f64; 5]>; BANDS],
+    /// `[x1, x2, y1, y2]` per channel and band
+    state: Vec<[[f64; 4]; BANDS]>,
+}
+
+impl Equalizer {
+    pub fn new(bands: EqualizerBands, sample_rate: u32) -> Self {
+        let mut equalizer = Self {
+            bands,
+            sample_rate,
+            preamp: 1.0,
+            filters: [None; BANDS],
+            state: vec![],
+        };
+        equalizer.update_filters();
+        equalizer
+    }
+
+    pub fn bands(&self) -> EqualizerBands {
+        self.bands
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_bands(&mut self, bands: EqualizerBands) {
+        if bands != self.bands {
+            self.bands = bands;
+            self.update_filters();
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.update_filters();
+        }
+    }
+
+    fn update_filters(&mut self) {
+        let nyquist = self.sample_rate as f32 / 2.0;
+        self.preamp = 10f32.powf(self.bands.preamp / 20.0);
+        for (band, (frequency, gain)) in FREQUENCIES.iter().zip(self.bands.gains).enumerate() {
+            let filter = (gain != 0.0 && *frequency < nyquist)
+                .then(|| peaking(*frequency as f64, gain as f64, self.sample_rate as f64));
+            // the state of bands that keep filtering stays, resetting it while a slider is
+            // dragged would click
+            if self.filters[band].is_none() {
+                for state in &mut self.state {
+                    state[band] = [0.0; 4];
+                }
+            }
+            self.filters[band] = filter;
+        }
+    }
+
+    /// Filters `samples` in place. They have to start at a frame boundary.
+    pub fn process(&mut self, samples: &mut [f32], channels: usize) {
+        let flat = self.filters.iter().all(Option::is_none);
+        if channels == 0 || (flat && self.preamp == 1.0) {
+            return;
+        }
+        if self.state.len() != channels {
+            self.state = vec![[[0.0; 4]; BANDS]; channels];
+        }
+        for frame in samples.chunks_mut(channels) {
+            for (sample, state) in frame.iter_mut().zip(&mut self.state) {
+                let mut x = (*sample * self.preamp) as f64;
+                for (c, s) in self.filters.iter().zip(state.iter_mut()) {
+                    let Some(c) = c else {
+                        continue;
+                    };
+                    let y = c[0] * x + c[1] * s[0] + c[2] * s[1] - c[3] * s[2] - c[4] * s[3];
+                    *s = [x, s[0], y, s[2]];
+                    x = y;
+                }
+                *sample = x as f32;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    /// A stereo sine at `frequency` with an amplitude of 0.25.
+    fn sine(frequency: f64, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .flat_map(|frame| {
+                let sample =
+                    0.25 * (2.0 * PI * frequency * frame as f64 / SAMPLE_RATE as f64).sin();
+                [sample as f32, sample as f32]
+            })
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f64 {
+        let sum: f64 = samples.iter().map(|sample| (*sample as f64).powi(2)).sum();
+        (sum / samples.len() as f64).sqrt()
+    }
+
+    fn bands(gains: [f32; BANDS]) -> EqualizerBands {
+        EqualizerBands { preamp: 0.0, gains }
+    }
+
+    #[test]
+    fn flat_passes_the_signal_through() {
+        let settings = EqualizerSettings {
+            enabled: true,
+            ..Default::default()
+        };
+        let flat = settings.bands_for(None).unwrap();
+        let mut equalizer = Equalizer::new(flat, SAMPLE_RATE);
+        let input = sine(440.0, 4096);
+        let mut output = input.clone();
+        equalizer.process(&mut output, 2);
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn a_band_raises_its_center_frequency() {
+        let mut gains = [0.0; BANDS];
+        gains[5] = 6.0;
+        let mut equalizer = Equalizer::new(bands(gains), SAMPLE_RATE);
+        let input = sine(FREQUENCIES[5] as f64, SAMPLE_RATE as usize);
+        let mut output = input.clone();
+        equalizer.process(&mut output, 2);
+
+        // after the filter settled
+        let half = input.len() / 2;
+        let gain = 20.0 * (rms(&output[half..]) / rms(&input[half..])).log10();
+        assert!((gain - 6.0).abs() < 0.3, "{gain} dB");
+
+        // two octaves away it's barely touched
+        let input = sine(FREQUENCIES[3] as f64, SAMPLE_RATE as usize);
+        let mut output = input.clone();
+        equalizer.process(&mut output, 2);
+        let gain = 20.0 * (rms(&output[half..]) / rms(&input[half..])).log10();
+        assert!(gain.abs() < 1.0, "{gain} dB");
+    }
+
+    #[test]
+    fn changing_the_bands_keeps_the_state() {
+        let mut gains = [0.0; BANDS];
+        gains[5] = 6.0;
+        gains[8] = -3.0;
+        let input = sine(FREQUENCIES[5] as f64, SAMPLE_RATE as usize);
+        let mut expected = input.clone();
+        Equalizer::new(bands(gains), SAMPLE_RATE).process(&mut expected, 2);
+
+        // moving a slider and back in the middle continues exactly where the filters were
+        let mut equalizer = Equalizer::new(bands(gains), SAMPLE_RATE);
+        let mut output = input.clone();
+        let (before, after) = output.split_at_mut(SAMPLE_RATE as usize);
+        equalizer.process(before, 2);
+        let mut moved = gains;
+        moved[5] = 9.0;
+        equalizer.set_bands(bands(moved));
+        equalizer.set_bands(bands(gains));
+        equalizer.process(after, 2);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let mut settings = EqualizerSettings {
+            enabled: true,
+            preamp: -3.5,
+            ..Default::default()
+        };
+        settings.gains[0] = 4.5;
+        settings.save_preset("Mine".to_string());
+        settings.select_preset("Rock");
+        settings
+            .song_presets
+            .insert(PathBuf::from("/music/a song.mp3"), "Mine".to_string());
+        settings
+            .song_presets
+            .insert(PathBuf::from("/music/another.flac"), "Vocal".to_string());
+
+        let path =
+            std::env::temp_dir().join(format!("mp3-player-test-{}-equalizer", std::process::id()));
+        settings.save(&path).unwrap();
+        let loaded = EqualizerSettings::load(&path);
+        _ = fs::remove_file(&path);
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded, settings);
+        assert_eq!(
+            loaded
+                .bands_for(Some(Path::new("/music/a song.mp3")))
+                .unwrap()
+                .gains[0],
+            4.5
+        );
+    }
+}
@@ -1,11 +1,14 @@
 use std::{
     ffi::{CString, OsString},
     fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
+use mp3_player::{
+    audio_raylib::RaylibBackend,
+    song::{LibraryOptions, Playlist},
+};
 use raylib::{
-    audio::RaylibAudio,
     drawing::RaylibScissorModeExt,
     ffi::KeyboardKey,
     math::{Rectangle, Vector2},
@@ -15,7 +18,7 @@ use raylib::{
 
 use crate::{
     gui_main::{gui_highlight_end, gui_highlight_start, Action},
-    song::{Playlist, SUPPORTED_FORMATS},
+    notifications::Notifications,
     GuiScreen,
 };
 
@@ -35,15 +38,21 @@ pub struct FileGuiState {
     scroll_value: f32,
     cur_path: PathBuf,
     cur_dir_entries: Vec<DirEntry>,
+    library: LibraryOptions,
 }
 
 impl FileGuiState {
-    pub fn default(music_path: &PathBuf, gui_screen: GuiScreen) -> std::io::Result<Self> {
+    pub fn default(
+        music_path: &PathBuf,
+        gui_screen: GuiScreen,
+        library: &LibraryOptions,
+    ) -> std::io::Result<Self> {
         let mut me = Self {
             cur_path: music_path.clone(),
             scroll_value: 0.0,
             selected: 0,
             cur_dir_entries: vec![],
+            library: library.clone(),
         };
 
         me.refresh_folder_entries(gui_screen);
@@ -90,13 +99,7 @@ impl FileGuiState {
 
             match entry.path().extension() {
                 Some(ext) => {
-                    if file_type.is_file()
-                        && ext != "m3u"
-                        && SUPPORTED_FORMATS
-                            .iter()
-                            .find(|&&extension| ext == extension)
-                            .is_none()
-                    {
+                    if file_type.is_file() && ext != "m3u" && !self.library.is_supported(ext) {
                         continue;
                     }
                 }
@@ -143,13 +146,29 @@ fn gui_button_text_left(
     val_1 || d.gui_label_button(rect, text)
 }
 
+fn add_path_to_playlist(
+    playlist: &mut Playlist<RaylibBackend>,
+    path: &Path,
+    notifications: &mut Notifications,
+) {
+    let len = playlist.len();
+    match playlist.add_song_by_path(path) {
+        Err(err) => notifications.error(format!("Failed to add {}: {err}", path.display())),
+        Ok(()) if playlist.len() == len => {
+            notifications.info(format!("No songs found in {}", path.display()))
+        }
+        Ok(()) => {}
+    }
+}
+
 pub fn render_file_gui(
     rl: &mut RaylibHandle,
-    playlist: &mut Playlist,
+    playlist: &mut Playlist<RaylibBackend>,
     gui_state: &mut FileGuiState,
     gui_screen: GuiScreen,
     thread: &RaylibThread,
-    audio: &mut RaylibAudio,
+    audio: &mut RaylibBackend,
+    notifications: &mut Notifications,
 ) -> Action {
     let mut action = Action::None;
     let special_action = matches!(
@@ -328,9 +347,9 @@ pub fn render_file_gui(
                     GuiScreen::FileSelectAddFile => {
                         let mut path = gui_state.cur_path.clone();
                         path.push(&entry.raw);
-                        let _ = playlist.add_song_by_path(&path);
+                        add_path_to_playlist(playlist, &path, notifications);
                         if !playlist.is_music_playing(audio) {
-                            playlist.play_ignore_err(0, thread, audio, d.get_screen_height());
+                            playlist.play_ignore_err(0, audio, d.get_screen_height());
                         }
                         action = Action::SwitchGuiScreen(GuiScreen::Player);
                     }
@@ -338,9 +357,9 @@ pub fn render_file_gui(
                         let mut path = gui_state.cur_path.clone();
                         path.push(&entry.raw);
                         playlist.clear(audio);
-                        let _ = playlist.add_song_by_path(&path);
+                        add_path_to_playlist(playlist, &path, notifications);
                         if !playlist.is_music_playing(audio) {
-                            playlist.play_ignore_err(0, thread, audio, d.get_screen_height());
+                            playlist.play_ignore_err(0, audio, d.get_screen_height());
                         }
                         action = Action::SwitchGuiScreen(GuiScreen::Player);
                     }
@@ -357,16 +376,16 @@ pub fn render_file_gui(
                 }
                 GuiScreen::FileSelectOpenFolder => {
                     playlist.clear(audio);
-                    let _ = playlist.add_song_by_path(&gui_state.cur_path);
+                    add_path_to_playlist(playlist, &gui_state.cur_path, notifications);
                     if !playlist.is_music_playing(audio) {
-                        playlist.play_ignore_err(0, thread, audio, d.get_screen_height());
+                        playlist.play_ignore_err(0, audio, d.get_screen_height());
                     }
                     action = Action::SwitchGuiScreen(GuiScreen::Player);
                 }
                 GuiScreen::FileSelectAddFolder => {
-                    let _ = playlist.add_song_by_path(&gui_state.cur_path);
+                    add_path_to_playlist(playlist, &gui_state.cur_path, notifications);
                     if !playlist.is_music_playing(audio) {
-                        playlist.play_ignore_err(0, thread, audio, d.get_screen_height());
+                        playlist.play_ignore_err(0, audio, d.get_screen_height());
                     }
                     action = Action::SwitchGuiScreen(GuiScreen::Player);
                 }
@@ -0,0 +1,28 @@
+//! What the background scanners and the saved state have in common.
+
+use std::{fs, io, path::Path, time::UNIX_EPOCH};
+
+use crate::audio::{PlayError, SampleSink};
+
+/// Decodes the song at the path into the sink, usually `AudioBackend::decode`.
+pub type DecodeFn = fn(&Path, &mut dyn SampleSink) -> Result<(), PlayError>;
+
+/// The modification time in seconds, cached results are only used while it stays the same.
+pub fn modified(path: &Path) -> io::Result<u64> {
+    let modified = fs::metadata(path)?.modified()?;
+    Ok(modified
+        .duration_since(UNIX_EPOCH)
+        .map(|dur| dur.as_secs())
+        .unwrap_or_default())
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it over `path`, so a crash
+/// never leaves a half-written file behind. Missing parent folders are created.
+pub fn write_atomic<C: AsRef<[u8]>>(path: &Path, contents: C) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
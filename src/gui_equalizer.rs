@@ -0,0 +1,231 @@
+use std::ffi::CString;
+
+use raylib::{
+    ffi::KeyboardKey,
+    math::Rectangle,
+    rgui::RaylibDrawGui,
+    rstr,
+    RaylibHandle, RaylibThread,
+};
+
+use mp3_player::{
+    audio_raylib::RaylibBackend,
+    equalizer::{BANDS, FREQUENCIES, MAX_GAIN},
+    song::Playlist,
+};
+
+use crate::{
+    gui_main::{gui_highlight_end, gui_highlight_start, Action},
+    GuiScreen,
+};
+
+#[derive(Default)]
+pub struct EqualizerGuiState {
+    /// 0 is the preamp, then the bands
+    row: usize,
+}
+
+const MP3_PLAYER_NAME_EQUALIZER: &std::ffi::CStr = rstr!("#11#MP3 Player - Equalizer");
+const ROW_HEIGHT: f32 = 24.0;
+
+pub fn render_equalizer_gui(
+    playlist: &mut Playlist<RaylibBackend>,
+    audio: &mut RaylibBackend,
+    thread: &RaylibThread,
+    rl: &mut RaylibHandle,
+    state: &mut EqualizerGuiState,
+) -> Action {
+    let mut d = rl.begin_drawing(thread);
+
+    if d.gui_window_box(
+        Rectangle::new(
+            0.0,
+            0.0,
+            d.get_screen_width() as f32,
+            d.get_screen_height() as f32,
+        ),
+        Some(MP3_PLAYER_NAME_EQUALIZER),
+    ) || d.is_key_pressed(KeyboardKey::KEY_ESCAPE)
+    {
+        return Action::SwitchGuiScreen(GuiScreen::Player);
+    }
+
+    let width = d.get_screen_width() as f32;
+    let before = playlist.equalizer.clone();
+    let eq = &mut playlist.equalizer;
+
+    // keyboard: up/down picks a slider, left/right moves it by 1dB, enter turns it on and off
+    if d.is_key_pressed(KeyboardKey::KEY_UP) && state.row > 0 {
+        state.row -= 1;
+    }
+    if d.is_key_pressed(KeyboardKey::KEY_DOWN) && state.row < BANDS {
+        state.row += 1;
+    }
+    let step = if d.is_key_pressed(KeyboardKey::KEY_RIGHT) {
+        1.0
+    } else if d.is_key_pressed(KeyboardKey::KEY_LEFT) {
+        -1.0
+    } else {
+        0.0
+    };
+    if step != 0.0 {
+        let value = match state.row {
+            0 => &mut eq.preamp,
+            band => &mut eq.gains[band - 1],
+        };
+        *value = (*value + step).clamp(-MAX_GAIN, MAX_GAIN);
+    }
+    if d.is_key_pressed(KeyboardKey::KEY_ENTER) {
+        eq.enabled = !eq.enabled;
+    }
+
+    eq.enabled = d.gui_check_box(
+        Rectangle::new(10.0, 32.0, 14.0, 14.0),
+        Some(rstr!("Enabled")),
+        eq.enabled,
+    );
+
+    let mut y = 56.0;
+    for row in 0..=BANDS {
+        let (name, value) = match row {
+            0 => ("Pre".to_string(), &mut eq.preamp),
+            band => {
+                let frequency = FREQUENCIES[band - 1];
+                let name = if frequency >= 1000.0 {
+                    format!("{}k", frequency / 1000.0)
+                } else {
+                    format!("{frequency}")
+                };
+                (name, &mut eq.gains[band - 1])
+            }
+        };
+        let name = CString::new(name).unwrap_or_default();
+        let label = CString::new(format!("{:+.1} dB", value)).unwrap_or_default();
+
+        if row == state.row {
+            gui_highlight_start();
+        }
+        let new_value = d.gui_slider(
+            Rectangle::new(40.0, y, width - 100.0, 14.0),
+            Some(name.as_c_str()),
+            Some(label.as_c_str()),
+            *value,
+            -MAX_GAIN,
+            MAX_GAIN,
+        );
+        if row == state.row {
+            gui_highlight_end();
+        }
+        if new_value != *value {
+            // half dB steps are plenty and make it easy to get back to 0
+            *value = (new_value * 2.0).round() / 2.0;
+            state.row = row;
+        }
+        y += ROW_HEIGHT;
+        if row == 0 {
+            // a little gap between the preamp and the bands
+            y += 6.0;
+        }
+    }
+    if eq.gains != before.gains {
+        eq.preset = None;
+    }
+
+    // preset selection, cycling through all presets
+    y += 6.0;
+    let names: Vec<String> = eq.presets().map(|(name, _)| name.to_string()).collect();
+    let current = eq
+        .preset
+        .as_ref()
+        .and_then(|preset| names.iter().position(|name| name == preset));
+    let mut select = None;
+    if d.gui_button(Rectangle::new(10.0, y, 24.0, 24.0), Some(rstr!("#114#"))) {
+        select = Some(match current {
+            Some(0) | None => names.len() - 1,
+            Some(idx) => idx - 1,
+        });
+    }
+    if d.gui_button(Rectangle::new(width - 34.0, y, 24.0, 24.0), Some(rstr!("#115#"))) {
+        select = Some(current.map_or(0, |idx| (idx + 1) % names.len()));
+    }
+    let preset_name = CString::new(eq.preset.as_deref().unwrap_or("Custom")).unwrap_or_default();
+    d.gui_label(
+        Rectangle::new(44.0, y, width - 88.0, 24.0),
+        Some(preset_name.as_c_str()),
+    );
+    if let Some(idx) = select {
+        eq.select_preset(&names[idx]);
+    }
+
+    y += ROW_HEIGHT + 6.0;
+    let half = (width - 30.0) / 2.0;
+    if d.gui_button(Rectangle::new(10.0, y, half, 24.0), Some(rstr!("Save as preset"))) {
+        let name = (1..)
+            .map(|n| format!("Preset {n}"))
+            .find(|name| !names.contains(name))
+            .unwrap_or_default();
+        eq.save_preset(name);
+    }
+    let user_preset = eq
+        .preset
+        .as_ref()
+        .is_some_and(|preset| eq.user_presets.iter().any(|user| user.name == *preset));
+    if user_preset
+        && d.gui_button(
+            Rectangle::new(20.0 + half, y, half, 24.0),
+            Some(rstr!("Delete preset")),
+        )
+    {
+        if let Some(preset) = eq.preset.clone() {
+            eq.delete_preset(&preset);
+        }
+    }
+
+    // per song preset
+    y += ROW_HEIGHT + 6.0;
+    let song = playlist
+        .currently_playing_id()
+        .and_then(|idx| playlist.get_songs().get(idx))
+        .map(|song| song.path().to_path_buf());
+    let eq = &mut playlist.equalizer;
+    if let Some(song) = song {
+        match eq.song_presets.get(&song).cloned() {
+            Some(preset) => {
+                let text = CString::new(format!("This song uses {preset}, reset"))
+                    .unwrap_or_default();
+                if d.gui_button(
+                    Rectangle::new(10.0, y, width - 20.0, 24.0),
+                    Some(text.as_c_str()),
+                ) {
+                    eq.song_presets.remove(&song);
+                }
+            }
+            None => {
+                if let Some(preset) = eq.preset.clone() {
+                    if d.gui_button(
+                        Rectangle::new(10.0, y, width - 20.0, 24.0),
+                        Some(rstr!("Use this preset for this song")),
+                    ) {
+                        eq.song_presets.insert(song, preset);
+                    }
+                }
+            }
+        }
+    }
+
+    if playlist.equalizer != before {
+        playlist.apply_equalizer(audio);
+    }
+
+    // balance, mono and so on have their own screen
+    y += ROW_HEIGHT + 6.0;
+    if d.gui_button(
+        Rectangle::new(10.0, y, width - 20.0, 24.0),
+        Some(rstr!("Stereo (S)")),
+    ) || d.is_key_pressed(KeyboardKey::KEY_S)
+    {
+        return Action::SwitchGuiScreen(GuiScreen::Stereo);
+    }
+
+    Action::None
+}
@@ -0,0 +1,167 @@
+use std::ffi::CString;
+
+use raylib::{
+    color::Color,
+    drawing::{RaylibDraw, RaylibScissorModeExt},
+    ffi::KeyboardKey,
+    math::{Rectangle, Vector2},
+    rgui::RaylibDrawGui,
+    rstr,
+    text::measure_text,
+    RaylibHandle, RaylibThread,
+};
+
+use mp3_player::{audio_raylib::RaylibBackend, plays, song::Playlist};
+
+use crate::{
+    gui_main::{gui_highlight_end, gui_highlight_start, Action, ICON_PLAY},
+    GuiScreen,
+};
+
+#[derive(Default)]
+pub struct HistoryGuiState {
+    /// row on screen, the newest song is the first row
+    selected: usize,
+    scroll: Vector2,
+}
+
+const MP3_PLAYER_NAME_HISTORY: &std::ffi::CStr = rstr!("#11#MP3 Player - History");
+const ROW_HEIGHT: f32 = 30.0;
+
+pub fn render_history_gui(
+    playlist: &mut Playlist<RaylibBackend>,
+    audio: &mut RaylibBackend,
+    thread: &RaylibThread,
+    rl: &mut RaylibHandle,
+    state: &mut HistoryGuiState,
+) -> Action {
+    let screen_height = rl.get_screen_height();
+    let len = playlist.history().len();
+    state.selected = state.selected.min(len.saturating_sub(1));
+
+    // keyboard: up/down selects, enter plays the song again
+    if rl.is_key_pressed(KeyboardKey::KEY_UP) && state.selected > 0 {
+        state.selected -= 1;
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_DOWN) && state.selected + 1 < len {
+        state.selected += 1;
+    }
+    if len > 0 && rl.is_key_pressed(KeyboardKey::KEY_ENTER) {
+        let idx = playlist.history()[len - 1 - state.selected].idx;
+        playlist.play_ignore_err(idx, audio, screen_height);
+        state.selected = 0;
+    }
+
+    let mut d = rl.begin_drawing(thread);
+
+    if d.gui_window_box(
+        Rectangle::new(
+            0.0,
+            0.0,
+            d.get_screen_width() as f32,
+            d.get_screen_height() as f32,
+        ),
+        Some(MP3_PLAYER_NAME_HISTORY),
+    ) || d.is_key_pressed(KeyboardKey::KEY_ESCAPE)
+    {
+        return Action::SwitchGuiScreen(GuiScreen::Player);
+    }
+
+    if len == 0 {
+        let text = "Nothing played yet";
+        d.draw_text(
+            text,
+            (d.get_screen_width() - measure_text(text, 20)) / 2,
+            30,
+            20,
+            Color::GRAY,
+        );
+        return Action::None;
+    }
+
+    let width = d.get_screen_width() as f32;
+    let height = len as f32 * ROW_HEIGHT + 10.0;
+    let (rect, scroll) = d.gui_scroll_panel(
+        Rectangle::new(0.0, 24.0, width, d.get_screen_height() as f32 - 24.0),
+        None,
+        Rectangle::new(0.0, 24.0, width - 14.0, height),
+        state.scroll,
+    );
+    state.scroll = scroll;
+
+    let mut d = d.begin_scissor_mode(
+        rect.x as i32,
+        rect.y as i32,
+        rect.width as i32,
+        rect.height as i32,
+    );
+
+    let now = plays::now();
+    let current = playlist.history_position();
+    let mut clicked_entry = None;
+    let mut y = rect.y + state.scroll.y + 5.0;
+    for row in 0..len {
+        if y >= rect.y + rect.height {
+            break;
+        }
+        if y + ROW_HEIGHT < rect.y {
+            y += ROW_HEIGHT;
+            continue;
+        }
+        let position = len - 1 - row;
+        let entry = playlist.history()[position];
+        let name = playlist
+            .get_songs()
+            .get(entry.idx)
+            .map(|song| song.file_name().to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let icon = if current == Some(position) {
+            ICON_PLAY.to_str().unwrap_or_default()
+        } else {
+            ""
+        };
+        let label = CString::new(format!("{icon}{name}")).unwrap_or_default();
+        let ago = time_ago(now.saturating_sub(entry.played_at));
+        let ago_width = measure_text(&ago, 10) as f32;
+        let button_width = rect.width - ago_width - 25.0;
+
+        if row == state.selected {
+            gui_highlight_start();
+        }
+        let clicked = d.gui_button(
+            Rectangle::new(rect.x + 5.0, y, button_width, 22.0),
+            Some(label.as_c_str()),
+        );
+        if row == state.selected {
+            gui_highlight_end();
+        }
+        if clicked && rect.check_collision_point_rec(d.get_mouse_position()) {
+            clicked_entry = Some(entry.idx);
+        }
+        d.draw_text(
+            &ago,
+            (rect.x + button_width + 15.0) as i32,
+            y as i32 + 6,
+            10,
+            Color::GRAY,
+        );
+        y += ROW_HEIGHT;
+    }
+
+    if let Some(idx) = clicked_entry {
+        playlist.play_ignore_err(idx, audio, screen_height);
+        state.selected = 0;
+    }
+
+    Action::None
+}
+
+/// Roughly how long `seconds` ago was, like "5 min ago".
+fn time_ago(seconds: u64) -> String {
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{} min ago", seconds / 60),
+        3600..=86399 => format!("{} h ago", seconds / 3600),
+        _ => format!("{} d ago", seconds / 86400),
+    }
+}
@@ -0,0 +1,130 @@
+use raylib::{
+    color::Color,
+    drawing::{RaylibDraw, RaylibScissorModeExt},
+    ffi::KeyboardKey,
+    math::{Rectangle, Vector2},
+    rgui::RaylibDrawGui,
+    rstr,
+    text::measure_text,
+    RaylibHandle, RaylibThread,
+};
+
+use crate::{
+    gui_main::Action,
+    notifications::{Level, Notifications},
+    GuiScreen,
+};
+
+#[derive(Default)]
+pub struct LogGuiState {
+    scroll: Vector2,
+}
+
+const MP3_PLAYER_NAME_LOG: &std::ffi::CStr = rstr!("#11#MP3 Player - Log");
+const LINE_HEIGHT: i32 = 15;
+
+pub fn render_log_gui(
+    notifications: &mut Notifications,
+    thread: &RaylibThread,
+    rl: &mut RaylibHandle,
+    state: &mut LogGuiState,
+) -> Action {
+    notifications.mark_read();
+
+    if rl.is_key_pressed(KeyboardKey::KEY_DELETE) {
+        notifications.clear_log();
+        state.scroll = Vector2::default();
+    }
+
+    let mut d = rl.begin_drawing(thread);
+
+    if d.gui_window_box(
+        Rectangle::new(
+            0.0,
+            0.0,
+            d.get_screen_width() as f32,
+            d.get_screen_height() as f32,
+        ),
+        Some(MP3_PLAYER_NAME_LOG),
+    ) || d.is_key_pressed(KeyboardKey::KEY_ESCAPE)
+    {
+        return Action::SwitchGuiScreen(GuiScreen::Player);
+    }
+
+    let log = notifications.log();
+    if log.is_empty() {
+        d.draw_text(
+            "Nothing happened yet",
+            (d.get_screen_width() - measure_text("Nothing happened yet", 20)) / 2,
+            30,
+            20,
+            Color::GRAY,
+        );
+        return Action::None;
+    }
+
+    if d.is_key_pressed(KeyboardKey::KEY_UP) {
+        state.scroll.y += LINE_HEIGHT as f32;
+    }
+    if d.is_key_pressed(KeyboardKey::KEY_DOWN) {
+        state.scroll.y -= LINE_HEIGHT as f32;
+    }
+    if d.is_key_pressed(KeyboardKey::KEY_LEFT) {
+        state.scroll.x += 15.0;
+    }
+    if d.is_key_pressed(KeyboardKey::KEY_RIGHT) {
+        state.scroll.x -= 15.0;
+    }
+
+    // newest entries first
+    let lines: Vec<String> = log
+        .iter()
+        .rev()
+        .map(|entry| format!("{} {}", entry.time, entry.message.replace('\n', " ")))
+        .collect();
+    let width = lines
+        .iter()
+        .map(|line| measure_text(line, 10))
+        .max()
+        .unwrap_or(0)
+        + 6;
+    let height = lines.len() as i32 * LINE_HEIGHT + 12; // 6px padding top & bottom
+
+    let (rect, scroll) = d.gui_scroll_panel(
+        Rectangle::new(
+            0.0,
+            24.0,
+            d.get_screen_width() as f32,
+            (d.get_screen_height() - 24) as f32,
+        ),
+        None,
+        Rectangle::new(0.0, 24.0, width as f32, height as f32),
+        state.scroll,
+    );
+    state.scroll = scroll;
+
+    let mut d = d.begin_scissor_mode(
+        rect.x as i32,
+        rect.y as i32,
+        rect.width as i32,
+        rect.height as i32,
+    );
+
+    let offset_x = 3 + state.scroll.x as i32;
+    let mut offset_y = 24 + 6 + state.scroll.y as i32;
+    for (line, entry) in lines.iter().zip(log.iter().rev()) {
+        if offset_y + LINE_HEIGHT >= 24 {
+            let color = match entry.level {
+                Level::Error => Color::MAROON,
+                Level::Info => Color::DARKGRAY,
+            };
+            d.draw_text(line, offset_x, offset_y, 10, color);
+        }
+        offset_y += LINE_HEIGHT;
+        if offset_y >= d.get_screen_height() {
+            break;
+        }
+    }
+
+    Action::None
+}
@@ -2,7 +2,9 @@ use raylib::{
     color::Color, drawing::RaylibDraw, math::{Rectangle, Vector2}, rgui::RaylibDrawGui, rstr, text::measure_text, RaylibHandle, RaylibThread
 };
 
-use crate::{gui_main::Action, song::Playlist, GuiScreen};
+use mp3_player::{audio_raylib::RaylibBackend, song::Playlist};
+
+use crate::{gui_main::Action, GuiScreen};
 
 #[derive(Default)]
 pub struct LyricsGuiState {
@@ -12,7 +14,7 @@ pub struct LyricsGuiState {
 const MP3_PLAYER_NAME_LYRICS: &std::ffi::CStr = rstr!("#11#MP3 Player - Lyrics");
 
 pub fn render_lyrics_gui(
-    playlist: &mut Playlist,
+    playlist: &mut Playlist<RaylibBackend>,
     thread: &RaylibThread,
     rl: &mut RaylibHandle,
     state: &mut LyricsGuiState,
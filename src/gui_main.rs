@@ -1,17 +1,24 @@
+use std::{ffi::CString, time::Duration};
+
+use mp3_player::{
+    audio::AudioBackend,
+    audio_raylib::RaylibBackend,
+    sleep::{SleepAfter, SleepTimer},
+    song::{Playlist, RepeatBehavior, ShuffleBehavior},
+    speed::{self, MAX_SPEED, MIN_SPEED},
+};
 use raylib::{
-    audio::RaylibAudio,
     color::Color,
     drawing::{RaylibDraw, RaylibDrawHandle, RaylibScissorModeExt},
-    ffi::{GuiControl, GuiControlProperty, KeyboardKey},
+    ffi::{GuiControl, GuiControlProperty, KeyboardKey, MouseButton},
     math::{Rectangle, Vector2},
     rgui::RaylibDrawGui,
-    rstr, RaylibHandle, RaylibThread,
+    rstr,
+    text::measure_text,
+    RaylibHandle, RaylibThread,
 };
 
-use crate::{
-    song::{Playlist, RepeatBehavior},
-    GuiScreen,
-};
+use crate::{config::Config, notifications::Notifications, GuiScreen};
 
 pub enum Action {
     None,
@@ -34,6 +41,38 @@ pub const ICON_FILE_ADD: &std::ffi::CStr = rstr!("#008#");
 pub const ICON_FOLDER_ADD: &std::ffi::CStr = rstr!("#221#");
 pub const ICON_FILE_CLOSE: &std::ffi::CStr = rstr!("#009#");
 pub const ICON_LYRICS: &std::ffi::CStr = rstr!("#219#");
+pub const ICON_INFO: &std::ffi::CStr = rstr!("#191#");
+pub const ICON_REPEAT: &std::ffi::CStr = rstr!("#224#");
+pub const ICON_NO_REPEAT: &std::ffi::CStr = rstr!("#222#");
+pub const ICON_REPEAT_SINGLE: &std::ffi::CStr = rstr!("#223#");
+pub const ICON_EQUALIZER: &std::ffi::CStr = rstr!("#225#");
+pub const ICON_SLEEP: &std::ffi::CStr = rstr!("#226#");
+pub const ICON_QUEUE: &std::ffi::CStr = rstr!("#227#");
+pub const ICON_NO_SHUFFLE: &std::ffi::CStr = rstr!("#228#");
+pub const ICON_SMART_SHUFFLE: &std::ffi::CStr = rstr!("#229#");
+pub const ICON_HISTORY: &std::ffi::CStr = rstr!("#230#");
+
+pub fn repeat_behavior_icon(repeat_behavior: RepeatBehavior) -> &'static std::ffi::CStr {
+    match repeat_behavior {
+        RepeatBehavior::Normal => ICON_NO_REPEAT,
+        RepeatBehavior::Repeat => ICON_REPEAT,
+        RepeatBehavior::RepeatSingle => ICON_REPEAT_SINGLE,
+    }
+}
+
+pub fn shuffle_behavior_icon(shuffle_behavior: ShuffleBehavior) -> &'static std::ffi::CStr {
+    match shuffle_behavior {
+        ShuffleBehavior::Normal => ICON_NO_SHUFFLE,
+        ShuffleBehavior::Shuffle => ICON_SHUFFLE,
+        ShuffleBehavior::Smart => ICON_SMART_SHUFFLE,
+    }
+}
+
+fn next_shuffle_behavior(playlist: &mut Playlist<RaylibBackend>) {
+    let mut shuffle_behavior = playlist.shuffle_behavior();
+    shuffle_behavior.next();
+    playlist.set_shuffle_behavior(shuffle_behavior);
+}
 
 pub fn gui_get_style_color(control: GuiControl, property: GuiControlProperty) -> Color {
     unsafe {
@@ -97,10 +136,50 @@ macro_rules! music_control_button {
     }};
 }
 
+/// Seconds of playback the notice about continuing a long song is shown for.
+const RESUMED_NOTICE_TIME: f32 = 10.0;
+
+/// What the sleep button steps through, starting from off.
+const SLEEP_MINUTES: [u64; 5] = [15, 30, 45, 60, 90];
+/// After the minutes, the sleep button steps through the end of this many songs.
+const SLEEP_SONGS: [u64; 4] = [1, 2, 3, 5];
+
+/// The sleep timer after `current` when clicking the sleep button: off, the minutes, the end of
+/// the current song, the end of a few more songs, off again.
+fn next_sleep_timer(current: Option<SleepAfter>) -> Option<SleepAfter> {
+    let minutes = |minutes: u64| SleepAfter::Duration(Duration::from_secs(minutes * 60));
+    match current {
+        None => Some(minutes(SLEEP_MINUTES[0])),
+        Some(SleepAfter::Duration(duration)) => Some(
+            SLEEP_MINUTES
+                .into_iter()
+                .find(|step| Duration::from_secs(step * 60) > duration)
+                .map_or(SleepAfter::Songs(SLEEP_SONGS[0]), minutes),
+        ),
+        Some(SleepAfter::Songs(songs)) => SLEEP_SONGS
+            .into_iter()
+            .find(|step| *step > songs)
+            .map(SleepAfter::Songs),
+    }
+}
+
+fn sleep_timer_text(
+    timer: &SleepTimer,
+    playlist: &Playlist<RaylibBackend>,
+    audio: &RaylibBackend,
+) -> String {
+    match timer.remaining(playlist, audio) {
+        Some(seconds) => {
+            let seconds = seconds.ceil() as u64;
+            format!("Sleep in {}:{:02}", seconds / 60, seconds % 60)
+        }
+        None => format!("Sleep after {} songs", timer.songs_left(playlist).unwrap_or(0)),
+    }
+}
+
 pub fn update_music(
-    audio: &mut RaylibAudio,
-    playlist: &mut Playlist,
-    thread: &RaylibThread,
+    audio: &mut RaylibBackend,
+    playlist: &mut Playlist<RaylibBackend>,
     rl: &mut RaylibHandle,
     main_state: &mut MainGuiState,
 ) {
@@ -113,43 +192,18 @@ pub fn update_music(
             || rl.is_key_down(KeyboardKey::KEY_RIGHT_SHIFT)
         {
             // prev
-            if let Some(idx) = playlist.currently_playing_id() {
-                if idx == 0 {
-                    playlist.play_ignore_err(idx + 1, &thread, audio, rl.get_screen_height());
-                } else {
-                    if idx < playlist.len() {
-                        playlist.play_ignore_err(idx - 1, thread, audio, rl.get_screen_height());
-                    } else if playlist.len() > 0 {
-                        playlist.play_ignore_err(
-                            playlist.len() - 1,
-                            thread,
-                            audio,
-                            rl.get_screen_height(),
-                        );
-                    }
-                }
-            } else {
-                playlist.play_ignore_err(0, &thread, audio, rl.get_screen_height());
-            }
+            playlist.play_previous(audio, rl.get_screen_height());
         } else {
             // next
-            if let Some(idx) = playlist.currently_playing_id() {
-                if idx + 1 < playlist.len() {
-                    playlist.play_ignore_err(idx + 1, &thread, audio, rl.get_screen_height());
-                } else {
-                    playlist.play_ignore_err(0, &thread, audio, rl.get_screen_height());
-                }
-            } else {
-                playlist.play_ignore_err(0, &thread, audio, rl.get_screen_height());
-            }
+            playlist.play_next(audio, rl.get_screen_height());
         }
     }
     if rl.is_key_pressed(KeyboardKey::KEY_R) {
         if rl.is_key_down(KeyboardKey::KEY_LEFT_CONTROL)
             || rl.is_key_down(KeyboardKey::KEY_RIGHT_CONTROL)
         {
-            // shuffle playlist
-            playlist.shuffle();
+            // shuffle off, on, smart
+            next_shuffle_behavior(playlist);
         } else {
             // repeat next
             playlist.repeat_behavior.next();
@@ -160,7 +214,7 @@ pub fn update_music(
             || rl.is_key_down(KeyboardKey::KEY_RIGHT_CONTROL)
         {
             // mute/unmute
-            if unsafe { raylib::ffi::GetMasterVolume() } != 0.0 {
+            if audio.master_volume() != 0.0 {
                 audio.set_master_volume(0.0);
             } else {
                 audio.set_master_volume(1.0);
@@ -171,39 +225,58 @@ pub fn update_music(
         }
     }
 
-    if let Some(idx) = playlist.currently_playing_id() {
-        if playlist.music_has_reached_the_end(&audio) {
-            match playlist.repeat_behavior {
-                RepeatBehavior::Normal => {
-                    if idx + 1 < playlist.len() {
-                        playlist.play_ignore_err(idx + 1, &thread, audio, rl.get_screen_height());
-                    } else {
-                        playlist.stop_playing(audio);
-                    }
-                }
-                RepeatBehavior::RepeatSingle => playlist.seek(0.0, audio),
-                RepeatBehavior::Repeat => {
-                    if idx + 1 < playlist.len() {
-                        playlist.play_ignore_err(idx + 1, &thread, audio, rl.get_screen_height());
-                    } else if playlist.len() > 0 {
-                        playlist.play_ignore_err(0, &thread, audio, rl.get_screen_height());
-                    } else {
-                        playlist.stop_playing(audio);
-                    }
-                }
-            }
-            // play next song
+    if rl.is_key_pressed(KeyboardKey::KEY_RIGHT_BRACKET) {
+        playlist.set_speed(speed::faster(playlist.speed()), audio);
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_LEFT_BRACKET) {
+        playlist.set_speed(speed::slower(playlist.speed()), audio);
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_BACKSLASH) {
+        playlist.set_speed(1.0, audio);
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_ZERO) {
+        // start over, forgetting where a long song stopped last time
+        playlist.start_over(audio);
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_F) {
+        playlist.mark_finished(audio, rl.get_screen_height());
+    }
+    if playlist.has_music_stream()
+        && (rl.is_key_pressed(KeyboardKey::KEY_A) || rl.is_key_pressed(KeyboardKey::KEY_B))
+    {
+        // a-b loop, shift clears it
+        let position = playlist.music_length_played(audio);
+        if rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT)
+            || rl.is_key_down(KeyboardKey::KEY_RIGHT_SHIFT)
+        {
+            playlist.clear_ab_loop();
+        } else if rl.is_key_pressed(KeyboardKey::KEY_A) {
+            playlist.set_loop_a(position);
+        } else {
+            playlist.set_loop_b(position);
         }
-        playlist.update(audio);
+    }
+
+    // the sleep timer runs in here too, also while nothing is playing, and the leading silence of
+    // songs is skipped once it's found
+    playlist.update(audio);
+    if playlist.has_music_stream() {
+        // the stream only notices its end while updating, so the next song starts in the same
+        // frame. With silence skipping, songs end where their trailing silence starts.
+        playlist.handle_ab_loop(audio);
+        playlist.handle_song_end(audio, rl.get_screen_height());
     }
 }
 
 pub fn render_main_gui(
-    audio: &mut RaylibAudio,
-    playlist: &mut Playlist,
+    audio: &mut RaylibBackend,
+    playlist: &mut Playlist<RaylibBackend>,
     thread: &RaylibThread,
     rl: &mut RaylibHandle,
     gui_state: &mut MainGuiState,
+    notifications: &mut Notifications,
+    config: &Config,
+    scan_progress: Option<(usize, usize)>,
 ) -> Action {
     let mut action: Action = Action::None;
 
@@ -223,8 +296,8 @@ pub fn render_main_gui(
             }
         }
         if gui_state.current_y == 1 {
-            // the 8 top bar buttons
-            if rl.is_key_pressed(KeyboardKey::KEY_RIGHT) && gui_state.current_x < 7 {
+            // the 12 top bar buttons
+            if rl.is_key_pressed(KeyboardKey::KEY_RIGHT) && gui_state.current_x < 11 {
                 gui_state.current_x += 1;
             }
             if rl.is_key_pressed(KeyboardKey::KEY_LEFT) && gui_state.current_x > 0 {
@@ -232,8 +305,8 @@ pub fn render_main_gui(
             }
         }
         if gui_state.current_y == 4 {
-            // the 7 top bar buttons
-            if rl.is_key_pressed(KeyboardKey::KEY_RIGHT) && gui_state.current_x < 4 {
+            // the 7 music control buttons
+            if rl.is_key_pressed(KeyboardKey::KEY_RIGHT) && gui_state.current_x < 6 {
                 gui_state.current_x += 1;
             }
             if rl.is_key_pressed(KeyboardKey::KEY_LEFT) && gui_state.current_x > 0 {
@@ -242,7 +315,7 @@ pub fn render_main_gui(
         }
         if rl.is_key_released(KeyboardKey::KEY_ENTER) && gui_state.current_y == 2 {
             gui_state.currently_unselected = true;
-            playlist.init_select(rl.get_screen_height());
+            init_select(playlist, rl.get_screen_height());
         }
     }
     if rl.is_key_released(KeyboardKey::KEY_ESCAPE) {
@@ -256,36 +329,36 @@ pub fn render_main_gui(
     // progress bar
     if gui_state.current_y == 3 || gui_state.current_y == 0 {
         let cur_prog = playlist.music_length_played(audio);
-        let max_prog = playlist.music_length_total(audio) - 6.0;
+        let max_prog = playlist.music_length_total(audio) - config.seek_step - 1.0;
 
         if rl.is_key_pressed(KeyboardKey::KEY_RIGHT) {
             if cur_prog >= max_prog {
                 playlist.pause(audio);
-                if let Some(idx) = playlist.currently_playing_id() {
-                    playlist.play_ignore_err(idx + 1, thread, audio, rl.get_screen_height());
+                if playlist.has_music_stream() {
+                    playlist.play_next(audio, rl.get_screen_height());
                 }
             } else {
-                playlist.seek(cur_prog + 5.0, audio);
+                playlist.seek(cur_prog + config.seek_step, audio);
             }
         } else if rl.is_key_pressed(KeyboardKey::KEY_LEFT) {
-            playlist.seek((cur_prog - 5.0).max(1.0), audio);
+            playlist.seek((cur_prog - config.seek_step).max(1.0), audio);
         }
     }
 
     // volume bar
     if gui_state.current_y == 5 {
-        let volume = unsafe { raylib::ffi::GetMasterVolume() };
+        let volume = audio.master_volume();
 
         if rl.is_key_pressed(KeyboardKey::KEY_RIGHT) {
-            audio.set_master_volume((volume + 0.05).min(1.0))
+            audio.set_master_volume((volume + config.volume_step).min(1.0))
         } else if rl.is_key_pressed(KeyboardKey::KEY_LEFT) {
-            audio.set_master_volume((volume - 0.05).max(0.0))
+            audio.set_master_volume((volume - config.volume_step).max(0.0))
         }
     }
 
     let mut d = rl.begin_drawing(&thread);
 
-    if gui_state.current_y == 1 && gui_state.current_x == 7 {
+    if gui_state.current_y == 1 && gui_state.current_x == 11 {
         gui_highlight_start_single_control(GuiControl::BUTTON);
     }
 
@@ -298,7 +371,7 @@ pub fn render_main_gui(
         ),
         None,
     ) || (gui_state.current_y == 1
-        && gui_state.current_x == 7
+        && gui_state.current_x == 11
         && d.is_key_pressed(KeyboardKey::KEY_ENTER))
     {
         return Action::ExitProgram;
@@ -334,6 +407,34 @@ pub fn render_main_gui(
     if window_bar_button!(6, ICON_LYRICS, gui_state, d) {
         action = Action::SwitchGuiScreen(GuiScreen::Lyrics);
     }
+    if window_bar_button!(7, ICON_INFO, gui_state, d) {
+        action = Action::SwitchGuiScreen(GuiScreen::Log);
+    }
+    if notifications.unread_errors() > 0 {
+        // little red dot on the log button
+        d.draw_rectangle(3 + 20 * 7 + 13, 4, 4, 4, Color::RED);
+    }
+    if window_bar_button!(8, ICON_EQUALIZER, gui_state, d) {
+        action = Action::SwitchGuiScreen(GuiScreen::Equalizer);
+    }
+    if window_bar_button!(9, ICON_QUEUE, gui_state, d) {
+        action = Action::SwitchGuiScreen(GuiScreen::Queue);
+    }
+    if window_bar_button!(10, ICON_HISTORY, gui_state, d) {
+        action = Action::SwitchGuiScreen(GuiScreen::History);
+    }
+    if let Some((done, total)) = scan_progress {
+        d.draw_text(
+            &format!("Analyzing {done}/{total}"),
+            3 + 20 * 11 + 4,
+            8,
+            10,
+            Color::get_color(u32::from_be_bytes(
+                d.gui_get_style(GuiControl::DEFAULT, 2 /* TEXT_COLOR_NORMAL */)
+                    .to_be_bytes(),
+            )),
+        );
+    }
 
     d.gui_set_style(
         GuiControl::BUTTON,
@@ -343,7 +444,7 @@ pub fn render_main_gui(
 
     let progress = playlist.progress(&audio);
 
-    let soundcontrol_start_x = (d.get_screen_width() / 2 - 90) as f32;
+    let soundcontrol_start_x = (d.get_screen_width() / 2 - 128) as f32;
     let soundcontrol_y = (d.get_screen_height() - 75) as f32;
 
     if gui_state.current_y == 3 {
@@ -369,10 +470,46 @@ pub fn render_main_gui(
         playlist.seek(new_progress * playlist.music_length_total(&audio), audio);
     }
 
+    // a-b loop: right clicks on the progress bar set A, then B, then clear the loop
+    let progress_bar = Rectangle::new(
+        10.0,
+        soundcontrol_y - 24.0,
+        (d.get_screen_width() - 20) as f32,
+        18.0,
+    );
+    let length = playlist.music_length_total(audio);
+    if playlist.has_music_stream()
+        && length > 0.0
+        && d.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_RIGHT)
+        && progress_bar.check_collision_point_rec(d.get_mouse_position())
+    {
+        let position = ((d.get_mouse_position().x - progress_bar.x) / progress_bar.width)
+            .clamp(0.0, 1.0)
+            * length;
+        match playlist.loop_points() {
+            (None, _) => playlist.set_loop_a(position),
+            (Some(_), None) => playlist.set_loop_b(position),
+            (Some(_), Some(_)) => playlist.clear_ab_loop(),
+        }
+    }
+    if length > 0.0 {
+        let x = |position: f32| progress_bar.x + position / length * progress_bar.width;
+        let (a, b) = playlist.loop_points();
+        if let (Some(a), Some(b)) = (a, b) {
+            d.draw_rectangle_rec(
+                Rectangle::new(x(a), soundcontrol_y - 20.0, x(b) - x(a), 10.0),
+                Color::ORANGE.fade(0.3),
+            );
+        }
+        for point in [a, b].into_iter().flatten() {
+            d.draw_rectangle(x(point) as i32 - 1, soundcontrol_y as i32 - 23, 2, 16, Color::ORANGE);
+        }
+    }
+
     if gui_state.current_y == 5 {
         gui_highlight_start();
     }
-    let volume = unsafe { raylib::ffi::GetMasterVolume() };
+    let volume = audio.master_volume();
     let new_volume = d.gui_slider_bar(
         Rectangle::new(
             27.0,
@@ -398,7 +535,7 @@ pub fn render_main_gui(
         Rectangle::new(10.0, soundcontrol_y + 28.0, 24.0, 24.0),
         None,
     ) {
-        if unsafe { raylib::ffi::GetMasterVolume() } != 0.0 {
+        if audio.master_volume() != 0.0 {
             audio.set_master_volume(0.0);
         } else {
             audio.set_master_volume(1.0);
@@ -411,13 +548,13 @@ pub fn render_main_gui(
 
     if music_control_button!(
         0,
-        ICON_SHUFFLE,
+        shuffle_behavior_icon(playlist.shuffle_behavior()),
         gui_state,
         d,
         soundcontrol_start_x,
         soundcontrol_y
     ) {
-        playlist.shuffle();
+        next_shuffle_behavior(playlist);
     }
     if music_control_button!(
         1,
@@ -427,20 +564,7 @@ pub fn render_main_gui(
         soundcontrol_start_x,
         soundcontrol_y
     ) {
-        let idx = playlist.currently_playing_id().unwrap_or(0);
-
-        if idx == 0 {
-            playlist.play_ignore_err(0, &thread, audio, d.get_screen_height());
-        } else {
-            let idx = if idx - 1 < playlist.len() {
-                idx - 1
-            } else if playlist.len() > 0 {
-                playlist.len() - 1
-            } else {
-                0
-            };
-            playlist.play_ignore_err(idx, &thread, audio, d.get_screen_height());
-        }
+        playlist.play_previous(audio, d.get_screen_height());
     }
     if music_control_button!(
         3,
@@ -450,19 +574,11 @@ pub fn render_main_gui(
         soundcontrol_start_x,
         soundcontrol_y
     ) {
-        if let Some(idx) = playlist.currently_playing_id() {
-            if idx + 1 < playlist.len() {
-                playlist.play_ignore_err(idx + 1, &thread, audio, d.get_screen_height());
-            } else {
-                playlist.play_ignore_err(0, &thread, audio, d.get_screen_height());
-            }
-        } else {
-            playlist.play_ignore_err(0, &thread, audio, d.get_screen_height());
-        }
+        playlist.play_next(audio, d.get_screen_height());
     }
     if music_control_button!(
         4,
-        playlist.repeat_behavior.to_icon(),
+        repeat_behavior_icon(playlist.repeat_behavior),
         gui_state,
         d,
         soundcontrol_start_x,
@@ -470,6 +586,34 @@ pub fn render_main_gui(
     ) {
         playlist.repeat_behavior.next();
     }
+    let speed = CString::new(format!("{}x", playlist.speed())).unwrap_or_default();
+    if music_control_button!(
+        5,
+        speed.as_c_str(),
+        gui_state,
+        d,
+        soundcontrol_start_x,
+        soundcontrol_y
+    ) {
+        // step through the speeds, going back to the slowest after the fastest
+        let speed = if playlist.speed() >= MAX_SPEED {
+            MIN_SPEED
+        } else {
+            speed::faster(playlist.speed())
+        };
+        playlist.set_speed(speed, audio);
+    }
+    if music_control_button!(
+        6,
+        ICON_SLEEP,
+        gui_state,
+        d,
+        soundcontrol_start_x,
+        soundcontrol_y
+    ) {
+        let after = next_sleep_timer(playlist.sleep_timer().map(SleepTimer::after));
+        playlist.set_sleep_timer(after, audio);
+    }
 
     if playlist.has_music_stream() {
         if music_control_button!(
@@ -495,7 +639,7 @@ pub fn render_main_gui(
             soundcontrol_start_x,
             soundcontrol_y
         ) {
-            playlist.play_ignore_err(0, &thread, audio, d.get_screen_height());
+            playlist.play_ignore_err(playlist.first_song(), audio, d.get_screen_height());
         }
     }
 
@@ -531,145 +675,230 @@ pub fn render_main_gui(
         )),
     );
 
-    playlist.render(
+    let resumed_from = playlist
+        .resumed_from()
+        .filter(|position| playlist.music_length_played(audio) < position + RESUMED_NOTICE_TIME);
+    if let Some(position) = resumed_from {
+        // offered for a few seconds after continuing a long song
+        let seconds = position as u64;
+        let text = CString::new(format!(
+            "Resumed at {}:{:02}, start over",
+            seconds / 60,
+            seconds % 60
+        ))
+        .unwrap_or_default();
+        let width = measure_text(text.to_str().unwrap_or_default(), 10) as f32;
+        if d.gui_label_button(
+            Rectangle::new(
+                d.get_screen_width() as f32 - 10.0 - width,
+                soundcontrol_y - 40.0,
+                width,
+                16.0,
+            ),
+            Some(text.as_c_str()),
+        ) {
+            playlist.start_over(audio);
+        }
+    } else if let Some(timer) = playlist.sleep_timer() {
+        let text = sleep_timer_text(timer, playlist, audio);
+        let width = measure_text(&text, 10);
+        d.draw_text(
+            &text,
+            d.get_screen_width() - 10 - width,
+            soundcontrol_y as i32 - 37,
+            10,
+            Color::get_color(u32::from_be_bytes(
+                d.gui_get_style(GuiControl::DEFAULT, 2 /* TEXT_COLOR_NORMAL */)
+                    .to_be_bytes(),
+            )),
+        );
+    }
+
+    render_playlist(
+        playlist,
         &mut d,
         audio,
-        thread,
         gui_state.current_y == 2 && gui_state.currently_unselected,
         gui_state.current_y == 2,
     );
 
+    if notifications.render_toast(&mut d) {
+        action = Action::SwitchGuiScreen(GuiScreen::Log);
+    }
+
     return action;
 }
 
-impl Playlist {
-    fn render(
-        &mut self,
-        d: &mut RaylibDrawHandle,
-        audio: &mut RaylibAudio,
-        thread: &RaylibThread,
-        is_focused: bool,
-        is_selected: bool,
-    ) {
-        if is_focused && self.len() > 0 {
-            if d.is_key_pressed(KeyboardKey::KEY_ENTER) {
-                self.play_ignore_err(
-                    self.__render_current_selected,
-                    thread,
-                    audio,
-                    d.get_screen_height(),
-                );
-            }
-            if d.is_key_pressed(KeyboardKey::KEY_DELETE) || d.is_key_pressed(KeyboardKey::KEY_BACKSPACE) {
-                self.remove_song(self.__render_current_selected, thread, audio, d.get_screen_height());
-                self.adjust_center_song(self.__render_current_selected, d.get_screen_height());
-            }
-            if d.is_key_pressed(KeyboardKey::KEY_UP) && self.__render_current_selected > 0 {
-                self.__render_current_selected -= 1;
-                self.adjust_center_song(self.__render_current_selected, d.get_screen_height());
-            }
-            if d.is_key_pressed(KeyboardKey::KEY_DOWN)
-                && self.__render_current_selected < self.len() - 1
+fn render_playlist(
+    playlist: &mut Playlist<RaylibBackend>,
+    d: &mut RaylibDrawHandle,
+    audio: &mut RaylibBackend,
+    is_focused: bool,
+    is_selected: bool,
+) {
+    if is_focused && playlist.len() > 0 {
+        if d.is_key_pressed(KeyboardKey::KEY_ENTER) {
+            playlist.play_ignore_err(
+                playlist.__render_current_selected,
+                audio,
+                d.get_screen_height(),
+            );
+        }
+        if d.is_key_pressed(KeyboardKey::KEY_Q) {
+            // add to the queue, shift plays it next
+            let idx = playlist.__render_current_selected;
+            if d.is_key_down(KeyboardKey::KEY_LEFT_SHIFT)
+                || d.is_key_down(KeyboardKey::KEY_RIGHT_SHIFT)
             {
-                self.__render_current_selected += 1;
-                self.adjust_center_song(self.__render_current_selected, d.get_screen_height());
-            }
-            if d.is_key_pressed(KeyboardKey::KEY_PAGE_UP) {
-                self.__render_current_selected = self.__render_current_selected.saturating_sub(10);
-                self.adjust_center_song(self.__render_current_selected, d.get_screen_height());
-            }
-            if d.is_key_pressed(KeyboardKey::KEY_PAGE_DOWN) {
-                self.__render_current_selected += 10;
-                if self.__render_current_selected >= self.len() {
-                    self.__render_current_selected = self.len() - 1;
-                }
-                self.adjust_center_song(self.__render_current_selected, d.get_screen_height());
-            }
-            if d.is_key_pressed(KeyboardKey::KEY_HOME) {
-                self.__render_current_selected = 0;
-                self.adjust_center_song(self.__render_current_selected, d.get_screen_height());
+                playlist.queue_next(idx);
+            } else {
+                playlist.enqueue(idx);
             }
-            if d.is_key_pressed(KeyboardKey::KEY_END) {
-                self.__render_current_selected = self.len() - 1;
-                self.adjust_center_song(self.__render_current_selected, d.get_screen_height());
+        }
+        if d.is_key_pressed(KeyboardKey::KEY_DELETE) || d.is_key_pressed(KeyboardKey::KEY_BACKSPACE) {
+            playlist.remove_song(playlist.__render_current_selected, audio, d.get_screen_height());
+            playlist.adjust_center_song(playlist.__render_current_selected, d.get_screen_height());
+        }
+        if d.is_key_pressed(KeyboardKey::KEY_UP) && playlist.__render_current_selected > 0 {
+            playlist.__render_current_selected -= 1;
+            playlist.adjust_center_song(playlist.__render_current_selected, d.get_screen_height());
+        }
+        if d.is_key_pressed(KeyboardKey::KEY_DOWN)
+            && playlist.__render_current_selected < playlist.len() - 1
+        {
+            playlist.__render_current_selected += 1;
+            playlist.adjust_center_song(playlist.__render_current_selected, d.get_screen_height());
+        }
+        if d.is_key_pressed(KeyboardKey::KEY_PAGE_UP) {
+            playlist.__render_current_selected = playlist.__render_current_selected.saturating_sub(10);
+            playlist.adjust_center_song(playlist.__render_current_selected, d.get_screen_height());
+        }
+        if d.is_key_pressed(KeyboardKey::KEY_PAGE_DOWN) {
+            playlist.__render_current_selected += 10;
+            if playlist.__render_current_selected >= playlist.len() {
+                playlist.__render_current_selected = playlist.len() - 1;
             }
+            playlist.adjust_center_song(playlist.__render_current_selected, d.get_screen_height());
         }
+        if d.is_key_pressed(KeyboardKey::KEY_HOME) {
+            playlist.__render_current_selected = 0;
+            playlist.adjust_center_song(playlist.__render_current_selected, d.get_screen_height());
+        }
+        if d.is_key_pressed(KeyboardKey::KEY_END) {
+            playlist.__render_current_selected = playlist.len() - 1;
+            playlist.adjust_center_song(playlist.__render_current_selected, d.get_screen_height());
+        }
+    }
 
-        let width = d.get_screen_width() - 20;
-        let height = d.get_screen_height() - 180;
-        let currently_playing_id = self.currently_playing_id().unwrap_or(self.len());
+    let width = d.get_screen_width() - 20;
+    let height = d.get_screen_height() - 180;
+    let currently_playing_id = playlist.currently_playing_id().unwrap_or(playlist.len());
 
-        let buttons_height = (self.len() * 30 + 2) as i32; // 22 buttonheight + 8 padding between buttons
+    let buttons_height = (playlist.len() * 30 + 2) as i32; // 22 buttonheight + 8 padding between buttons
 
-        let (rect, scroll) = d.gui_scroll_panel(
-            Rectangle::new(10.0, 40.0, width as f32, height as f32),
-            None,
-            Rectangle::new(10.0, 40.0, (width - 14) as f32, buttons_height as f32),
-            Vector2::new(0.0, self.__render_scroll_index),
+    let (rect, scroll) = d.gui_scroll_panel(
+        Rectangle::new(10.0, 40.0, width as f32, height as f32),
+        None,
+        Rectangle::new(10.0, 40.0, (width - 14) as f32, buttons_height as f32),
+        Vector2::new(0.0, playlist.__render_scroll_index),
+    );
+
+    if is_focused || is_selected {
+        let col = gui_get_style_color(
+            GuiControl::DEFAULT,
+            GuiControlProperty::BORDER_COLOR_FOCUSED,
         );
+        d.draw_rectangle_lines(10, 40, width, height, col);
+    }
 
-        if is_focused || is_selected {
-            let col = gui_get_style_color(
-                GuiControl::DEFAULT,
-                GuiControlProperty::BORDER_COLOR_FOCUSED,
-            );
-            d.draw_rectangle_lines(10, 40, width, height, col);
-        }
+    playlist.__render_scroll_index = scroll.y;
 
-        self.__render_scroll_index = scroll.y;
+    let x = rect.x;
+    let y = rect.y;
+    let w = rect.width;
 
-        let x = rect.x;
-        let y = rect.y;
-        let w = rect.width;
+    let button_start_y = rect.y + playlist.__render_scroll_index + 5.0;
 
-        let button_start_y = rect.y + self.__render_scroll_index + 5.0;
+    let mut d = d.begin_scissor_mode(
+        x.floor() as i32,
+        y.floor() as i32,
+        w.floor() as i32,
+        rect.height.floor() as i32,
+    );
 
-        let mut d = d.begin_scissor_mode(
-            x.floor() as i32,
-            y.floor() as i32,
-            w.floor() as i32,
-            rect.height.floor() as i32,
-        );
+    for i in 0..playlist.len() {
+        let song = &playlist.get_songs()[i];
+        if button_start_y + (i * 30) as f32 >= rect.y + rect.height {
+            break;
+        }
+        if (button_start_y + (i * 30 + 22) as f32) < rect.y {
+            continue;
+        }
+        let val = if i == currently_playing_id
+            || (is_focused && i == playlist.__render_current_selected)
+        {
+            gui_highlight_start();
+            let val = d.gui_button(
+                Rectangle::new(x + 5.0, button_start_y + (i * 30) as f32, w - 10.0, 22.0),
+                Some(song.file_name()),
+            );
+            gui_highlight_end();
+            val
+        } else {
+            d.gui_button(
+                Rectangle::new(x + 5.0, button_start_y + (i * 30) as f32, w - 10.0, 22.0),
+                Some(song.file_name()),
+            )
+        };
+
+        if song.load_failed() {
+            d.draw_text(
+                "!",
+                (x + w - 15.0) as i32,
+                (button_start_y + (i * 30) as f32 + 6.0) as i32,
+                10,
+                Color::RED,
+            );
+        }
+        if let Some(position) = playlist.queue().iter().position(|queued| *queued == i) {
+            // where it is in the queue
+            let text = format!("{}", position + 1);
+            d.draw_text(
+                &text,
+                (x + w - 25.0) as i32 - measure_text(&text, 10),
+                (button_start_y + (i * 30) as f32 + 6.0) as i32,
+                10,
+                Color::GRAY,
+            );
+        }
 
-        for i in 0..self.len() {
-            let path = &self.get_songs()[i];
-            if button_start_y + (i * 30) as f32 >= rect.y + rect.height {
-                break;
-            }
-            if (button_start_y + (i * 30 + 22) as f32) < rect.y {
-                continue;
-            }
-            let val = if i == currently_playing_id
-                || (is_focused && i == self.__render_current_selected)
+        if val && rect.check_collision_point_rec(d.get_mouse_position()) {
+            playlist.play_ignore_err(i, audio, d.get_screen_height());
+        }
+        let button = Rectangle::new(x + 5.0, button_start_y + (i * 30) as f32, w - 10.0, 22.0);
+        if d.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_RIGHT)
+            && rect.check_collision_point_rec(d.get_mouse_position())
+            && button.check_collision_point_rec(d.get_mouse_position())
+        {
+            // right click adds to the queue, with shift it plays next
+            if d.is_key_down(KeyboardKey::KEY_LEFT_SHIFT)
+                || d.is_key_down(KeyboardKey::KEY_RIGHT_SHIFT)
             {
-                gui_highlight_start();
-                let val = d.gui_button(
-                    Rectangle::new(x + 5.0, button_start_y + (i * 30) as f32, w - 10.0, 22.0),
-                    Some(path.file_name()),
-                );
-                gui_highlight_end();
-                val
+                playlist.queue_next(i);
             } else {
-                d.gui_button(
-                    Rectangle::new(x + 5.0, button_start_y + (i * 30) as f32, w - 10.0, 22.0),
-                    Some(path.file_name()),
-                )
-            };
-
-            if val && rect.check_collision_point_rec(d.get_mouse_position()) {
-                self.play_ignore_err(i, thread, audio, d.get_screen_height());
+                playlist.enqueue(i);
             }
         }
     }
+}
 
-    fn init_select(&mut self, screen_height: i32) {
-        if let Some(id) = self.currently_playing_id() {
-            self.__render_current_selected = id;
-            self.adjust_center_song(id, screen_height);
-        } else {
-            self.__render_current_selected = 0;
-            self.__render_scroll_index = 0.0;
-        }
+fn init_select(playlist: &mut Playlist<RaylibBackend>, screen_height: i32) {
+    if let Some(id) = playlist.currently_playing_id() {
+        playlist.__render_current_selected = id;
+        playlist.adjust_center_song(id, screen_height);
+    } else {
+        playlist.__render_current_selected = 0;
+        playlist.__render_scroll_index = 0.0;
     }
 }
 
@@ -0,0 +1,202 @@
+use std::ffi::CString;
+
+use raylib::{
+    color::Color,
+    drawing::{RaylibDraw, RaylibScissorModeExt},
+    ffi::KeyboardKey,
+    math::{Rectangle, Vector2},
+    rgui::RaylibDrawGui,
+    rstr,
+    text::measure_text,
+    RaylibHandle, RaylibThread,
+};
+
+use mp3_player::{audio_raylib::RaylibBackend, song::Playlist};
+
+use crate::{
+    gui_main::{gui_highlight_end, gui_highlight_start, Action},
+    GuiScreen,
+};
+
+#[derive(Default)]
+pub struct QueueGuiState {
+    selected: usize,
+    scroll: Vector2,
+}
+
+const MP3_PLAYER_NAME_QUEUE: &std::ffi::CStr = rstr!("#11#MP3 Player - Queue");
+const ROW_HEIGHT: f32 = 30.0;
+
+pub fn render_queue_gui(
+    playlist: &mut Playlist<RaylibBackend>,
+    audio: &mut RaylibBackend,
+    thread: &RaylibThread,
+    rl: &mut RaylibHandle,
+    state: &mut QueueGuiState,
+) -> Action {
+    let screen_height = rl.get_screen_height();
+    let len = playlist.queue().len();
+    state.selected = state.selected.min(len.saturating_sub(1));
+
+    // keyboard: up/down selects, with shift it moves the song, delete removes it, enter plays it
+    let shift = rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT)
+        || rl.is_key_down(KeyboardKey::KEY_RIGHT_SHIFT);
+    if rl.is_key_pressed(KeyboardKey::KEY_UP) && state.selected > 0 {
+        if shift {
+            playlist.move_queued(state.selected, state.selected - 1);
+        }
+        state.selected -= 1;
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_DOWN) && state.selected + 1 < len {
+        if shift {
+            playlist.move_queued(state.selected, state.selected + 1);
+        }
+        state.selected += 1;
+    }
+    if len > 0
+        && (rl.is_key_pressed(KeyboardKey::KEY_DELETE)
+            || rl.is_key_pressed(KeyboardKey::KEY_BACKSPACE))
+    {
+        playlist.dequeue(state.selected);
+    }
+    if len > 0 && rl.is_key_pressed(KeyboardKey::KEY_ENTER) {
+        play_queued(playlist, audio, state.selected, screen_height);
+    }
+
+    let mut d = rl.begin_drawing(thread);
+
+    if d.gui_window_box(
+        Rectangle::new(
+            0.0,
+            0.0,
+            d.get_screen_width() as f32,
+            d.get_screen_height() as f32,
+        ),
+        Some(MP3_PLAYER_NAME_QUEUE),
+    ) || d.is_key_pressed(KeyboardKey::KEY_ESCAPE)
+    {
+        return Action::SwitchGuiScreen(GuiScreen::Player);
+    }
+
+    let queue: Vec<usize> = playlist.queue().iter().copied().collect();
+    if queue.is_empty() {
+        let text = "The queue is empty";
+        d.draw_text(
+            text,
+            (d.get_screen_width() - measure_text(text, 20)) / 2,
+            30,
+            20,
+            Color::GRAY,
+        );
+        let hint = "Right click songs or press Q to add them";
+        d.draw_text(
+            hint,
+            (d.get_screen_width() - measure_text(hint, 10)) / 2,
+            56,
+            10,
+            Color::GRAY,
+        );
+        return Action::None;
+    }
+
+    let width = d.get_screen_width() as f32;
+    let height = queue.len() as f32 * ROW_HEIGHT + 10.0;
+    let (rect, scroll) = d.gui_scroll_panel(
+        Rectangle::new(0.0, 24.0, width, d.get_screen_height() as f32 - 24.0),
+        None,
+        Rectangle::new(0.0, 24.0, width - 14.0, height),
+        state.scroll,
+    );
+    state.scroll = scroll;
+
+    let mut d = d.begin_scissor_mode(
+        rect.x as i32,
+        rect.y as i32,
+        rect.width as i32,
+        rect.height as i32,
+    );
+
+    let mut action = None;
+    let mut y = rect.y + state.scroll.y + 5.0;
+    let button_x = rect.x + rect.width - 3.0 * 26.0 - 5.0;
+    for (position, idx) in queue.into_iter().enumerate() {
+        if y >= rect.y + rect.height {
+            break;
+        }
+        if y + ROW_HEIGHT < rect.y {
+            y += ROW_HEIGHT;
+            continue;
+        }
+        let name = playlist
+            .get_songs()
+            .get(idx)
+            .map(|song| song.file_name().to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let label = CString::new(format!("{}. {name}", position + 1)).unwrap_or_default();
+
+        if position == state.selected {
+            gui_highlight_start();
+        }
+        let clicked = d.gui_button(
+            Rectangle::new(rect.x + 5.0, y, button_x - rect.x - 10.0, 22.0),
+            Some(label.as_c_str()),
+        );
+        if position == state.selected {
+            gui_highlight_end();
+        }
+        let mouse_inside = rect.check_collision_point_rec(d.get_mouse_position());
+        if clicked && mouse_inside {
+            action = Some(QueueAction::Play(position));
+        }
+        if d.gui_button(Rectangle::new(button_x, y, 22.0, 22.0), Some(rstr!("#117#")))
+            && mouse_inside
+            && position > 0
+        {
+            action = Some(QueueAction::Move(position, position - 1));
+        }
+        if d.gui_button(Rectangle::new(button_x + 26.0, y, 22.0, 22.0), Some(rstr!("#116#")))
+            && mouse_inside
+        {
+            action = Some(QueueAction::Move(position, position + 1));
+        }
+        if d.gui_button(Rectangle::new(button_x + 52.0, y, 22.0, 22.0), Some(rstr!("#128#")))
+            && mouse_inside
+        {
+            action = Some(QueueAction::Remove(position));
+        }
+        y += ROW_HEIGHT;
+    }
+
+    match action {
+        Some(QueueAction::Play(position)) => {
+            play_queued(playlist, audio, position, screen_height)
+        }
+        Some(QueueAction::Move(from, to)) => {
+            playlist.move_queued(from, to);
+            state.selected = to.min(playlist.queue().len() - 1);
+        }
+        Some(QueueAction::Remove(position)) => playlist.dequeue(position),
+        None => {}
+    }
+
+    Action::None
+}
+
+enum QueueAction {
+    Play(usize),
+    Move(usize, usize),
+    Remove(usize),
+}
+
+/// Plays the queued song at `position` right away and takes it out of the queue.
+fn play_queued(
+    playlist: &mut Playlist<RaylibBackend>,
+    audio: &mut RaylibBackend,
+    position: usize,
+    screen_height: i32,
+) {
+    if let Some(&idx) = playlist.queue().get(position) {
+        playlist.dequeue(position);
+        playlist.play_ignore_err(idx, audio, screen_height);
+    }
+}
@@ -0,0 +1,131 @@
+use std::ffi::CString;
+
+use raylib::{
+    ffi::KeyboardKey,
+    math::Rectangle,
+    rgui::RaylibDrawGui,
+    rstr,
+    RaylibHandle, RaylibThread,
+};
+
+use mp3_player::{audio_raylib::RaylibBackend, song::Playlist};
+
+use crate::{
+    gui_main::{gui_highlight_end, gui_highlight_start, Action},
+    GuiScreen,
+};
+
+#[derive(Default)]
+pub struct StereoGuiState {
+    /// 0 is the balance, then the check boxes
+    row: usize,
+}
+
+const MP3_PLAYER_NAME_STEREO: &std::ffi::CStr = rstr!("#11#MP3 Player - Stereo");
+const ROW_HEIGHT: f32 = 24.0;
+const BALANCE_STEP: f32 = 0.1;
+const CHECK_BOXES: usize = 4;
+
+pub fn render_stereo_gui(
+    playlist: &mut Playlist<RaylibBackend>,
+    audio: &mut RaylibBackend,
+    thread: &RaylibThread,
+    rl: &mut RaylibHandle,
+    state: &mut StereoGuiState,
+) -> Action {
+    let mut d = rl.begin_drawing(thread);
+
+    if d.gui_window_box(
+        Rectangle::new(
+            0.0,
+            0.0,
+            d.get_screen_width() as f32,
+            d.get_screen_height() as f32,
+        ),
+        Some(MP3_PLAYER_NAME_STEREO),
+    ) || d.is_key_pressed(KeyboardKey::KEY_ESCAPE)
+    {
+        return Action::SwitchGuiScreen(GuiScreen::Equalizer);
+    }
+
+    let width = d.get_screen_width() as f32;
+    let before = playlist.channels();
+    let mut channels = before;
+
+    // keyboard: up/down picks a row, left/right moves the balance, enter centers it or toggles
+    if d.is_key_pressed(KeyboardKey::KEY_UP) && state.row > 0 {
+        state.row -= 1;
+    }
+    if d.is_key_pressed(KeyboardKey::KEY_DOWN) && state.row < CHECK_BOXES {
+        state.row += 1;
+    }
+    if state.row == 0 {
+        if d.is_key_pressed(KeyboardKey::KEY_RIGHT) {
+            channels.balance += BALANCE_STEP;
+        }
+        if d.is_key_pressed(KeyboardKey::KEY_LEFT) {
+            channels.balance -= BALANCE_STEP;
+        }
+    }
+    let enter = d.is_key_pressed(KeyboardKey::KEY_ENTER);
+
+    let balance_text = match channels.balance {
+        balance if balance.abs() < 0.01 => "Center".to_string(),
+        balance if balance < 0.0 => format!("L {:.0}%", -balance * 100.0),
+        balance => format!("R {:.0}%", balance * 100.0),
+    };
+    let balance_text = CString::new(balance_text).unwrap_or_default();
+    if state.row == 0 {
+        gui_highlight_start();
+    }
+    let balance = d.gui_slider(
+        Rectangle::new(40.0, 34.0, width - 100.0, 14.0),
+        Some(rstr!("L")),
+        Some(balance_text.as_c_str()),
+        channels.balance,
+        -1.0,
+        1.0,
+    );
+    if state.row == 0 {
+        gui_highlight_end();
+    }
+    if balance != channels.balance {
+        channels.balance = balance;
+        state.row = 0;
+    }
+    if d.gui_button(Rectangle::new(10.0, 56.0, 80.0, 20.0), Some(rstr!("Center")))
+        || (state.row == 0 && enter)
+    {
+        channels.balance = 0.0;
+    }
+
+    let mut y = 56.0 + ROW_HEIGHT + 10.0;
+    let check_boxes = [
+        (&mut channels.mono, rstr!("Mono")),
+        (&mut channels.swap, rstr!("Swap left and right")),
+        (&mut channels.invert_left, rstr!("Invert left polarity")),
+        (&mut channels.invert_right, rstr!("Invert right polarity")),
+    ];
+    for (row, (value, text)) in (1..).zip(check_boxes) {
+        if row == state.row {
+            gui_highlight_start();
+        }
+        let checked = d.gui_check_box(Rectangle::new(10.0, y, 14.0, 14.0), Some(text), *value);
+        if row == state.row {
+            gui_highlight_end();
+        }
+        if checked != *value {
+            state.row = row;
+        }
+        *value = checked != (row == state.row && enter);
+        y += ROW_HEIGHT;
+    }
+
+    // steps add up to values like 0.30000001
+    channels.balance = ((channels.balance * 100.0).round() / 100.0).clamp(-1.0, 1.0);
+    if channels != before {
+        playlist.set_channels(channels, audio);
+    }
+
+    Action::None
+}
@@ -0,0 +1,360 @@
+use std::{
+    io::{self, Read, Write},
+    net::SocketAddr,
+    thread,
+    time::Duration,
+};
+
+use mp3_player::{
+    audio::AudioBackend,
+    audio_raylib::RaylibBackend,
+    equalizer::EqualizerSettings,
+    plays::PlayStats,
+    resume::ResumePositions,
+    scanner::LoudnessScanner,
+    silence::SilenceScanner,
+    song::{Playlist, RepeatBehavior},
+};
+
+use crate::{
+    args::Args,
+    config::Config,
+    dirs,
+    ipc::{self, IpcServer},
+    mpd::MpdServer,
+    remote::c_vec_to_string,
+    save_play_stats, save_resume_positions,
+    session::{self, Session},
+};
+
+const HELP: &str = "space: play/pause, n: next, N: previous, r: repeat mode, +/-: volume, q: quit";
+
+/// Puts the terminal into non-canonical mode without echo for as long as it is alive, so single
+/// key presses can be read without waiting for a newline.
+struct RawTerminal {
+    original: libc::termios,
+}
+
+impl RawTerminal {
+    fn enable() -> io::Result<Self> {
+        let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let original = termios;
+        // ISIG is turned off as well, ctrl+c is handled as a key so the terminal gets restored
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        // make read() return immediately, even if no key was pressed
+        termios.c_cc[libc::VMIN] = 0;
+        termios.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { original })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+fn format_time(seconds: f32) -> String {
+    let seconds = seconds.max(0.0) as u32;
+    format!("{:02}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn status_line<B: AudioBackend>(playlist: &Playlist<B>, audio: &B) -> String {
+    let Some(idx) = playlist.currently_playing_id() else {
+        return "Not Playing".to_string();
+    };
+    let title = playlist
+        .filename_vec()
+        .map(|vec| c_vec_to_string(vec))
+        .unwrap_or_default();
+    let author = playlist
+        .author_vec()
+        .map(|vec| c_vec_to_string(vec))
+        .unwrap_or_default();
+    let state = if playlist.is_music_playing(audio) {
+        "playing"
+    } else {
+        "paused"
+    };
+    let repeat = match playlist.repeat_behavior {
+        RepeatBehavior::Normal => "no repeat",
+        RepeatBehavior::Repeat => "repeat",
+        RepeatBehavior::RepeatSingle => "repeat single",
+    };
+
+    format!(
+        "[{}/{}] {title} - {author}  {} / {}  ({state}, {repeat}, vol {:.0}%)",
+        idx + 1,
+        playlist.len(),
+        format_time(playlist.music_length_played(audio)),
+        format_time(playlist.music_length_total(audio)),
+        audio.master_volume() * 100.0,
+    )
+}
+
+/// Everything the headless player prints while playing goes through this, anything else on stdout
+/// would break the status line.
+#[derive(Default)]
+struct StatusLine {
+    last: String,
+}
+
+impl StatusLine {
+    /// Prints `message` above the status line, which gets redrawn by the next `draw`.
+    fn message<W: Write>(&mut self, out: &mut W, message: &str) -> io::Result<()> {
+        write!(out, "\r\x1b[2K{message}\n")?;
+        self.last.clear();
+        Ok(())
+    }
+
+    fn draw<W: Write>(&mut self, out: &mut W, status: String) -> io::Result<()> {
+        if status != self.last {
+            // \x1b[2K clears the line, so shorter lines don't leave garbage behind
+            write!(out, "\r\x1b[2K{status}")?;
+            out.flush()?;
+            self.last = status;
+        }
+        Ok(())
+    }
+}
+
+/// Plays the paths from `args` without opening a window, controlled by single key presses on the
+/// terminal.
+pub fn run(args: &Args, config: &Config) -> io::Result<()> {
+    let mut audio = RaylibBackend::init();
+    let mut playlist: Playlist<RaylibBackend> = Default::default();
+    playlist.library = config.library.clone();
+    playlist.crossfade = config.crossfade;
+    playlist.replay_gain = config.replay_gain;
+    // set up in the GUI, the headless player only uses it
+    if let Some(path) = session::equalizer_path().filter(|path| path.exists()) {
+        match EqualizerSettings::load(&path) {
+            Ok(equalizer) => playlist.equalizer = equalizer,
+            Err(err) => eprintln!("Failed to load the equalizer settings: {err}"),
+        }
+    }
+    // volume, balance and so on from the GUI's session, the songs come from the arguments
+    if let Some(session) = Session::path().and_then(|path| Session::load(&path).ok()) {
+        session.restore_settings(&mut playlist, &mut audio);
+    }
+    let resume_path = session::resume_path();
+    if let Some(path) = resume_path.as_deref().filter(|path| path.exists()) {
+        match ResumePositions::load(path) {
+            Ok(resume) => playlist.resume = resume,
+            Err(err) => eprintln!("Failed to load the resume positions: {err}"),
+        }
+    }
+    playlist.resume.min_length = config.resume_min_length * 60.0;
+    let plays_path = session::plays_path();
+    if let Some(path) = plays_path.as_deref().filter(|path| path.exists()) {
+        match PlayStats::load(path) {
+            Ok(plays) => playlist.plays = plays,
+            Err(err) => eprintln!("Failed to load the play counts: {err}"),
+        }
+    }
+    playlist.shuffle_favorites = config.smart_shuffle_favorites;
+
+    args.apply(&mut playlist, &mut audio, 0);
+    if playlist.len() < 1 {
+        eprintln!("Nothing to play");
+        return Ok(());
+    }
+
+    let mut ipc_server = match IpcServer::bind(&ipc::socket_path()) {
+        Ok(server) => Some(server),
+        Err(err) => {
+            eprintln!("Remote control is disabled: {err}");
+            None
+        }
+    };
+    let mut mpd_server = None;
+    if config.mpd_enabled {
+        let (music_dir, _) =
+            dirs::music_dir(args.music_dir.as_deref().or(config.music_dir.as_deref()));
+        let address = SocketAddr::new(config.mpd_address, config.mpd_port);
+        match MpdServer::bind(address, &music_dir) {
+            Ok(server) => mpd_server = Some(server),
+            Err(err) => eprintln!("Failed to start the MPD server on {address}: {err}"),
+        }
+    }
+    #[cfg(feature = "mpris")]
+    let mut mpris_server = match crate::mpris::MprisServer::connect() {
+        Ok(server) => Some(server),
+        Err(err) => {
+            eprintln!("MPRIS is disabled: {err}");
+            None
+        }
+    };
+
+    let mut scanner = config.replay_gain_scan.then(|| {
+        LoudnessScanner::spawn(
+            RaylibBackend::decode,
+            dirs::cache_home().map(|dir| dir.join("mp3-player").join("loudness")),
+            config.replay_gain_scan_write_tags,
+        )
+    });
+    let mut silence_scanner = config.skip_silence.then(|| {
+        SilenceScanner::spawn(
+            RaylibBackend::decode,
+            config.silence_threshold,
+            dirs::cache_home().map(|dir| dir.join("mp3-player").join("silence")),
+        )
+    });
+
+    let _raw_terminal = RawTerminal::enable()?;
+    let mut stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut status = StatusLine::default();
+    let mut buf = [0u8; 16];
+
+    println!("{HELP}");
+
+    loop {
+        let read = stdin.read(&mut buf)?;
+        for &key in &buf[..read] {
+            match key {
+                b' ' => playlist.pause_resume(&mut audio),
+                b'n' => playlist.play_next(&mut audio, 0),
+                b'N' => playlist.play_previous(&mut audio, 0),
+                b'r' => playlist.repeat_behavior.next(),
+                b'+' => {
+                    audio.set_master_volume((audio.master_volume() + config.volume_step).min(1.0))
+                }
+                b'-' => {
+                    audio.set_master_volume((audio.master_volume() - config.volume_step).max(0.0))
+                }
+                b'q' | 3 /* ctrl+c */ => {
+                    println!();
+                    save_resume_positions(resume_path.as_deref(), &mut playlist, &audio);
+                    save_play_stats(plays_path.as_deref(), &playlist.plays);
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(server) = &mut ipc_server {
+            server.poll(&mut playlist, &mut audio, 0);
+        }
+        if let Some(server) = &mut mpd_server {
+            server.poll(&mut playlist, &mut audio, 0);
+        }
+        #[cfg(feature = "mpris")]
+        if let Some(server) = &mut mpris_server {
+            server.poll(&mut playlist, &mut audio, 0);
+        }
+
+        playlist.update(&mut audio);
+        // stopping or clearing remotely keeps the player running, so songs can be added again
+        if playlist.handle_song_end(&mut audio, 0) {
+            // reached the end of the playlist
+            println!();
+            save_resume_positions(resume_path.as_deref(), &mut playlist, &audio);
+            save_play_stats(plays_path.as_deref(), &playlist.plays);
+            return Ok(());
+        }
+
+        let mut errors: Vec<String> =
+            playlist.take_errors().iter().map(|err| err.to_string()).collect();
+        if let Some(scanner) = &mut scanner {
+            errors.extend(scanner.update(&mut playlist));
+        }
+        if let Some(scanner) = &mut silence_scanner {
+            errors.extend(scanner.update(&mut playlist));
+        }
+        for err in errors {
+            status.message(&mut stdout, &err)?;
+        }
+        status.draw(&mut stdout, status_line(&playlist, &audio))?;
+
+        thread::sleep(Duration::from_millis(16));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process::Command};
+
+    use mp3_player::{audio_null::NullBackend, song::SongEntry};
+
+    use super::*;
+
+    const START: &str = "\x02";
+    const END: &str = "\x03";
+
+    /// Plays a few songs the way `run` does. It's started by `only_the_status_line_is_printed`
+    /// in a separate process, so stdout isn't captured by the test harness.
+    #[test]
+    #[ignore = "run by only_the_status_line_is_printed"]
+    fn print_status_lines() {
+        let dir =
+            std::env::temp_dir().join(format!("mp3-player-test-{}-headless", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut playlist: Playlist<NullBackend> = Playlist::default();
+        for idx in 0..3 {
+            let path = dir.join(format!("song {idx}.mp3"));
+            fs::write(&path, "").unwrap();
+            playlist.add_song(SongEntry::new(path).unwrap());
+        }
+        let mut audio = NullBackend::new().with_step(1.0).with_default_length(5.0);
+        playlist.crossfade = 1.0;
+
+        let mut stdout = io::stdout();
+        let mut status = StatusLine::default();
+        write!(stdout, "{START}").unwrap();
+        playlist.play_next(&mut audio, 0);
+        for _ in 0..100 {
+            playlist.update(&mut audio);
+            if playlist.handle_song_end(&mut audio, 0) {
+                break;
+            }
+            for err in playlist.take_errors() {
+                status.message(&mut stdout, &err.to_string()).unwrap();
+            }
+            status
+                .draw(&mut stdout, status_line(&playlist, &audio))
+                .unwrap();
+        }
+        write!(stdout, "{END}").unwrap();
+        stdout.flush().unwrap();
+        _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn only_the_status_line_is_printed() {
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "headless::tests::print_status_lines",
+                "--exact",
+                "--ignored",
+                "--nocapture",
+            ])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let (_, printed) = stdout.split_once(START).unwrap();
+        let (printed, _) = printed.split_once(END).unwrap();
+
+        let lines: Vec<&str> = printed.split('\r').collect();
+        assert_eq!(lines[0], "", "printed before the status line: {printed:?}");
+        for line in &lines[1..] {
+            assert!(line.starts_with("\x1b[2K["), "not a status line: {line:?}");
+            assert!(!line.contains('\n'), "not a status line: {line:?}");
+        }
+        for song in 1..=3 {
+            let prefix = format!("\x1b[2K[{song}/3] song {song_idx}", song_idx = song - 1);
+            assert!(
+                lines.iter().any(|line| line.starts_with(&prefix)),
+                "{prefix:?} in {printed:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use mp3_player::{audio::AudioBackend, song::Playlist};
+
+use crate::{
+    dirs,
+    remote::{json_string, status_json, Command, COMMANDS_HELP},
+};
+
+/// Clients that send more than this without a newline get disconnected.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// `$XDG_RUNTIME_DIR/mp3-player.sock`, or a per-user socket in the temp directory if there's no
+/// runtime dir.
+pub fn socket_path() -> PathBuf {
+    match dirs::runtime_dir() {
+        Some(dir) => dir.join("mp3-player.sock"),
+        None => {
+            let uid = unsafe { libc::getuid() };
+            std::env::temp_dir().join(format!("mp3-player-{uid}.sock"))
+        }
+    }
+}
+
+struct Client {
+    stream: UnixStream,
+    buf: Vec<u8>,
+}
+
+/// Listens on a unix socket for line-based commands (see [`Command::parse`]) and answers every
+/// line with a single line of JSON, either `{"ok":true,"status":{...}}` or
+/// `{"ok":false,"error":"..."}`.
+///
+/// Everything is nonblocking, [`IpcServer::poll`] has to be called once a frame.
+pub struct IpcServer {
+    path: PathBuf,
+    listener: UnixListener,
+    clients: Vec<Client>,
+}
+
+impl IpcServer {
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    ErrorKind::AddrInUse,
+                    format!("another player is already listening on {}", path.display()),
+                ));
+            }
+            // left behind by a player that crashed
+            fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        // the temp dir fallback is shared with other users
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            listener,
+            clients: Vec::new(),
+        })
+    }
+
+    pub fn poll<B: AudioBackend>(
+        &mut self,
+        playlist: &mut Playlist<B>,
+        audio: &mut B,
+        screen_height: i32,
+    ) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if stream.set_nonblocking(true).is_ok() {
+                        self.clients.push(Client {
+                            stream,
+                            buf: Vec::new(),
+                        });
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    eprintln!("Failed to accept a remote control connection: {err}");
+                    break;
+                }
+            }
+        }
+
+        self.clients
+            .retain_mut(|client| client.poll(playlist, audio, screen_height));
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Client {
+    /// Handles everything the client sent so far. Returns false once the client should be
+    /// disconnected.
+    fn poll<B: AudioBackend>(
+        &mut self,
+        playlist: &mut Playlist<B>,
+        audio: &mut B,
+        screen_height: i32,
+    ) -> bool {
+        let mut chunk = [0u8; 1024];
+        let mut connected = true;
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    connected = false;
+                    break;
+                }
+                Ok(read) => self.buf.extend_from_slice(&chunk[..read]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match Command::parse(&line)
+                .and_then(|command| command.execute(playlist, audio, screen_height))
+            {
+                Ok(()) => format!("{{\"ok\":true,\"status\":{}}}\n", status_json(playlist, audio)),
+                Err(err) => format!("{{\"ok\":false,\"error\":{}}}\n", json_string(&err)),
+            };
+            if self.stream.write_all(response.as_bytes()).is_err() {
+                return false;
+            }
+        }
+
+        connected && self.buf.len() <= MAX_LINE_LENGTH
+    }
+}
+
+/// `mp3-player ctl <command>`: sends the command to the running player and prints the response.
+/// Returns the exit code.
+pub fn run_client<I: Iterator<Item = String>>(args: I) -> i32 {
+    let command = args.collect::<Vec<_>>().join(" ");
+    if command.trim().is_empty() {
+        eprintln!("Usage: mp3-player ctl <command>\n\nCommands: {COMMANDS_HELP}");
+        return 2;
+    }
+    // catch typos before bothering the player
+    let command = match Command::parse(&command) {
+        // the player has its own working directory
+        Ok(Command::Add(path)) => match std::path::absolute(&path) {
+            Ok(path) => format!("add {}", path.display()),
+            Err(err) => {
+                eprintln!("Failed to add {}: {err}", path.display());
+                return 1;
+            }
+        },
+        Ok(_) => command,
+        Err(err) => {
+            eprintln!("{err}\n\nCommands: {COMMANDS_HELP}");
+            return 2;
+        }
+    };
+
+    let path = socket_path();
+    let response = UnixStream::connect(&path).and_then(|mut stream| {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        stream.write_all(format!("{}\n", command.trim()).as_bytes())?;
+        let mut response = String::new();
+        BufReader::new(stream).read_line(&mut response)?;
+        Ok(response)
+    });
+
+    match response {
+        Ok(response) if response.is_empty() => {
+            eprintln!("The player closed the connection without answering");
+            1
+        }
+        Ok(response) => {
+            print!("{response}");
+            if response.starts_with("{\"ok\":true") {
+                0
+            } else {
+                1
+            }
+        }
+        Err(err) => {
+            eprintln!("Failed to talk to the player at {}: {err}", path.display());
+            1
+        }
+    }
+}
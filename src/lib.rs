@@ -0,0 +1,24 @@
+//! The playlist and playback model of the player, independent of the GUI.
+//!
+//! Playback goes through an [`audio::AudioBackend`], so the model can be driven by raylib
+//! (`audio_raylib::RaylibBackend`, with the `raylib` feature) or without any audio device at all
+//! ([`audio_null::NullBackend`]).
+
+pub mod audio;
+pub mod audio_null;
+#[cfg(feature = "raylib")]
+pub mod audio_raylib;
+pub mod channels;
+pub mod equalizer;
+pub mod files;
+pub mod loudness;
+pub mod plays;
+pub mod replaygain;
+pub mod resume;
+mod rng;
+pub mod scanner;
+pub mod silence;
+pub mod sleep;
+mod smart_shuffle;
+pub mod song;
+pub mod speed;
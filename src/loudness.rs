@@ -0,0 +1,344 @@
+//! Loudness measurement following EBU R128 / ITU-R BS.1770-4: K-weighted, gated integrated
+//! loudness and the true peak from 4x oversampling.
+
+use std::{collections::BTreeMap, f64::consts::PI};
+
+use crate::audio::SampleSink;
+
+/// Loudness that ReplayGain 2.0 gains are relative to.
+pub const REFERENCE_LOUDNESS: f64 = -18.0;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+/// Histogram bins are this many LU wide.
+const BIN_WIDTH: f64 = 0.1;
+
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+/// The result of analyzing one song.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Analysis {
+    /// integrated loudness in LUFS
+    pub loudness: f64,
+    /// true peak relative to full scale
+    pub peak: f64,
+    /// number of 400ms blocks above the absolute gate per `BIN_WIDTH` bin above -70 LUFS, used to
+    /// gate a whole album at once
+    pub histogram: BTreeMap<u16, u32>,
+}
+
+impl Analysis {
+    /// Gain in dB that brings the song to the ReplayGain reference loudness.
+    pub fn gain(&self) -> f64 {
+        REFERENCE_LOUDNESS - self.loudness
+    }
+
+    /// Loudness and peak of several songs as if they were one, for album gain. `None` if all of
+    /// them are silent.
+    pub fn combine<'a, I: IntoIterator<Item = &'a Analysis>>(songs: I) -> Option<Analysis> {
+        let mut histogram = BTreeMap::new();
+        let mut peak: f64 = 0.0;
+        for song in songs {
+            peak = peak.max(song.peak);
+            for (bin, count) in &song.histogram {
+                *histogram.entry(*bin).or_insert(0) += count;
+            }
+        }
+        let blocks = histogram
+            .iter()
+            .map(|(bin, count)| (energy(*bin as f64 * BIN_WIDTH + ABSOLUTE_GATE), *count));
+        Some(Analysis {
+            loudness: gated_loudness(blocks)?,
+            peak,
+            histogram,
+        })
+    }
+}
+
+fn loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn energy(loudness: f64) -> f64 {
+    10f64.powf((loudness + 0.691) / 10.0)
+}
+
+/// Integrated loudness of blocks above the absolute gate, given as (energy, count).
+fn gated_loudness<I: Iterator<Item = (f64, u32)> + Clone>(blocks: I) -> Option<f64> {
+    let mean = |blocks: &mut dyn Iterator<Item = (f64, u32)>| {
+        let (sum, count) = blocks.fold((0.0, 0u64), |(sum, count), (energy, n)| {
+            (sum + energy * n as f64, count + n as u64)
+        });
+        if count == 0 {
+            None
+        } else {
+            Some(sum / count as f64)
+        }
+    };
+    let relative_gate = energy(loudness(mean(&mut blocks.clone())?) + RELATIVE_GATE);
+    let gated = mean(&mut blocks.filter(|(energy, _)| *energy > relative_gate))?;
+    Some(loudness(gated))
+}
+
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[1] * self.y[0]
+            - self.a[2] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// The two stages of the K-weighting filter (high shelf and high pass), with the coefficients
+/// computed for `sample_rate` like libebur128 does.
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+    [shelf, high_pass]
+}
+
+/// Windowed sinc interpolation filter, `taps[phase][tap]`.
+fn oversampling_filter() -> [[f64; TAPS_PER_PHASE]; OVERSAMPLING] {
+    let len = OVERSAMPLING * TAPS_PER_PHASE;
+    let mut taps = [[0.0; TAPS_PER_PHASE]; OVERSAMPLING];
+    for n in 0..len {
+        let x = (n as f64 - (len - 1) as f64 / 2.0) / OVERSAMPLING as f64;
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            (PI * x).sin() / (PI * x)
+        };
+        // blackman window
+        let t = n as f64 / (len - 1) as f64;
+        let window = 0.42 - 0.5 * (2.0 * PI * t).cos() + 0.08 * (4.0 * PI * t).cos();
+        taps[n % OVERSAMPLING][n / OVERSAMPLING] = sinc * window;
+    }
+    taps
+}
+
+/// BS.1770 channel weights, surround channels count more and the LFE doesn't count at all.
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        (6, 3) => 0.0,
+        (6, 4 | 5) | (5, 3 | 4) => 1.41,
+        _ => 1.0,
+    }
+}
+
+struct Channel {
+    filter: [Biquad; 2],
+    weight: f64,
+    /// newest sample first
+    history: [f64; TAPS_PER_PHASE],
+}
+
+/// Measures the loudness of the samples it is fed, see `Analyzer::finish`.
+pub struct Analyzer {
+    channels: Vec<Channel>,
+    oversampling: [[f64; TAPS_PER_PHASE]; OVERSAMPLING],
+    /// samples per channel in 100ms
+    step_len: usize,
+    step_position: usize,
+    step_energy: f64,
+    /// the energy of the last 4 steps, which make up a block
+    steps: [f64; 4],
+    step_count: usize,
+    /// energy of all 400ms blocks (overlapping by 300ms) above the absolute gate
+    blocks: Vec<f64>,
+    peak: f64,
+    /// position in the current frame, chunks don't have to end on a frame boundary
+    channel: usize,
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Analyzer {
+    pub fn new() -> Self {
+        Self {
+            channels: vec![],
+            oversampling: oversampling_filter(),
+            step_len: 0,
+            step_position: 0,
+            step_energy: 0.0,
+            steps: [0.0; 4],
+            step_count: 0,
+            blocks: vec![],
+            peak: 0.0,
+            channel: 0,
+        }
+    }
+
+    /// `None` if the song is silent (or too short to have a single 400ms block).
+    pub fn finish(self) -> Option<Analysis> {
+        let integrated = gated_loudness(self.blocks.iter().map(|energy| (*energy, 1)))?;
+        let mut histogram = BTreeMap::new();
+        for energy in &self.blocks {
+            let bin = ((loudness(*energy) - ABSOLUTE_GATE) / BIN_WIDTH).round();
+            *histogram.entry(bin.clamp(0.0, u16::MAX as f64) as u16).or_insert(0) += 1;
+        }
+        Some(Analysis {
+            loudness: integrated,
+            peak: self.peak,
+            histogram,
+        })
+    }
+
+    fn end_step(&mut self) {
+        self.steps = [self.steps[1], self.steps[2], self.steps[3], self.step_energy];
+        self.step_energy = 0.0;
+        self.step_position = 0;
+        self.step_count += 1;
+        if self.step_count >= 4 {
+            let block = self.steps.iter().sum::<f64>() / (4 * self.step_len) as f64;
+            if block > energy(ABSOLUTE_GATE) {
+                self.blocks.push(block);
+            }
+        }
+    }
+}
+
+impl SampleSink for Analyzer {
+    fn format(&mut self, sample_rate: u32, channels: u32) {
+        let channels = channels as usize;
+        self.channels = (0..channels)
+            .map(|channel| Channel {
+                filter: k_weighting(sample_rate as f64),
+                weight: channel_weight(channel, channels),
+                history: [0.0; TAPS_PER_PHASE],
+            })
+            .collect();
+        self.step_len = (sample_rate as usize / 10).max(1);
+    }
+
+    fn samples(&mut self, samples: &[f32]) {
+        if self.channels.is_empty() {
+            return;
+        }
+        for sample in samples {
+            let x = *sample as f64;
+            let channel = &mut self.channels[self.channel];
+
+            let mut y = x;
+            for filter in &mut channel.filter {
+                y = filter.process(y);
+            }
+            self.step_energy += channel.weight * y * y;
+
+            channel.history.copy_within(0..TAPS_PER_PHASE - 1, 1);
+            channel.history[0] = x;
+            let mut peak = self.peak.max(x.abs());
+            for phase in &self.oversampling {
+                let value: f64 = phase.iter().zip(&channel.history).map(|(t, x)| t * x).sum();
+                peak = peak.max(value.abs());
+            }
+            self.peak = peak;
+
+            self.channel += 1;
+            if self.channel == self.channels.len() {
+                self.channel = 0;
+                self.step_position += 1;
+                if self.step_position == self.step_len {
+                    self.end_step();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// A stereo 1 kHz sine with a peak of `dbfs`.
+    fn sine(dbfs: f64, seconds: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+        (0..(seconds * SAMPLE_RATE as f64) as usize)
+            .flat_map(|frame| {
+                let sample =
+                    amplitude * (2.0 * PI * 1000.0 * frame as f64 / SAMPLE_RATE as f64).sin();
+                [sample as f32, sample as f32]
+            })
+            .collect()
+    }
+
+    fn analyze(parts: &[&[f32]]) -> Option<Analysis> {
+        let mut analyzer = Analyzer::new();
+        analyzer.format(SAMPLE_RATE, 2);
+        for part in parts {
+            analyzer.samples(part);
+        }
+        analyzer.finish()
+    }
+
+    #[test]
+    fn measures_a_sine_like_the_reference() {
+        // EBU Tech 3341 case 1, a stereo 1 kHz sine is as loud in LUFS as its peak in dBFS
+        let analysis = analyze(&[&sine(-20.0, 10.0)]).unwrap();
+        assert!(
+            (analysis.loudness + 20.0).abs() < 0.1,
+            "{} LUFS",
+            analysis.loudness
+        );
+        assert!((analysis.gain() - 2.0).abs() < 0.1);
+        assert!((analysis.peak - 0.1).abs() < 0.001, "{}", analysis.peak);
+    }
+
+    #[test]
+    fn silence_is_gated() {
+        let tone = sine(-20.0, 5.0);
+        // the blocks that overlap the start and end of the tone count, but none of the silent ones
+        let pause = vec![0.0; SAMPLE_RATE as usize];
+        let silence = vec![0.0; 10 * SAMPLE_RATE as usize];
+        let short = analyze(&[&pause, &tone, &pause]).unwrap();
+        let long = analyze(&[&silence, &tone, &silence]).unwrap();
+        assert!(
+            (long.loudness - short.loudness).abs() < 0.01,
+            "{} LUFS, {} LUFS with less silence",
+            long.loudness,
+            short.loudness
+        );
+        assert!((long.loudness + 20.0).abs() < 0.5, "{} LUFS", long.loudness);
+        assert!(analyze(&[&silence]).is_none());
+    }
+}
@@ -1,6 +1,14 @@
-use std::{fs, path::PathBuf};
+use std::{
+    net::SocketAddr,
+    path::Path,
+    time::{Duration, Instant},
+};
 
-use raylib::audio::RaylibAudio;
+use mp3_player::{
+    audio::AudioBackend, audio_raylib::RaylibBackend, equalizer::EqualizerSettings,
+    plays::PlayStats, resume::ResumePositions, scanner::LoudnessScanner,
+    silence::SilenceScanner, song::Playlist,
+};
 
 // #[macro_export]
 // macro_rules! cstr {
@@ -9,22 +17,55 @@ use raylib::audio::RaylibAudio;
 //     };
 // }
 
+mod args;
+mod config;
+mod dirs;
 mod file_gui;
+mod gui_equalizer;
+mod gui_history;
+mod gui_log;
 mod gui_lyrics;
 mod gui_main;
-mod song;
-use song::Playlist;
+mod gui_queue;
+mod gui_stereo;
+mod headless;
+mod ipc;
+mod mpd;
+#[cfg(feature = "mpris")]
+mod mpris;
+mod notifications;
+mod remote;
+mod session;
 
 use crate::{
+    args::{Args, USAGE},
+    config::Config,
     file_gui::FileGuiState,
+    gui_equalizer::{render_equalizer_gui, EqualizerGuiState},
+    gui_history::{render_history_gui, HistoryGuiState},
+    gui_log::{render_log_gui, LogGuiState},
     gui_lyrics::{render_lyrics_gui, LyricsGuiState},
     gui_main::{render_main_gui, Action, MainGuiState},
+    gui_queue::{render_queue_gui, QueueGuiState},
+    gui_stereo::{render_stereo_gui, StereoGuiState},
+    ipc::IpcServer,
+    mpd::MpdServer,
+    notifications::Notifications,
+    session::Session,
 };
 
+/// How often the session gets saved while the player is running, so a crash doesn't lose it.
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub enum GuiScreen {
     Player,
     Lyrics,
+    Log,
+    Equalizer,
+    Stereo,
+    Queue,
+    History,
     FileSelectAddFolder,
     FileSelectAddFile,
     FileSelectOpenFolder,
@@ -33,28 +74,53 @@ pub enum GuiScreen {
 }
 
 fn main() {
-    let homedir = get_home_directory().expect("Failed to get the home directory!");
-    let musicdir = match fs::read_dir(&homedir) {
-        Err(..) => panic!("Failed to read the home directory!"),
-        Ok(mut dirs) => dirs
-            .find(|dir| {
-                if let Ok(dir) = dir {
-                    dir.file_name().to_ascii_lowercase() == "music"
-                } else {
-                    false
-                }
-            })
-            .and_then(|val| val.ok()),
+    let mut env_args = std::env::args().skip(1).peekable();
+    if env_args.next_if(|arg| arg == "ctl").is_some() {
+        std::process::exit(ipc::run_client(env_args));
+    }
+
+    let args = match Args::parse(env_args) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}\n\n{USAGE}");
+            std::process::exit(2);
+        }
     };
-    println!("Home: {}", homedir.display());
-    let musicdir = match musicdir {
-        Some(v) => homedir.join(v.file_name()),
-        None => panic!("Failed to read the music directory!"),
+    if args.help {
+        println!("{USAGE}");
+        return;
+    }
+    if args.print_default_config {
+        print!("{}", config::default_config_str());
+        return;
+    }
+
+    let (config, config_error) = match Config::path().map(|path| Config::load(&path)) {
+        Some(Ok(config)) => (config, None),
+        Some(Err(err)) => {
+            eprintln!("Invalid config, using the default settings: {err}");
+            (Config::default(), Some(err))
+        }
+        None => (Config::default(), None),
     };
-    if fs::read_dir(&musicdir).is_err() {
-        panic!("Failed to read the music directory");
+
+    if args.headless {
+        if let Err(err) = headless::run(&args, &config) {
+            eprintln!("Failed to run the headless player: {err}");
+        }
+        return;
     }
+
+    let mut notifications = Notifications::default();
+    let (musicdir, notice) =
+        dirs::music_dir(args.music_dir.as_deref().or(config.music_dir.as_deref()));
     println!("Music: {}", musicdir.display());
+    if let Some(notice) = notice {
+        notifications.info(notice);
+    }
+    if let Some(err) = config_error {
+        notifications.error(format!("Invalid config, using the defaults: {err}"));
+    }
 
     println!("Initializing Raylib");
 
@@ -115,27 +181,156 @@ fn main() {
             0x0, 0x3ffc0000, 0x20042004, 0x20002000, 0x20202000, 0x3ff82030, 0x00200030, 0x0,
         ],
     );
+    // register ICON_EQUALIZER
+    load_custom_icon(
+        225,
+        [
+            0x0, 0x08880888, 0x09c809c8, 0x1c880888, 0x089c1c88, 0x0888089c, 0x08880888, 0x0,
+        ],
+    );
+    // register ICON_SLEEP
+    load_custom_icon(
+        226,
+        [
+            0x0, 0x003801e0, 0x400c701c, 0x700e200e, 0x001e000e, 0x38fc203c, 0x07e01ff8, 0x0,
+        ],
+    );
+    // register ICON_QUEUE
+    load_custom_icon(
+        227,
+        [
+            0x0, 0x3fec3fec, 0x3fec0000, 0x00003fec, 0x13ec03ec, 0x3f803000, 0x10003000, 0x0,
+        ],
+    );
+    // register ICON_NO_SHUFFLE
+    load_custom_icon(
+        228,
+        [
+            0x0, 0x04000000, 0x1ffc0c00, 0x04000c00, 0x04000000, 0x1ffc0c00, 0x04000c00, 0x0,
+        ],
+    );
+    // register ICON_SMART_SHUFFLE
+    load_custom_icon(
+        229,
+        [
+            0x00040000, 0x301f1004, 0x31047f04, 0x01001100, 0x00400080, 0x30201020, 0x30003fff,
+            0x00001000,
+        ],
+    );
+    // register ICON_HISTORY
+    load_custom_icon(
+        230,
+        [
+            0x0, 0x081007e0, 0x20841088, 0x20842084, 0x20042784, 0x10082004, 0x07e00810, 0x0,
+        ],
+    );
 
-    let (mut rl, thread) = raylib::init()
-        .width(350)
-        .height(500)
-        .title("MP3 Player")
-        .undecorated()
-        .build();
-    rl.set_target_fps(60);
+    let mut builder = raylib::init();
+    builder
+        .width(config.window_width)
+        .height(config.window_height)
+        .title("MP3 Player");
+    if config.window_undecorated {
+        builder.undecorated();
+    }
+    let (mut rl, thread) = builder.build();
+    rl.set_target_fps(config.fps);
     rl.set_exit_key(None);
 
-    let mut audio = RaylibAudio::init_audio_device();
+    let mut audio = RaylibBackend::init();
 
-    let mut playlist: Playlist = Default::default();
+    let mut playlist: Playlist<RaylibBackend> = Default::default();
+    playlist.library = config.library.clone();
+    playlist.crossfade = config.crossfade;
+    playlist.replay_gain = config.replay_gain;
+    let equalizer_path = session::equalizer_path();
+    if let Some(path) = equalizer_path.as_deref().filter(|path| path.exists()) {
+        match EqualizerSettings::load(path) {
+            Ok(equalizer) => playlist.equalizer = equalizer,
+            Err(err) => {
+                notifications.error(format!("Failed to load the equalizer settings: {err}"))
+            }
+        }
+    }
+    let resume_path = session::resume_path();
+    if let Some(path) = resume_path.as_deref().filter(|path| path.exists()) {
+        match ResumePositions::load(path) {
+            Ok(resume) => playlist.resume = resume,
+            Err(err) => notifications.error(format!("Failed to load the resume positions: {err}")),
+        }
+    }
+    playlist.resume.min_length = config.resume_min_length * 60.0;
+    let plays_path = session::plays_path();
+    if let Some(path) = plays_path.as_deref().filter(|path| path.exists()) {
+        match PlayStats::load(path) {
+            Ok(plays) => playlist.plays = plays,
+            Err(err) => notifications.error(format!("Failed to load the play counts: {err}")),
+        }
+    }
+    playlist.shuffle_favorites = config.smart_shuffle_favorites;
 
     playlist.clear(&mut audio);
     // load_dir_recursively_mut_vec(&musicdir, &mut playlist);
-    playlist.play_ignore_err(0, &thread, &mut audio, rl.get_screen_height());
+    let session_path = Session::path();
+    if let Some(session) = session_path.as_deref().and_then(|path| Session::load(path).ok()) {
+        // paths on the command line replace the songs of the last session, not its settings
+        if args.paths.is_empty() {
+            session.restore(&mut playlist, &mut audio, rl.get_screen_height());
+        } else {
+            session.restore_settings(&mut playlist, &mut audio);
+        }
+    }
+    args.apply(&mut playlist, &mut audio, rl.get_screen_height());
+    let mut last_session_save = Instant::now();
+
+    let mut ipc_server = match IpcServer::bind(&ipc::socket_path()) {
+        Ok(server) => Some(server),
+        Err(err) => {
+            notifications.error(format!("Remote control is disabled: {err}"));
+            None
+        }
+    };
+    let mut mpd_server = None;
+    if config.mpd_enabled {
+        let address = SocketAddr::new(config.mpd_address, config.mpd_port);
+        match MpdServer::bind(address, &musicdir) {
+            Ok(server) => mpd_server = Some(server),
+            Err(err) => notifications.error(format!("Failed to start the MPD server on {address}: {err}")),
+        }
+    }
+    #[cfg(feature = "mpris")]
+    let mut mpris_server = match mpris::MprisServer::connect() {
+        Ok(server) => Some(server),
+        Err(err) => {
+            // not having a session bus is normal outside of desktop sessions
+            notifications.info(format!("MPRIS is disabled: {err}"));
+            None
+        }
+    };
+
+    let mut scanner = config.replay_gain_scan.then(|| {
+        LoudnessScanner::spawn(
+            RaylibBackend::decode,
+            dirs::cache_home().map(|dir| dir.join("mp3-player").join("loudness")),
+            config.replay_gain_scan_write_tags,
+        )
+    });
+    let mut silence_scanner = config.skip_silence.then(|| {
+        SilenceScanner::spawn(
+            RaylibBackend::decode,
+            config.silence_threshold,
+            dirs::cache_home().map(|dir| dir.join("mp3-player").join("silence")),
+        )
+    });
 
     let mut state_maingui: MainGuiState = Default::default();
     let mut state_lyricsgui: LyricsGuiState = Default::default();
-    let mut state_filegui: FileGuiState = FileGuiState::default(&musicdir, GuiScreen::Player)
+    let mut state_loggui: LogGuiState = Default::default();
+    let mut state_equalizergui: EqualizerGuiState = Default::default();
+    let mut state_queuegui: QueueGuiState = Default::default();
+    let mut state_historygui: HistoryGuiState = Default::default();
+    let mut state_stereogui: StereoGuiState = Default::default();
+    let mut state_filegui: FileGuiState = FileGuiState::default(&musicdir, GuiScreen::Player, &config.library)
         .expect("Failed to initialise the file gui");
     let mut cur_screen: GuiScreen = GuiScreen::Player;
 
@@ -151,8 +346,40 @@ fn main() {
                 &thread,
                 &mut rl,
                 &mut state_maingui,
+                &mut notifications,
+                &config,
+                scanner.as_ref().and_then(LoudnessScanner::progress),
             ),
             GuiScreen::Lyrics => render_lyrics_gui(&mut playlist, &thread, &mut rl, &mut state_lyricsgui),
+            GuiScreen::Log => render_log_gui(&mut notifications, &thread, &mut rl, &mut state_loggui),
+            GuiScreen::Equalizer => render_equalizer_gui(
+                &mut playlist,
+                &mut audio,
+                &thread,
+                &mut rl,
+                &mut state_equalizergui,
+            ),
+            GuiScreen::Stereo => render_stereo_gui(
+                &mut playlist,
+                &mut audio,
+                &thread,
+                &mut rl,
+                &mut state_stereogui,
+            ),
+            GuiScreen::Queue => render_queue_gui(
+                &mut playlist,
+                &mut audio,
+                &thread,
+                &mut rl,
+                &mut state_queuegui,
+            ),
+            GuiScreen::History => render_history_gui(
+                &mut playlist,
+                &mut audio,
+                &thread,
+                &mut rl,
+                &mut state_historygui,
+            ),
             GuiScreen::FileSelectAddFolder
             | GuiScreen::FileSelectAddFile
             | GuiScreen::FileSelectOpenFolder
@@ -164,19 +391,36 @@ fn main() {
                 cur_screen,
                 &thread,
                 &mut audio,
+                &mut notifications,
             ),
         };
 
         match action {
             Action::None => {}
             Action::ExitProgram => break,
-            Action::SwitchGuiScreen(screen @ (GuiScreen::Player | GuiScreen::Lyrics)) => {
+            Action::SwitchGuiScreen(
+                screen @ (GuiScreen::Player
+                | GuiScreen::Lyrics
+                | GuiScreen::Log
+                | GuiScreen::Equalizer
+                | GuiScreen::Stereo
+                | GuiScreen::Queue
+                | GuiScreen::History),
+            ) => {
+                if cur_screen == GuiScreen::Equalizer {
+                    save_equalizer(equalizer_path.as_deref(), &playlist.equalizer);
+                }
                 state_maingui = Default::default();
                 state_lyricsgui = Default::default();
+                state_loggui = Default::default();
+                state_equalizergui = Default::default();
+                state_queuegui = Default::default();
+                state_historygui = Default::default();
+                state_stereogui = Default::default();
                 cur_screen = screen;
             }
             Action::SwitchGuiScreen(screen) => {
-                if let Ok(state) = FileGuiState::default(&musicdir, screen) {
+                if let Ok(state) = FileGuiState::default(&musicdir, screen, &config.library) {
                     state_filegui = state;
                     cur_screen = screen;
                     state_maingui = Default::default();
@@ -186,39 +430,88 @@ fn main() {
             }
         }
 
-        gui_main::update_music(
-            &mut audio,
-            &mut playlist,
-            &thread,
-            &mut rl,
-            &mut state_maingui,
-        );
+        if let Some(server) = &mut ipc_server {
+            server.poll(&mut playlist, &mut audio, rl.get_screen_height());
+        }
+        if let Some(server) = &mut mpd_server {
+            server.poll(&mut playlist, &mut audio, rl.get_screen_height());
+        }
+        #[cfg(feature = "mpris")]
+        if let Some(server) = &mut mpris_server {
+            server.poll(&mut playlist, &mut audio, rl.get_screen_height());
+        }
+        gui_main::update_music(&mut audio, &mut playlist, &mut rl, &mut state_maingui);
+        for err in playlist.take_errors() {
+            notifications.error(err.to_string());
+        }
+        if let Some(scanner) = &mut scanner {
+            for err in scanner.update(&mut playlist) {
+                notifications.error(err);
+            }
+        }
+        if let Some(scanner) = &mut silence_scanner {
+            for err in scanner.update(&mut playlist) {
+                notifications.error(err);
+            }
+        }
+
+        if last_session_save.elapsed() >= SESSION_SAVE_INTERVAL {
+            save_session(session_path.as_deref(), &playlist, &audio);
+            save_resume_positions(resume_path.as_deref(), &mut playlist, &audio);
+            save_play_stats(plays_path.as_deref(), &playlist.plays);
+            last_session_save = Instant::now();
+        }
+    }
+
+    save_session(session_path.as_deref(), &playlist, &audio);
+    save_equalizer(equalizer_path.as_deref(), &playlist.equalizer);
+    save_resume_positions(resume_path.as_deref(), &mut playlist, &audio);
+    save_play_stats(plays_path.as_deref(), &playlist.plays);
+}
+
+fn save_session(
+    path: Option<&Path>,
+    playlist: &Playlist<RaylibBackend>,
+    audio: &RaylibBackend,
+) {
+    let Some(path) = path else {
+        return;
+    };
+    if let Err(err) = Session::from_playlist(playlist, audio).save(path) {
+        eprintln!("Failed to save the session to {}: {err}", path.display());
+    }
+}
+
+fn save_equalizer(path: Option<&Path>, equalizer: &EqualizerSettings) {
+    let Some(path) = path else {
+        return;
+    };
+    if let Err(err) = equalizer.save(path) {
+        eprintln!("Failed to save the equalizer settings to {}: {err}", path.display());
     }
 }
 
-fn get_home_directory() -> Option<PathBuf> {
-    let mut path = PathBuf::new();
-    if cfg!(target_os = "windows") {
-        // %HomeDrive%%HomePath% aka $HomeDrive$HomePath
-        path.push(std::env::var("HomeDrive").ok()?);
-        path.push(std::env::var("HomePath").ok()?);
-        panic!("FUCK THIS BS")
-    } else if cfg!(any(
-        target_os = "linux",
-        target_os = "macos",
-        target_os = "freebsd",
-        target_os = "dragonfly",
-        target_os = "openbsd",
-        target_os = "netbsd"
-    )) {
-        // $HOME
-        path.push(std::env::var("HOME").ok()?);
-    } else if cfg!(target_os = "none") {
-        panic!("Embedded architecture isnt supported yet :c");
-    } else {
-        panic!("Unsupported target_os cfg: {}", std::env::consts::OS);
-    }
-    Some(path)
+pub fn save_resume_positions<B: AudioBackend>(
+    path: Option<&Path>,
+    playlist: &mut Playlist<B>,
+    audio: &B,
+) {
+    let Some(path) = path else {
+        return;
+    };
+    playlist.remember_position(audio);
+    if let Err(err) = playlist.resume.save(path) {
+        eprintln!("Failed to save the resume positions to {}: {err}", path.display());
+    }
+}
+
+pub fn save_play_stats(path: Option<&Path>, plays: &PlayStats) {
+    let Some(path) = path else {
+        return;
+    };
+    if let Err(err) = plays.save(path) {
+        eprintln!("Failed to save the play counts to {}: {err}", path.display());
+    }
 }
 
 fn load_custom_icon(id: u8, icon: [u32; 8]) {
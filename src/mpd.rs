@@ -0,0 +1,969 @@
+use std::{
+    fmt::Write as _,
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    time::Instant,
+};
+
+use mp3_player::{
+    audio::AudioBackend,
+    replaygain::ReplayGainMode,
+    song::{Playlist, RepeatBehavior, ShuffleBehavior},
+};
+
+use crate::remote::{Command, Relative};
+
+/// The protocol version we claim to speak. Clients use it to decide which commands they can
+/// send, so it shouldn't be newer than what is implemented.
+const GREETING: &[u8] = b"OK MPD 0.21.0\n";
+/// Clients that send more than this without a newline get disconnected.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+/// Clients whose command list gets longer than this get disconnected, like MPD's default
+/// `max_command_list_size`.
+const MAX_COMMAND_LIST_SIZE: usize = 2048 * 1024;
+
+// error codes from MPD's src/protocol/Ack.hxx
+const ACK_ERROR_ARG: u32 = 2;
+const ACK_ERROR_PASSWORD: u32 = 3;
+const ACK_ERROR_PERMISSION: u32 = 4;
+const ACK_ERROR_UNKNOWN: u32 = 5;
+const ACK_ERROR_NO_EXIST: u32 = 50;
+
+// idle subsystems
+const PLAYER: u8 = 1 << 0;
+const MIXER: u8 = 1 << 1;
+const OPTIONS: u8 = 1 << 2;
+const PLAYLIST: u8 = 1 << 3;
+const SUBSYSTEMS: &[(&str, u8)] = &[
+    ("player", PLAYER),
+    ("mixer", MIXER),
+    ("options", OPTIONS),
+    ("playlist", PLAYLIST),
+];
+
+const COMMANDS: &[&str] = &[
+    "add",
+    "addid",
+    "clear",
+    "close",
+    "command_list_begin",
+    "command_list_end",
+    "command_list_ok_begin",
+    "commands",
+    "consume",
+    "crossfade",
+    "currentsong",
+    "delete",
+    "deleteid",
+    "getvol",
+    "idle",
+    "next",
+    "noidle",
+    "notcommands",
+    "outputs",
+    "password",
+    "pause",
+    "ping",
+    "play",
+    "playid",
+    "playlistid",
+    "playlistinfo",
+    "plchanges",
+    "plchangesposid",
+    "previous",
+    "random",
+    "repeat",
+    "replay_gain_mode",
+    "replay_gain_status",
+    "seek",
+    "seekcur",
+    "seekid",
+    "setvol",
+    "single",
+    "stats",
+    "status",
+    "stop",
+    "tagtypes",
+    "volume",
+];
+
+struct Ack {
+    code: u32,
+    message: String,
+}
+
+impl Ack {
+    fn new<S: Into<String>>(code: u32, message: S) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn arg<S: Into<String>>(message: S) -> Self {
+        Self::new(ACK_ERROR_ARG, message)
+    }
+}
+
+enum Response {
+    Ok,
+    Idle(u8),
+    Close,
+}
+
+/// What the idle subsystems are derived from.
+#[derive(Clone, PartialEq)]
+struct Snapshot {
+    state: &'static str,
+    song: Option<usize>,
+    position: f32,
+    volume: u32,
+    repeat_behavior: RepeatBehavior,
+    shuffle_behavior: ShuffleBehavior,
+    crossfade: f32,
+    replay_gain_mode: ReplayGainMode,
+    playlist_version: u64,
+}
+
+impl Snapshot {
+    fn new<B: AudioBackend>(playlist: &Playlist<B>, audio: &B) -> Self {
+        Self {
+            state: player_state(playlist, audio),
+            song: playlist.currently_playing_id(),
+            position: playlist.music_length_played(audio),
+            volume: (audio.master_volume() * 100.0).round() as u32,
+            repeat_behavior: playlist.repeat_behavior,
+            shuffle_behavior: playlist.shuffle_behavior(),
+            crossfade: playlist.crossfade,
+            replay_gain_mode: playlist.replay_gain.mode,
+            playlist_version: playlist.version(),
+        }
+    }
+
+    /// The subsystems that changed between `self` and `new`, `elapsed` seconds later.
+    fn changes(&self, new: &Self, elapsed: f32) -> u8 {
+        let mut changes = 0;
+        let mut expected_position = self.position;
+        if self.state == "play" {
+            expected_position += elapsed;
+        }
+        if self.state != new.state
+            || self.song != new.song
+            || (new.position - expected_position).abs() > 1.0
+        {
+            changes |= PLAYER;
+        }
+        if self.volume != new.volume {
+            changes |= MIXER;
+        }
+        if self.repeat_behavior != new.repeat_behavior
+            || self.shuffle_behavior != new.shuffle_behavior
+            || self.crossfade != new.crossfade
+            || self.replay_gain_mode != new.replay_gain_mode
+        {
+            changes |= OPTIONS;
+        }
+        if self.playlist_version != new.playlist_version {
+            changes |= PLAYLIST;
+        }
+        changes
+    }
+}
+
+fn player_state<B: AudioBackend>(playlist: &Playlist<B>, audio: &B) -> &'static str {
+    if !playlist.has_music_stream() {
+        "stop"
+    } else if playlist.is_music_playing(audio) {
+        "play"
+    } else {
+        "pause"
+    }
+}
+
+/// Splits a command line into words. Arguments can be quoted with double quotes, inside of which
+/// backslashes escape the next character.
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+        let Some(&c) = chars.peek() else {
+            return Ok(tokens);
+        };
+
+        let mut token = String::new();
+        if c == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => token.push(chars.next().ok_or("missing closing '\"'")?),
+                    Some(c) => token.push(c),
+                    None => return Err("missing closing '\"'".to_string()),
+                }
+            }
+            if chars.peek().is_some_and(|c| !c.is_ascii_whitespace()) {
+                return Err("space expected after closing '\"'".to_string());
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_ascii_whitespace()) {
+                token.push(c);
+            }
+        }
+        tokens.push(token);
+    }
+}
+
+fn parse_bool(arg: Option<&str>) -> Result<bool, Ack> {
+    match arg {
+        Some("0") => Ok(false),
+        Some("1") => Ok(true),
+        Some(arg) => Err(Ack::arg(format!("Boolean (0/1) expected: {arg}"))),
+        None => Err(Ack::arg("too few arguments")),
+    }
+}
+
+fn parse_position(arg: Option<&str>) -> Result<usize, Ack> {
+    let arg = arg.ok_or_else(|| Ack::arg("too few arguments"))?;
+    arg.parse()
+        .map_err(|_| Ack::arg(format!("Integer expected: {arg}")))
+}
+
+/// `start:end`, `start:` or a single position.
+fn parse_range(arg: &str, len: usize) -> Result<(usize, usize), Ack> {
+    let invalid = || Ack::arg(format!("Bad range: {arg}"));
+    let range = match arg.split_once(':') {
+        Some((start, "")) => (start.parse().map_err(|_| invalid())?, len),
+        Some((start, end)) => (
+            start.parse().map_err(|_| invalid())?,
+            end.parse().map_err(|_| invalid())?,
+        ),
+        None => {
+            let pos: usize = arg.parse().map_err(|_| invalid())?;
+            (pos, pos + 1)
+        }
+    };
+    if range.0 > range.1 || range.1 > len {
+        return Err(Ack::arg("Bad song index"));
+    }
+    Ok(range)
+}
+
+/// The file `uri` names inside `music_dir`. Like MPD does for remote clients, absolute paths and
+/// `..` going above the music folder are refused, anyone who can connect could read any file
+/// otherwise.
+fn music_path(music_dir: &Path, uri: &str) -> Option<PathBuf> {
+    let mut path = music_dir.to_path_buf();
+    let mut depth = 0;
+    for component in Path::new(uri).components() {
+        match component {
+            Component::Normal(part) => {
+                path.push(part);
+                depth += 1;
+            }
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => {
+                path.pop();
+                depth -= 1;
+            }
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(path)
+}
+
+struct CommandList {
+    /// `command_list_ok_begin` answers every command with `list_OK`
+    ok: bool,
+    lines: Vec<String>,
+    /// bytes of all the lines
+    size: usize,
+}
+
+struct Client {
+    stream: TcpStream,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    command_list: Option<CommandList>,
+    /// the subsystems the client waits for, while it is in `idle`
+    idle: Option<u8>,
+    /// subsystems that changed since the client last got told about them
+    pending: u8,
+    closed: bool,
+}
+
+/// A subset of the MPD protocol (<https://mpd.readthedocs.io/en/latest/protocol.html>), enough
+/// for the usual clients to show and control what is playing.
+///
+/// There is no database, `add` only takes paths relative to the music folder, see `music_path`.
+/// Song ids are the same as positions.
+pub struct MpdServer {
+    listener: TcpListener,
+    clients: Vec<Client>,
+    music_dir: PathBuf,
+    last_snapshot: Option<Snapshot>,
+    last_poll: Instant,
+    started: Instant,
+}
+
+impl MpdServer {
+    pub fn bind(address: SocketAddr, music_dir: &Path) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            clients: Vec::new(),
+            music_dir: music_dir.to_path_buf(),
+            last_snapshot: None,
+            last_poll: Instant::now(),
+            started: Instant::now(),
+        })
+    }
+
+    /// Accepts new clients, runs their commands and answers idling clients. Never blocks, has to
+    /// be called once a frame.
+    pub fn poll<B: AudioBackend>(
+        &mut self,
+        playlist: &mut Playlist<B>,
+        audio: &mut B,
+        screen_height: i32,
+    ) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if stream.set_nonblocking(true).is_ok() {
+                        self.clients.push(Client {
+                            stream,
+                            input: Vec::new(),
+                            output: GREETING.to_vec(),
+                            command_list: None,
+                            idle: None,
+                            pending: 0,
+                            closed: false,
+                        });
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    eprintln!("Failed to accept an MPD connection: {err}");
+                    break;
+                }
+            }
+        }
+
+        let mut ctx = Context {
+            playlist,
+            audio,
+            screen_height,
+            music_dir: &self.music_dir,
+            started: self.started,
+        };
+        for client in &mut self.clients {
+            client.read(&mut ctx);
+        }
+
+        let snapshot = Snapshot::new(playlist, audio);
+        let changes = match &self.last_snapshot {
+            Some(last) => last.changes(&snapshot, self.last_poll.elapsed().as_secs_f32()),
+            None => 0,
+        };
+        self.last_snapshot = Some(snapshot);
+        self.last_poll = Instant::now();
+
+        for client in &mut self.clients {
+            client.pending |= changes;
+            client.answer_idle(false);
+            client.flush();
+        }
+        self.clients.retain(|client| !client.closed);
+    }
+}
+
+/// Everything a command can touch.
+struct Context<'a, B: AudioBackend> {
+    playlist: &'a mut Playlist<B>,
+    audio: &'a mut B,
+    screen_height: i32,
+    music_dir: &'a Path,
+    started: Instant,
+}
+
+impl<B: AudioBackend> Context<'_, B> {
+    fn run(&mut self, command: Command) -> Result<(), Ack> {
+        command
+            .execute(self.playlist, self.audio, self.screen_height)
+            .map_err(|err| Ack::new(ACK_ERROR_ARG, err))
+    }
+
+    fn check_position(&self, pos: usize) -> Result<(), Ack> {
+        if pos >= self.playlist.len() {
+            return Err(Ack::arg("Bad song index"));
+        }
+        Ok(())
+    }
+
+    /// Paths inside the music folder are shown relative to it, like MPD does.
+    fn uri(&self, path: &Path) -> String {
+        path.strip_prefix(self.music_dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn write_song(&self, out: &mut String, pos: usize) {
+        let song = &self.playlist.get_songs()[pos];
+        _ = writeln!(out, "file: {}", self.uri(song.path()));
+        let author = song.author().to_string_lossy();
+        if !author.is_empty() {
+            _ = writeln!(out, "Artist: {author}");
+        }
+        _ = writeln!(out, "Title: {}", song.file_name().to_string_lossy());
+        // the length is only known for the song that is loaded
+        if self.playlist.currently_playing_id() == Some(pos) {
+            let duration = self.playlist.music_length_total(self.audio);
+            _ = writeln!(out, "Time: {}", duration.round() as u64);
+            _ = writeln!(out, "duration: {duration:.3}");
+        }
+        _ = writeln!(out, "Pos: {pos}\nId: {pos}");
+    }
+
+    fn write_status(&self, out: &mut String) {
+        let repeat_behavior = self.playlist.repeat_behavior;
+        _ = writeln!(
+            out,
+            "volume: {}",
+            (self.audio.master_volume() * 100.0).round() as u32
+        );
+        _ = writeln!(
+            out,
+            "repeat: {}",
+            (repeat_behavior != RepeatBehavior::Normal) as u8
+        );
+        _ = writeln!(
+            out,
+            "random: {}",
+            (self.playlist.shuffle_behavior() != ShuffleBehavior::Normal) as u8
+        );
+        _ = writeln!(
+            out,
+            "single: {}",
+            (repeat_behavior == RepeatBehavior::RepeatSingle) as u8
+        );
+        _ = writeln!(out, "consume: 0");
+        if self.playlist.crossfade > 0.0 {
+            _ = writeln!(out, "xfade: {}", self.playlist.crossfade.round() as u32);
+        }
+        _ = writeln!(out, "playlist: {}", self.playlist.version());
+        _ = writeln!(out, "playlistlength: {}", self.playlist.len());
+        _ = writeln!(out, "state: {}", player_state(self.playlist, self.audio));
+        if let Some(pos) = self.playlist.currently_playing_id() {
+            let elapsed = self.playlist.music_length_played(self.audio);
+            let duration = self.playlist.music_length_total(self.audio);
+            _ = writeln!(out, "song: {pos}\nsongid: {pos}");
+            _ = writeln!(out, "time: {}:{}", elapsed as u64, duration.round() as u64);
+            _ = writeln!(out, "elapsed: {elapsed:.3}\nduration: {duration:.3}");
+        }
+    }
+
+    fn execute(&mut self, args: &[String], out: &mut String) -> Result<Response, Ack> {
+        let name = args[0].as_str();
+        let arg = |i: usize| args.get(i).map(String::as_str);
+
+        match name {
+            "ping" => {}
+            "close" => return Ok(Response::Close),
+            "idle" => {
+                let mut subsystems = 0;
+                for arg in &args[1..] {
+                    // subsystems we never report are accepted too, the client just never hears
+                    // about them
+                    subsystems |= SUBSYSTEMS
+                        .iter()
+                        .find(|(name, _)| name == arg)
+                        .map_or(0, |(_, bit)| *bit);
+                }
+                if args.len() == 1 {
+                    subsystems = u8::MAX;
+                }
+                return Ok(Response::Idle(subsystems));
+            }
+            // only meaningful while idling, which is handled before getting here
+            "noidle" => {}
+            "status" => self.write_status(out),
+            "stats" => {
+                _ = writeln!(out, "songs: {}", self.playlist.len());
+                _ = writeln!(out, "uptime: {}", self.started.elapsed().as_secs());
+            }
+            "currentsong" => {
+                if let Some(pos) = self.playlist.currently_playing_id() {
+                    self.write_song(out, pos);
+                }
+            }
+            "playlistinfo" | "playlistid" => {
+                let (start, end) = match arg(1) {
+                    Some(arg) => parse_range(arg, self.playlist.len())?,
+                    None => (0, self.playlist.len()),
+                };
+                for pos in start..end {
+                    self.write_song(out, pos);
+                }
+            }
+            // there's no history of changes, so everything counts as changed
+            "plchanges" | "plchangesposid" => {
+                let version: u64 = arg(1)
+                    .and_then(|arg| arg.parse().ok())
+                    .ok_or_else(|| Ack::arg("Integer expected"))?;
+                if version != self.playlist.version() {
+                    for pos in 0..self.playlist.len() {
+                        if name == "plchanges" {
+                            self.write_song(out, pos);
+                        } else {
+                            _ = writeln!(out, "cpos: {pos}\nId: {pos}");
+                        }
+                    }
+                }
+            }
+            "play" | "playid" => match arg(1) {
+                Some(pos) => {
+                    let pos = parse_position(Some(pos))?;
+                    self.check_position(pos)?;
+                    self.run(Command::Play(Some(pos)))?;
+                }
+                None => self.run(Command::Play(None))?,
+            },
+            "pause" => match arg(1) {
+                Some(pause) => {
+                    if parse_bool(Some(pause))? {
+                        self.run(Command::Pause)?
+                    } else {
+                        self.run(Command::Play(None))?
+                    }
+                }
+                None => self.run(Command::Toggle)?,
+            },
+            "stop" => self.run(Command::Stop)?,
+            "next" => self.run(Command::Next)?,
+            "previous" => self.run(Command::Prev)?,
+            "seekcur" => {
+                let time = arg(1).ok_or_else(|| Ack::arg("too few arguments"))?;
+                let time = Relative::parse(time)
+                    .ok_or_else(|| Ack::arg(format!("Float expected: {time}")))?;
+                self.run(Command::Seek(time))?;
+            }
+            "seek" | "seekid" => {
+                let pos = parse_position(arg(1))?;
+                self.check_position(pos)?;
+                let time: f32 = arg(2)
+                    .and_then(|arg| arg.parse().ok())
+                    .ok_or_else(|| Ack::arg("Float expected"))?;
+                if self.playlist.currently_playing_id() != Some(pos) {
+                    self.run(Command::Play(Some(pos)))?;
+                }
+                self.run(Command::Seek(Relative::To(time)))?;
+            }
+            "setvol" => {
+                let volume = arg(1)
+                    .and_then(|arg| arg.parse::<u32>().ok())
+                    .filter(|volume| *volume <= 100)
+                    .ok_or_else(|| Ack::arg("Invalid volume value"))?;
+                self.run(Command::Volume(Relative::To(volume as f32 / 100.0)))?;
+            }
+            "volume" => {
+                let change = arg(1)
+                    .and_then(|arg| arg.parse::<i32>().ok())
+                    .ok_or_else(|| Ack::arg("Integer expected"))?;
+                self.run(Command::Volume(Relative::By(change as f32 / 100.0)))?;
+            }
+            "getvol" => {
+                _ = writeln!(
+                    out,
+                    "volume: {}",
+                    (self.audio.master_volume() * 100.0).round() as u32
+                );
+            }
+            "add" | "addid" => {
+                let uri = arg(1).ok_or_else(|| Ack::arg("too few arguments"))?;
+                let path = music_path(self.music_dir, uri)
+                    .ok_or_else(|| Ack::new(ACK_ERROR_PERMISSION, "Access denied"))?;
+                let len = self.playlist.len();
+                self.playlist
+                    .add_song_by_path(&path)
+                    .map_err(|err| Ack::new(ACK_ERROR_NO_EXIST, format!("{uri}: {err}")))?;
+                if self.playlist.len() == len {
+                    return Err(Ack::new(ACK_ERROR_NO_EXIST, "No such song"));
+                }
+                if name == "addid" {
+                    _ = writeln!(out, "Id: {len}");
+                }
+            }
+            "delete" | "deleteid" => {
+                let arg = arg(1).ok_or_else(|| Ack::arg("too few arguments"))?;
+                let (start, end) = parse_range(arg, self.playlist.len())?;
+                if start == end {
+                    return Err(Ack::arg("Bad song index"));
+                }
+                // from the back, so the positions stay valid
+                for pos in (start..end).rev() {
+                    self.run(Command::Remove(pos))?;
+                }
+            }
+            "clear" => self.run(Command::Clear)?,
+            "repeat" => {
+                let repeat = parse_bool(arg(1))?;
+                let repeat_behavior = match (repeat, self.playlist.repeat_behavior) {
+                    (false, _) => RepeatBehavior::Normal,
+                    (true, RepeatBehavior::Normal) => RepeatBehavior::Repeat,
+                    (true, repeat_behavior) => repeat_behavior,
+                };
+                self.run(Command::Repeat(repeat_behavior))?;
+            }
+            "single" => {
+                let single = arg(1);
+                let repeat_behavior = match (single, self.playlist.repeat_behavior) {
+                    (Some("oneshot"), _) => return Err(Ack::arg("oneshot is not supported")),
+                    _ if parse_bool(single)? => RepeatBehavior::RepeatSingle,
+                    (_, RepeatBehavior::RepeatSingle) => RepeatBehavior::Repeat,
+                    (_, repeat_behavior) => repeat_behavior,
+                };
+                self.run(Command::Repeat(repeat_behavior))?;
+            }
+            "random" => {
+                let shuffle_behavior = match parse_bool(arg(1))? {
+                    // stays smart
+                    true if self.playlist.shuffle_behavior() != ShuffleBehavior::Normal => {
+                        self.playlist.shuffle_behavior()
+                    }
+                    true => ShuffleBehavior::Shuffle,
+                    false => ShuffleBehavior::Normal,
+                };
+                self.run(Command::Shuffle(Some(shuffle_behavior)))?;
+            }
+            "consume" => {
+                if parse_bool(arg(1))? {
+                    return Err(Ack::arg("consume is not supported"));
+                }
+            }
+            // only applies to songs loaded afterwards, like in mpd
+            "replay_gain_mode" => {
+                let mode = arg(1).ok_or_else(|| Ack::arg("too few arguments"))?;
+                self.playlist.replay_gain.mode = ReplayGainMode::from_name(mode)
+                    .ok_or_else(|| Ack::arg("Unrecognized replay gain mode"))?;
+            }
+            "replay_gain_status" => {
+                _ = writeln!(out, "replay_gain_mode: {}", self.playlist.replay_gain.mode.name());
+            }
+            "crossfade" => {
+                let seconds = arg(1)
+                    .and_then(|v| v.parse::<u32>().ok())
+                    .ok_or_else(|| Ack::arg("need an integer"))?;
+                self.run(Command::Crossfade(seconds.min(12) as f32))?;
+            }
+            "password" => return Err(Ack::new(ACK_ERROR_PASSWORD, "incorrect password")),
+            "commands" => {
+                for command in COMMANDS {
+                    _ = writeln!(out, "command: {command}");
+                }
+            }
+            "notcommands" => {}
+            "tagtypes" => {
+                // "tagtypes clear" etc. are accepted, but the tags are always the same
+                if args.len() == 1 {
+                    _ = writeln!(out, "tagtype: Artist\ntagtype: Title");
+                }
+            }
+            "outputs" => {
+                _ = writeln!(out, "outputid: 0\noutputname: default\noutputenabled: 1");
+            }
+            _ => return Err(Ack::new(ACK_ERROR_UNKNOWN, format!("unknown command \"{name}\""))),
+        }
+
+        Ok(Response::Ok)
+    }
+}
+
+impl Client {
+    fn read<B: AudioBackend>(&mut self, ctx: &mut Context<B>) {
+        let mut chunk = [0u8; 4096];
+        let mut eof = false;
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    eof = true;
+                    break;
+                }
+                Ok(read) => self.input.extend_from_slice(&chunk[..read]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => {
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+
+        // removing each line from the front would move the rest every time
+        let mut start = 0;
+        while let Some(len) = self.input[start..].iter().position(|&b| b == b'\n') {
+            let line = String::from_utf8_lossy(&self.input[start..start + len]).into_owned();
+            start += len + 1;
+            self.handle_line(line.trim_end_matches('\r'), ctx);
+            if self.closed {
+                return;
+            }
+        }
+        self.input.drain(..start);
+        if eof || self.input.len() > MAX_LINE_LENGTH {
+            self.closed = true;
+        }
+    }
+
+    fn handle_line<B: AudioBackend>(&mut self, line: &str, ctx: &mut Context<B>) {
+        if self.idle.is_some() {
+            // the only thing a client may send while idling
+            if line == "noidle" {
+                self.answer_idle(true);
+            } else {
+                self.closed = true;
+            }
+            return;
+        }
+
+        if let Some(list) = &mut self.command_list {
+            if line != "command_list_end" {
+                list.size += line.len() + 1;
+                if list.size > MAX_COMMAND_LIST_SIZE {
+                    // nothing in it runs, MPD doesn't answer either
+                    self.closed = true;
+                    return;
+                }
+                list.lines.push(line.to_string());
+                return;
+            }
+            let list = self.command_list.take().unwrap_or(CommandList {
+                ok: false,
+                lines: vec![],
+                size: 0,
+            });
+            let mut out = String::new();
+            for (i, line) in list.lines.iter().enumerate() {
+                match self.run_line(line, i, ctx, &mut out) {
+                    Some(Response::Ok) if list.ok => out.push_str("list_OK\n"),
+                    Some(Response::Ok) => {}
+                    Some(Response::Close) => {
+                        self.closed = true;
+                        return;
+                    }
+                    Some(Response::Idle(_)) => {
+                        out.push_str(&format!(
+                            "ACK [{ACK_ERROR_ARG}@{i}] {{idle}} idle in a command list\n"
+                        ));
+                        self.output.extend_from_slice(out.as_bytes());
+                        return;
+                    }
+                    None => {
+                        self.output.extend_from_slice(out.as_bytes());
+                        return;
+                    }
+                }
+            }
+            out.push_str("OK\n");
+            self.output.extend_from_slice(out.as_bytes());
+            return;
+        }
+
+        match line {
+            "command_list_begin" | "command_list_ok_begin" => {
+                self.command_list = Some(CommandList {
+                    ok: line == "command_list_ok_begin",
+                    lines: vec![],
+                    size: 0,
+                });
+                return;
+            }
+            "" => return,
+            _ => {}
+        }
+
+        let mut out = String::new();
+        match self.run_line(line, 0, ctx, &mut out) {
+            Some(Response::Ok) => out.push_str("OK\n"),
+            Some(Response::Idle(subsystems)) => {
+                self.idle = Some(subsystems);
+                self.answer_idle(false);
+            }
+            Some(Response::Close) => self.closed = true,
+            None => {}
+        }
+        self.output.extend_from_slice(out.as_bytes());
+    }
+
+    /// Runs a single command and writes its output or ACK to `out`. Returns `None` on errors.
+    fn run_line<B: AudioBackend>(
+        &mut self,
+        line: &str,
+        list_index: usize,
+        ctx: &mut Context<B>,
+        out: &mut String,
+    ) -> Option<Response> {
+        let args = match tokenize(line) {
+            Ok(args) if !args.is_empty() => args,
+            Ok(_) => {
+                _ = writeln!(out, "ACK [{ACK_ERROR_UNKNOWN}@{list_index}] {{}} No command given");
+                return None;
+            }
+            Err(err) => {
+                _ = writeln!(out, "ACK [{ACK_ERROR_ARG}@{list_index}] {{}} {err}");
+                return None;
+            }
+        };
+        let mut output = String::new();
+        match ctx.execute(&args, &mut output) {
+            Ok(response) => {
+                out.push_str(&output);
+                Some(response)
+            }
+            Err(ack) => {
+                _ = writeln!(
+                    out,
+                    "ACK [{}@{list_index}] {{{}}} {}",
+                    ack.code,
+                    args[0],
+                    ack.message.replace('\n', " ")
+                );
+                None
+            }
+        }
+    }
+
+    /// Ends the idle with the subsystems the client waits for, if any of them changed (or in any
+    /// case with `force`, for `noidle`).
+    fn answer_idle(&mut self, force: bool) {
+        let Some(subsystems) = self.idle else {
+            return;
+        };
+        let changed = self.pending & subsystems;
+        if changed == 0 && !force {
+            return;
+        }
+        for (name, bit) in SUBSYSTEMS {
+            if changed & bit != 0 {
+                self.output
+                    .extend_from_slice(format!("changed: {name}\n").as_bytes());
+            }
+        }
+        self.output.extend_from_slice(b"OK\n");
+        self.pending &= !changed;
+        self.idle = None;
+    }
+
+    /// Writes as much of the output as the socket takes without blocking.
+    fn flush(&mut self) {
+        while !self.output.is_empty() {
+            match self.stream.write(&self.output) {
+                Ok(0) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(written) => {
+                    self.output.drain(..written);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => {
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, Shutdown},
+        thread,
+        time::Duration,
+    };
+
+    use mp3_player::audio_null::NullBackend;
+
+    use super::*;
+
+    /// Sends `input` to a new server, and returns what the server answered until it closed the
+    /// connection or stopped answering.
+    fn talk(input: Vec<u8>) -> (Vec<u8>, bool) {
+        let address = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+        let mut server = MpdServer::bind(address, Path::new("/music")).unwrap();
+        let mut stream = TcpStream::connect(server.listener.local_addr().unwrap()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        // the server only reads while polling, so this would block on big inputs
+        let sender = thread::spawn(move || {
+            _ = writer.write_all(&input);
+            _ = writer.shutdown(Shutdown::Write);
+        });
+
+        let mut playlist = Playlist::default();
+        let mut audio = NullBackend::new();
+        let mut output = vec![];
+        let mut closed = false;
+        for _ in 0..500 {
+            server.poll(&mut playlist, &mut audio, 0);
+            let mut chunk = [0; 4096];
+            match stream.read(&mut chunk) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(read) => output.extend_from_slice(&chunk[..read]),
+                Err(err) if err.kind() == ErrorKind::ConnectionReset => {
+                    closed = true;
+                    break;
+                }
+                Err(_) => {}
+            }
+        }
+        sender.join().unwrap();
+        (output, closed)
+    }
+
+    #[test]
+    fn runs_command_lists() {
+        let input = b"command_list_ok_begin\nping\nping\ncommand_list_end\n".to_vec();
+        let (output, _) = talk(input);
+        assert_eq!(output, b"OK MPD 0.21.0\nlist_OK\nlist_OK\nOK\n");
+    }
+
+    #[test]
+    fn long_command_lists_disconnect() {
+        let mut input = b"command_list_begin\n".to_vec();
+        while input.len() <= MAX_COMMAND_LIST_SIZE + 1024 {
+            input.extend_from_slice(b"ping\n");
+        }
+        input.extend_from_slice(b"command_list_end\n");
+        let (output, closed) = talk(input);
+        assert!(closed);
+        assert_eq!(output, GREETING);
+    }
+
+    #[test]
+    fn music_path_stays_in_the_music_dir() {
+        let dir = Path::new("/music");
+        assert_eq!(
+            music_path(dir, "artist/song.mp3"),
+            Some(PathBuf::from("/music/artist/song.mp3"))
+        );
+        assert_eq!(
+            music_path(dir, "./artist/../other/song.mp3"),
+            Some(PathBuf::from("/music/other/song.mp3"))
+        );
+        assert_eq!(music_path(dir, ""), Some(PathBuf::from("/music")));
+        assert_eq!(music_path(dir, "/etc/passwd"), None);
+        assert_eq!(music_path(dir, "../secret.mp3"), None);
+        assert_eq!(music_path(dir, "artist/../../secret.mp3"), None);
+    }
+}
@@ -0,0 +1,604 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use dbus::{
+    arg::{PropMap, RefArg, Variant},
+    blocking::{
+        stdintf::org_freedesktop_dbus::{PropertiesPropertiesChanged, RequestNameReply},
+        LocalConnection,
+    },
+    message::SignalArgs,
+    Message,
+};
+use dbus_crossroads::{Crossroads, IfaceBuilder};
+use mp3_player::{
+    audio::AudioBackend,
+    song::{Playlist, RepeatBehavior, ShuffleBehavior},
+    speed::{MAX_SPEED, MIN_SPEED},
+};
+
+use crate::remote::{c_vec_to_string, Command, Relative};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.mp3_player";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const ROOT_IFACE: &str = "org.mpris.MediaPlayer2";
+const PLAYER_IFACE: &str = "org.mpris.MediaPlayer2.Player";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+#[derive(Clone, PartialEq)]
+struct Track {
+    id: dbus::Path<'static>,
+    title: String,
+    artist: String,
+    url: String,
+    /// in microseconds, like every time in MPRIS
+    length: i64,
+}
+
+/// Everything the D-Bus side can read. It's a copy, since the handlers can't borrow the playlist.
+#[derive(Clone, PartialEq)]
+struct Status {
+    playback_status: &'static str,
+    loop_status: &'static str,
+    shuffle: bool,
+    volume: f64,
+    rate: f64,
+    position: i64,
+    track: Option<Track>,
+}
+
+/// The data behind the object path. Method calls and property writes only queue commands, which
+/// get run against the playlist after all messages were handled.
+struct State {
+    status: Status,
+    commands: Vec<Command>,
+}
+
+fn micros(seconds: f32) -> i64 {
+    (seconds as f64 * 1_000_000.0) as i64
+}
+
+fn seconds(micros: i64) -> f32 {
+    (micros as f64 / 1_000_000.0) as f32
+}
+
+fn loop_status(repeat_behavior: RepeatBehavior) -> &'static str {
+    match repeat_behavior {
+        RepeatBehavior::Normal => "None",
+        RepeatBehavior::Repeat => "Playlist",
+        RepeatBehavior::RepeatSingle => "Track",
+    }
+}
+
+/// `file://` url of `path`, with everything but unreserved characters and slashes percent-encoded.
+fn file_url(path: &Path) -> String {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut url = String::from("file://");
+    for &b in path.as_os_str().as_encoded_bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            url.push(b as char);
+        } else {
+            url.push_str(&format!("%{b:02X}"));
+        }
+    }
+    url
+}
+
+/// The reverse of [`file_url`], for OpenUri. Returns `None` for anything but `file://` urls.
+fn path_from_url(url: &str) -> Option<PathBuf> {
+    let encoded = url.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        let hex = encoded
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (encoded[i], hex) {
+            (b'%', Some(b)) => {
+                bytes.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                bytes.push(b);
+                i += 1;
+            }
+        }
+    }
+    Some(PathBuf::from(String::from_utf8(bytes).ok()?))
+}
+
+impl Status {
+    fn new<B: AudioBackend>(playlist: &Playlist<B>, audio: &B) -> Self {
+        let track = playlist.currently_playing_id().map(|idx| Track {
+            id: dbus::Path::from(format!("/org/mpris/MediaPlayer2/mp3_player/track/{idx}")),
+            title: playlist
+                .filename_vec()
+                .map(|vec| c_vec_to_string(vec))
+                .unwrap_or_default(),
+            artist: playlist
+                .author_vec()
+                .map(|vec| c_vec_to_string(vec))
+                .unwrap_or_default(),
+            url: playlist
+                .get_songs()
+                .get(idx)
+                .map(|song| file_url(song.path()))
+                .unwrap_or_default(),
+            length: micros(playlist.music_length_total(audio)),
+        });
+        let playback_status = if !playlist.has_music_stream() {
+            "Stopped"
+        } else if playlist.is_music_playing(audio) {
+            "Playing"
+        } else {
+            "Paused"
+        };
+
+        Self {
+            playback_status,
+            loop_status: loop_status(playlist.repeat_behavior),
+            shuffle: playlist.shuffle_behavior() != ShuffleBehavior::Normal,
+            volume: audio.master_volume() as f64,
+            rate: playlist.speed() as f64,
+            position: micros(playlist.music_length_played(audio)),
+            track,
+        }
+    }
+
+    fn metadata(&self) -> PropMap {
+        let mut metadata = PropMap::new();
+        let Some(track) = &self.track else {
+            metadata.insert(
+                "mpris:trackid".to_string(),
+                Variant(Box::new(dbus::Path::from(NO_TRACK)) as Box<dyn RefArg>),
+            );
+            return metadata;
+        };
+        metadata.insert("mpris:trackid".to_string(), Variant(Box::new(track.id.clone())));
+        metadata.insert("mpris:length".to_string(), Variant(Box::new(track.length)));
+        metadata.insert("xesam:title".to_string(), Variant(Box::new(track.title.clone())));
+        if !track.artist.is_empty() {
+            metadata.insert(
+                "xesam:artist".to_string(),
+                Variant(Box::new(vec![track.artist.clone()])),
+            );
+        }
+        metadata.insert("xesam:url".to_string(), Variant(Box::new(track.url.clone())));
+        metadata
+    }
+}
+
+fn register_root(b: &mut IfaceBuilder<State>) {
+    b.method("Raise", (), (), |_, _: &mut State, _: ()| Ok(()));
+    b.method("Quit", (), (), |_, _: &mut State, _: ()| Ok(()));
+    b.property("CanQuit").get(|_, _: &mut State| Ok(false));
+    b.property("CanRaise").get(|_, _: &mut State| Ok(false));
+    b.property("HasTrackList").get(|_, _: &mut State| Ok(false));
+    b.property("Identity").get(|_, _: &mut State| Ok("MP3 Player".to_string()));
+    b.property("SupportedUriSchemes")
+        .get(|_, _: &mut State| Ok(vec!["file".to_string()]));
+    b.property("SupportedMimeTypes").get(|_, _: &mut State| {
+        Ok(vec![
+            "audio/mpeg".to_string(),
+            "audio/ogg".to_string(),
+            "audio/flac".to_string(),
+            "audio/wav".to_string(),
+        ])
+    });
+}
+
+fn register_player(b: &mut IfaceBuilder<State>) {
+    let queue = |command: fn() -> Command| {
+        move |_: &mut dbus_crossroads::Context, state: &mut State, _: ()| {
+            state.commands.push(command());
+            Ok(())
+        }
+    };
+    b.method("Next", (), (), queue(|| Command::Next));
+    b.method("Previous", (), (), queue(|| Command::Prev));
+    b.method("Pause", (), (), queue(|| Command::Pause));
+    b.method("PlayPause", (), (), queue(|| Command::Toggle));
+    b.method("Stop", (), (), queue(|| Command::Stop));
+    b.method("Play", (), (), queue(|| Command::Play(None)));
+    b.method("Seek", ("Offset",), (), |_, state: &mut State, (offset,): (i64,)| {
+        let Some(track) = &state.status.track else {
+            return Ok(());
+        };
+        // seeking past the end is the same as Next according to the spec
+        let position = state.status.position.saturating_add(offset);
+        if position >= track.length {
+            state.commands.push(Command::Next);
+        } else {
+            state.commands.push(Command::Seek(Relative::To(seconds(position.max(0)))));
+        }
+        Ok(())
+    });
+    b.method(
+        "SetPosition",
+        ("TrackId", "Position"),
+        (),
+        |_, state: &mut State, (track_id, position): (dbus::Path<'static>, i64)| {
+            // calls for a track that isn't playing anymore are ignored
+            match &state.status.track {
+                Some(track) if track.id == track_id && (0..=track.length).contains(&position) => {
+                    state.commands.push(Command::Seek(Relative::To(seconds(position))));
+                }
+                _ => {}
+            }
+            Ok(())
+        },
+    );
+    b.method("OpenUri", ("Uri",), (), |_, state: &mut State, (uri,): (String,)| {
+        let path = path_from_url(&uri)
+            .ok_or_else(|| dbus::MethodErr::invalid_arg(&"only file:// urls are supported"))?;
+        state.commands.push(Command::Add(path));
+        Ok(())
+    });
+    b.signal::<(i64,), _>("Seeked", ("Position",));
+
+    b.property("PlaybackStatus")
+        .get(|_, state: &mut State| Ok(state.status.playback_status.to_string()));
+    b.property("LoopStatus")
+        .get(|_, state: &mut State| Ok(state.status.loop_status.to_string()))
+        .set(|_, state: &mut State, value: String| {
+            let repeat_behavior = match value.as_str() {
+                "None" => RepeatBehavior::Normal,
+                "Track" => RepeatBehavior::RepeatSingle,
+                "Playlist" => RepeatBehavior::Repeat,
+                _ => return Err(dbus::MethodErr::invalid_arg(&value)),
+            };
+            state.commands.push(Command::Repeat(repeat_behavior));
+            Ok(None)
+        });
+    b.property("Shuffle")
+        .get(|_, state: &mut State| Ok(state.status.shuffle))
+        .set(|_, state: &mut State, value: bool| {
+            if value == state.status.shuffle {
+                // turning it on again would replace smart shuffle
+                return Ok(None);
+            }
+            let shuffle_behavior = match value {
+                true => ShuffleBehavior::Shuffle,
+                false => ShuffleBehavior::Normal,
+            };
+            state.commands.push(Command::Shuffle(Some(shuffle_behavior)));
+            Ok(None)
+        });
+    b.property("Metadata")
+        .get(|_, state: &mut State| Ok(state.status.metadata()));
+    b.property("Volume")
+        .get(|_, state: &mut State| Ok(state.status.volume))
+        .set(|_, state: &mut State, value: f64| {
+            state
+                .commands
+                .push(Command::Volume(Relative::To(value.clamp(0.0, 1.0) as f32)));
+            Ok(None)
+        });
+    b.property("Position")
+        .emits_changed_false()
+        .get(|_, state: &mut State| Ok(state.status.position));
+    b.property("Rate")
+        .get(|_, state: &mut State| Ok(state.status.rate))
+        .set(|_, state: &mut State, value: f64| {
+            if !value.is_finite() {
+                return Err(dbus::MethodErr::invalid_arg(&value));
+            }
+            // the spec says clients shouldn't set it outside of the range, but some do anyway
+            let speed = (value as f32).clamp(MIN_SPEED, MAX_SPEED);
+            state.commands.push(Command::Speed(speed));
+            Ok(None)
+        });
+    b.property("MinimumRate").get(|_, _: &mut State| Ok(MIN_SPEED as f64));
+    b.property("MaximumRate").get(|_, _: &mut State| Ok(MAX_SPEED as f64));
+    for name in ["CanGoNext", "CanGoPrevious", "CanPlay", "CanPause", "CanSeek"] {
+        b.property(name).get(|_, _: &mut State| Ok(true));
+    }
+    b.property("CanControl")
+        .emits_changed_const()
+        .get(|_, _: &mut State| Ok(true));
+}
+
+/// Exposes the player on the session bus as `org.mpris.MediaPlayer2.mp3_player`, so media keys,
+/// desktop widgets and `playerctl` can control it.
+///
+/// The bus is found through `$DBUS_SESSION_BUS_ADDRESS`, so it can be tried on a private bus with
+/// `dbus-run-session -- mp3-player --headless <files>`.
+///
+/// Like [`crate::ipc::IpcServer`], this never blocks and [`MprisServer::poll`] has to be called
+/// once a frame.
+pub struct MprisServer {
+    conn: LocalConnection,
+    cr: Crossroads,
+    path: dbus::Path<'static>,
+    last_status: Option<Status>,
+    last_poll: Instant,
+}
+
+impl MprisServer {
+    pub fn connect() -> Result<Self, dbus::Error> {
+        Self::with_connection(LocalConnection::new_session()?)
+    }
+
+    fn with_connection(conn: LocalConnection) -> Result<Self, dbus::Error> {
+        // the spec wants a unique suffix if another instance already has the name
+        if conn.request_name(BUS_NAME, false, false, true)? != RequestNameReply::PrimaryOwner {
+            let name = format!("{BUS_NAME}.instance{}", std::process::id());
+            conn.request_name(name, false, false, true)?;
+        }
+
+        let mut cr = Crossroads::new();
+        let root = cr.register(ROOT_IFACE, register_root);
+        let player = cr.register(PLAYER_IFACE, register_player);
+        let path = dbus::Path::from(OBJECT_PATH);
+        cr.insert(
+            path.clone(),
+            &[root, player],
+            State {
+                status: Status {
+                    playback_status: "Stopped",
+                    loop_status: "None",
+                    shuffle: false,
+                    volume: 1.0,
+                    rate: 1.0,
+                    position: 0,
+                    track: None,
+                },
+                commands: Vec::new(),
+            },
+        );
+
+        Ok(Self {
+            conn,
+            cr,
+            path,
+            last_status: None,
+            last_poll: Instant::now(),
+        })
+    }
+
+    pub fn poll<B: AudioBackend>(
+        &mut self,
+        playlist: &mut Playlist<B>,
+        audio: &mut B,
+        screen_height: i32,
+    ) {
+        if self.conn.channel().read_write(Some(Duration::ZERO)).is_err() {
+            return;
+        }
+        while let Some(msg) = self.conn.channel().pop_message() {
+            // signals and the like aren't for us
+            _ = self.cr.handle_message(msg, &self.conn);
+        }
+
+        let commands = match self.cr.data_mut::<State>(&self.path) {
+            Some(state) => std::mem::take(&mut state.commands),
+            None => Vec::new(),
+        };
+        for command in commands {
+            if let Err(err) = command.execute(playlist, audio, screen_height) {
+                eprintln!("MPRIS: {err}");
+            }
+        }
+
+        let status = Status::new(playlist, audio);
+        self.emit_changes(&status);
+        if let Some(state) = self.cr.data_mut::<State>(&self.path) {
+            state.status = status.clone();
+        }
+        self.last_status = Some(status);
+        self.last_poll = Instant::now();
+    }
+
+    /// Sends PropertiesChanged for everything that changed since the last poll, and Seeked if
+    /// the position jumped.
+    fn emit_changes(&self, status: &Status) {
+        let mut changed = PropMap::new();
+        let last = self.last_status.as_ref();
+
+        if last.map(|last| last.playback_status) != Some(status.playback_status) {
+            changed.insert(
+                "PlaybackStatus".to_string(),
+                Variant(Box::new(status.playback_status.to_string())),
+            );
+        }
+        if last.map(|last| last.loop_status) != Some(status.loop_status) {
+            changed.insert(
+                "LoopStatus".to_string(),
+                Variant(Box::new(status.loop_status.to_string())),
+            );
+        }
+        if last.map(|last| last.shuffle) != Some(status.shuffle) {
+            changed.insert("Shuffle".to_string(), Variant(Box::new(status.shuffle)));
+        }
+        if last.map(|last| last.volume) != Some(status.volume) {
+            changed.insert("Volume".to_string(), Variant(Box::new(status.volume)));
+        }
+        if last.map(|last| last.rate) != Some(status.rate) {
+            changed.insert("Rate".to_string(), Variant(Box::new(status.rate)));
+        }
+        if last.map(|last| &last.track) != Some(&status.track) {
+            changed.insert("Metadata".to_string(), Variant(Box::new(status.metadata())));
+        }
+
+        if !changed.is_empty() {
+            let msg = PropertiesPropertiesChanged {
+                interface_name: PLAYER_IFACE.to_string(),
+                changed_properties: changed,
+                invalidated_properties: Vec::new(),
+            }
+            .to_emit_message(&self.path);
+            _ = self.conn.channel().send(msg);
+        }
+
+        let Some(last) = last else {
+            return;
+        };
+        let same_track = match (&last.track, &status.track) {
+            (Some(last), Some(current)) => last.id == current.id,
+            _ => false,
+        };
+        if same_track {
+            let mut expected = last.position;
+            if last.playback_status == "Playing" {
+                expected += (self.last_poll.elapsed().as_micros() as f64 * last.rate) as i64;
+            }
+            // anything more than normal playback drift means someone seeked
+            if (status.position - expected).abs() > 1_000_000 {
+                if let Ok(msg) = Message::new_signal(OBJECT_PATH, PLAYER_IFACE, "Seeked") {
+                    _ = self.conn.channel().send(msg.append1(status.position));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{BufRead, BufReader},
+        process::{Child, Command as Process, Stdio},
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use dbus::{
+        blocking::{stdintf::org_freedesktop_dbus::Properties, Connection},
+        channel::Channel,
+    };
+    use mp3_player::{audio_null::NullBackend, song::SongEntry};
+
+    use super::*;
+
+    /// A private session bus, stopped again when dropped.
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        fn start() -> Self {
+            let mut daemon = Process::new("dbus-daemon")
+                .args(["--session", "--print-address", "--nofork"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("dbus-daemon has to be installed");
+            let mut address = String::new();
+            let stdout = daemon.stdout.take().unwrap();
+            BufReader::new(stdout).read_line(&mut address).unwrap();
+            Self {
+                daemon,
+                address: address.trim().to_string(),
+            }
+        }
+
+        fn channel(&self) -> Channel {
+            let mut channel = Channel::open_private(&self.address).unwrap();
+            channel.register().unwrap();
+            channel
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            _ = self.daemon.kill();
+            _ = self.daemon.wait();
+        }
+    }
+
+    #[test]
+    #[ignore = "starts a dbus-daemon"]
+    fn controls_the_player_over_the_session_bus() {
+        let bus = Bus::start();
+        let dir =
+            std::env::temp_dir().join(format!("mp3-player-test-{}-mpris", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut playlist: Playlist<NullBackend> = Playlist::default();
+        for idx in 0..3 {
+            let path = dir.join(format!("song {idx}.mp3"));
+            fs::write(&path, "").unwrap();
+            playlist.add_song(SongEntry::new(path).unwrap());
+        }
+        let mut audio = NullBackend::new();
+        let mut server =
+            MprisServer::with_connection(LocalConnection::from(bus.channel())).unwrap();
+
+        let client_conn = Connection::from(bus.channel());
+        let client = thread::spawn(move || {
+            let changes = Arc::new(Mutex::new(Vec::new()));
+            let received = changes.clone();
+            client_conn
+                .add_match(
+                    PropertiesPropertiesChanged::match_rule(None, None).static_clone(),
+                    move |changed: PropertiesPropertiesChanged, _, _| {
+                        received.lock().unwrap().push(changed.changed_properties);
+                        true
+                    },
+                )
+                .unwrap();
+            let player = client_conn.with_proxy(BUS_NAME, OBJECT_PATH, Duration::from_secs(5));
+            let title = || {
+                let metadata: PropMap = player.get(PLAYER_IFACE, "Metadata").unwrap();
+                metadata["xesam:title"].0.as_str().map(str::to_string)
+            };
+
+            let status: String = player.get(PLAYER_IFACE, "PlaybackStatus").unwrap();
+            assert_eq!(status, "Stopped");
+            player
+                .method_call::<(), _, _, _>(PLAYER_IFACE, "PlayPause", ())
+                .unwrap();
+            let status: String = player.get(PLAYER_IFACE, "PlaybackStatus").unwrap();
+            assert_eq!(status, "Playing");
+            assert_eq!(title().as_deref(), Some("song 0"));
+
+            player
+                .method_call::<(), _, _, _>(PLAYER_IFACE, "Next", ())
+                .unwrap();
+            assert_eq!(title().as_deref(), Some("song 1"));
+
+            // the signals may still be on their way
+            let title_changed = |changes: &[PropMap]| {
+                changes.iter().any(|changed| {
+                    changed.get("Metadata").is_some_and(|metadata| {
+                        let metadata = metadata.0.as_iter().unwrap().collect::<Vec<_>>();
+                        metadata.chunks(2).any(|entry| {
+                            entry[0].as_str() == Some("xesam:title")
+                                && entry[1].as_str() == Some("song 1")
+                        })
+                    })
+                })
+            };
+            for _ in 0..50 {
+                if title_changed(&changes.lock().unwrap()) {
+                    break;
+                }
+                client_conn.process(Duration::from_millis(100)).unwrap();
+            }
+            let changes = changes.lock().unwrap();
+            assert!(title_changed(&changes));
+            assert!(changes.iter().any(|changed| {
+                changed
+                    .get("PlaybackStatus")
+                    .is_some_and(|status| status.0.as_str() == Some("Playing"))
+            }));
+        });
+
+        let start = Instant::now();
+        while !client.is_finished() {
+            assert!(
+                start.elapsed() < Duration::from_secs(20),
+                "the client got stuck"
+            );
+            server.poll(&mut playlist, &mut audio, 0);
+            thread::sleep(Duration::from_millis(5));
+        }
+        _ = fs::remove_dir_all(&dir);
+        if let Err(panic) = client.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
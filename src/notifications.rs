@@ -0,0 +1,150 @@
+use std::{
+    collections::VecDeque,
+    ffi::CString,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use raylib::{
+    color::Color,
+    drawing::{RaylibDraw, RaylibDrawHandle},
+    ffi::MouseButton,
+    math::Rectangle,
+    rgui::RaylibDrawGui,
+};
+
+/// How long a toast stays visible.
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+/// Only the newest entries are kept in the log.
+const MAX_LOG_ENTRIES: usize = 500;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Error,
+}
+
+pub struct LogEntry {
+    pub level: Level,
+    /// local time of day, formatted as HH:MM:SS
+    pub time: String,
+    pub message: String,
+}
+
+struct Toast {
+    level: Level,
+    text: CString,
+    shown_at: Option<Instant>,
+}
+
+/// Short-lived messages shown in a status bar at the bottom of the player, plus a log of
+/// everything that was shown, for the log screen.
+#[derive(Default)]
+pub struct Notifications {
+    toasts: VecDeque<Toast>,
+    log: VecDeque<LogEntry>,
+    unread_errors: usize,
+}
+
+fn local_time_of_day() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|dur| dur.as_secs() as libc::time_t)
+        .unwrap_or_default();
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+    if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+        return "--:--:--".to_string();
+    }
+    format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
+
+impl Notifications {
+    pub fn info<S: Into<String>>(&mut self, message: S) {
+        self.push(Level::Info, message.into());
+    }
+
+    pub fn error<S: Into<String>>(&mut self, message: S) {
+        self.push(Level::Error, message.into());
+    }
+
+    fn push(&mut self, level: Level, message: String) {
+        if level == Level::Error {
+            eprintln!("{message}");
+            self.unread_errors += 1;
+        } else {
+            println!("{message}");
+        }
+
+        // the status bar is only one line high
+        let first_line = message.lines().next().unwrap_or_default();
+        if let Ok(text) = CString::new(first_line) {
+            self.toasts.push_back(Toast {
+                level,
+                text,
+                shown_at: None,
+            });
+        }
+
+        if self.log.len() >= MAX_LOG_ENTRIES {
+            self.log.pop_front();
+        }
+        self.log.push_back(LogEntry {
+            level,
+            time: local_time_of_day(),
+            message,
+        });
+    }
+
+    pub fn log(&self) -> &VecDeque<LogEntry> {
+        &self.log
+    }
+
+    pub fn clear_log(&mut self) {
+        self.log.clear();
+        self.unread_errors = 0;
+    }
+
+    /// Errors that happened since the log was last opened.
+    pub fn unread_errors(&self) -> usize {
+        self.unread_errors
+    }
+
+    pub fn mark_read(&mut self) {
+        self.unread_errors = 0;
+    }
+
+    /// Draws the current toast in a status bar at the bottom of the screen. Returns true if the
+    /// status bar was clicked, which should open the log.
+    pub fn render_toast(&mut self, d: &mut RaylibDrawHandle) -> bool {
+        while let Some(toast) = self.toasts.front() {
+            match toast.shown_at {
+                Some(shown_at) if shown_at.elapsed() >= TOAST_DURATION => {
+                    self.toasts.pop_front();
+                }
+                _ => break,
+            }
+        }
+        let Some(toast) = self.toasts.front_mut() else {
+            return false;
+        };
+        toast.shown_at.get_or_insert_with(Instant::now);
+
+        let bounds = Rectangle::new(
+            0.0,
+            (d.get_screen_height() - 20) as f32,
+            d.get_screen_width() as f32,
+            20.0,
+        );
+        d.gui_status_bar(bounds, Some(toast.text.as_c_str()));
+        if toast.level == Level::Error {
+            d.draw_rectangle(0, bounds.y as i32, 3, 20, Color::RED);
+        }
+
+        if d.is_mouse_button_released(MouseButton::MOUSE_BUTTON_LEFT)
+            && bounds.check_collision_point_rec(d.get_mouse_position())
+        {
+            self.toasts.clear();
+            return true;
+        }
+        false
+    }
+}
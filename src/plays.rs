@@ -0,0 +1,74 @@
+//! How often and when songs were played, which smart shuffle uses to pick the next songs.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::files::write_atomic;
+
+#[derive(Clone, Copy, Default)]
+pub struct PlayStat {
+    pub count: u32,
+    /// seconds since the unix epoch, 0 if it never played
+    pub last_played: u64,
+}
+
+#[derive(Default)]
+pub struct PlayStats {
+    stats: HashMap<PathBuf, PlayStat>,
+}
+
+impl PlayStats {
+    pub fn get(&self, path: &Path) -> PlayStat {
+        self.stats.get(path).copied().unwrap_or_default()
+    }
+
+    /// Counts a play of the file that started at `time`, see `now`.
+    pub fn record(&mut self, path: &Path, time: u64) {
+        let stat = self.stats.entry(path.to_path_buf()).or_default();
+        stat.count = stat.count.saturating_add(1);
+        stat.last_played = time;
+    }
+
+    /// Reads stats written by `save`, invalid lines are skipped.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut me = Self::default();
+        for line in fs::read_to_string(path)?.lines() {
+            let mut parts = line.splitn(3, '\t');
+            let (Some(count), Some(last_played), Some(file)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            if let (Ok(count), Ok(last_played)) = (count.parse(), last_played.parse()) {
+                me.stats
+                    .insert(PathBuf::from(file), PlayStat { count, last_played });
+            }
+        }
+        Ok(me)
+    }
+
+    /// Writes one `count<tab>last played<tab>path` line per file.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut contents = String::with_capacity(self.stats.len() * 70);
+        for (file, stat) in &self.stats {
+            let Some(file) = file.to_str().filter(|file| !file.contains('\n')) else {
+                continue;
+            };
+            contents.push_str(&format!("{}\t{}\t{file}\n", stat.count, stat.last_played));
+        }
+
+        write_atomic(path, contents)
+    }
+}
+
+/// The current time in seconds since the unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|dur| dur.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,307 @@
+use std::{path::PathBuf, time::Duration};
+
+use mp3_player::{
+    audio::AudioBackend,
+    sleep::SleepAfter,
+    song::{Playlist, RepeatBehavior, ShuffleBehavior},
+    speed::{MAX_SPEED, MIN_SPEED},
+};
+
+/// A command from one of the remote control interfaces.
+pub enum Command {
+    /// resume, or start playing the song at the index
+    Play(Option<usize>),
+    Pause,
+    Toggle,
+    Stop,
+    Next,
+    Prev,
+    Seek(Relative),
+    /// volume goes from 0 to 1
+    Volume(Relative),
+    Add(PathBuf),
+    Remove(usize),
+    Clear,
+    Repeat(RepeatBehavior),
+    /// `None` switches to the next shuffle behavior
+    Shuffle(Option<ShuffleBehavior>),
+    /// seconds, 0 to 12
+    Crossfade(f32),
+    /// 0.5 to 3
+    Speed(f32),
+    /// `None` turns the sleep timer off
+    Sleep(Option<SleepAfter>),
+    Status,
+}
+
+pub enum Relative {
+    To(f32),
+    By(f32),
+}
+
+impl Relative {
+    /// `+5` and `-5` are relative, `5` is absolute.
+    pub fn parse(value: &str) -> Option<Self> {
+        let relative = value.starts_with('+') || value.starts_with('-');
+        let value = value.parse::<f32>().ok().filter(|v| v.is_finite())?;
+        Some(if relative {
+            Self::By(value)
+        } else {
+            Self::To(value)
+        })
+    }
+
+    fn apply(&self, current: f32) -> f32 {
+        match self {
+            Self::To(value) => *value,
+            Self::By(value) => current + value,
+        }
+    }
+}
+
+pub const COMMANDS_HELP: &str = "\
+play [index], pause, toggle, stop, next, prev, seek <[+-]seconds>, volume <[+-]0-100>, \
+add <path>, remove <index>, clear, repeat <none|all|single>, shuffle [on|off|smart], \
+crossfade <seconds>, speed <0.5-3>, sleep <minutes|song|<n> songs|off>, status";
+
+impl Command {
+    /// Parses a line like `seek +10` or `add /home/user/Music`.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (name, arg) = match line.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, Some(arg.trim())),
+            None => (line, None),
+        };
+
+        let missing = || format!("{name} needs an argument");
+        Ok(match (name, arg) {
+            ("play", None) => Self::Play(None),
+            ("play", Some(idx)) => Self::Play(Some(parse_index(idx)?)),
+            ("pause", None) => Self::Pause,
+            ("toggle", None) => Self::Toggle,
+            ("stop", None) => Self::Stop,
+            ("next", None) => Self::Next,
+            ("prev", None) => Self::Prev,
+            ("seek", Some(value)) => Self::Seek(
+                Relative::parse(value).ok_or_else(|| format!("invalid position '{value}'"))?,
+            ),
+            ("volume", Some(value)) => Self::Volume(
+                match Relative::parse(value).ok_or_else(|| format!("invalid volume '{value}'"))? {
+                    Relative::To(value) => Relative::To(value / 100.0),
+                    Relative::By(value) => Relative::By(value / 100.0),
+                },
+            ),
+            ("add", Some(path)) => Self::Add(PathBuf::from(path)),
+            ("remove", Some(idx)) => Self::Remove(parse_index(idx)?),
+            ("clear", None) => Self::Clear,
+            ("repeat", Some(value)) => Self::Repeat(value.parse()?),
+            ("shuffle", None) => Self::Shuffle(None),
+            ("shuffle", Some(value)) => Self::Shuffle(Some(value.parse()?)),
+            ("crossfade", Some(value)) => {
+                Self::Crossfade(value.parse().ok().filter(|v| (0.0..=12.0).contains(v)).ok_or_else(
+                    || format!("invalid crossfade '{value}', expected 0 to 12 seconds"),
+                )?)
+            }
+            ("speed", Some(value)) => Self::Speed(
+                value
+                    .parse()
+                    .ok()
+                    .filter(|v| (MIN_SPEED..=MAX_SPEED).contains(v))
+                    .ok_or_else(|| format!("invalid speed '{value}', expected 0.5 to 3"))?,
+            ),
+            ("sleep", Some(value)) => Self::Sleep(parse_sleep(value).ok_or_else(|| {
+                format!("invalid sleep timer '{value}', expected minutes, song, <n> songs or off")
+            })?),
+            ("status", None) => Self::Status,
+            (
+                "seek" | "volume" | "add" | "remove" | "repeat" | "crossfade" | "speed" | "sleep",
+                None,
+            ) => return Err(missing()),
+            (
+                "pause" | "toggle" | "stop" | "next" | "prev" | "clear" | "status",
+                Some(_),
+            ) => {
+                return Err(format!("{name} doesn't take an argument"))
+            }
+            ("", _) => return Err("empty command".to_string()),
+            _ => return Err(format!("unknown command '{name}'")),
+        })
+    }
+
+    pub fn execute<B: AudioBackend>(
+        &self,
+        playlist: &mut Playlist<B>,
+        audio: &mut B,
+        screen_height: i32,
+    ) -> Result<(), String> {
+        match self {
+            Self::Play(Some(idx)) => {
+                check_index(playlist, *idx)?;
+                playlist.play_ignore_err(*idx, audio, screen_height);
+            }
+            Self::Play(None) => {
+                if playlist.has_music_stream() {
+                    playlist.resume(audio);
+                } else {
+                    playlist.play_ignore_err(playlist.first_song(), audio, screen_height);
+                }
+            }
+            Self::Pause => playlist.pause(audio),
+            Self::Toggle => {
+                if playlist.has_music_stream() {
+                    playlist.pause_resume(audio);
+                } else {
+                    playlist.play_ignore_err(playlist.first_song(), audio, screen_height);
+                }
+            }
+            Self::Stop => playlist.stop_playing(audio),
+            Self::Next => playlist.play_next(audio, screen_height),
+            Self::Prev => playlist.play_previous(audio, screen_height),
+            Self::Seek(position) => {
+                if !playlist.has_music_stream() {
+                    return Err("nothing is playing".to_string());
+                }
+                let position = position
+                    .apply(playlist.music_length_played(audio))
+                    .clamp(0.0, playlist.music_length_total(audio));
+                playlist.seek(position, audio);
+            }
+            Self::Volume(volume) => {
+                let volume = volume.apply(audio.master_volume()).clamp(0.0, 1.0);
+                audio.set_master_volume(volume);
+            }
+            Self::Add(path) => {
+                let len = playlist.len();
+                playlist
+                    .add_song_by_path(path)
+                    .map_err(|err| format!("failed to add {}: {err}", path.display()))?;
+                if playlist.len() == len {
+                    return Err(format!("no songs found in {}", path.display()));
+                }
+                if !playlist.has_music_stream() {
+                    playlist.play_ignore_err(len, audio, screen_height);
+                }
+            }
+            Self::Remove(idx) => {
+                check_index(playlist, *idx)?;
+                playlist.remove_song(*idx, audio, screen_height);
+            }
+            Self::Clear => playlist.clear(audio),
+            Self::Repeat(repeat_behavior) => playlist.repeat_behavior = *repeat_behavior,
+            Self::Shuffle(Some(shuffle_behavior)) => {
+                playlist.set_shuffle_behavior(*shuffle_behavior)
+            }
+            Self::Shuffle(None) => {
+                let mut shuffle_behavior = playlist.shuffle_behavior();
+                shuffle_behavior.next();
+                playlist.set_shuffle_behavior(shuffle_behavior);
+            }
+            Self::Crossfade(seconds) => playlist.crossfade = *seconds,
+            Self::Speed(speed) => playlist.set_speed(*speed, audio),
+            Self::Sleep(after) => playlist.set_sleep_timer(*after, audio),
+            Self::Status => {}
+        }
+        Ok(())
+    }
+}
+
+/// `off`, minutes, `song` for the end of the current song or `<n> songs`.
+fn parse_sleep(value: &str) -> Option<Option<SleepAfter>> {
+    match value {
+        "off" => return Some(None),
+        "song" => return Some(Some(SleepAfter::Songs(1))),
+        _ => {}
+    }
+    if let Some(songs) = value.strip_suffix("songs").or(value.strip_suffix("song")) {
+        let songs = songs.trim().parse::<u64>().ok().filter(|songs| *songs > 0)?;
+        return Some(Some(SleepAfter::Songs(songs)));
+    }
+    let minutes = value.parse::<f32>().ok().filter(|v| v.is_finite() && *v > 0.0)?;
+    Some(Some(SleepAfter::Duration(Duration::from_secs_f32(minutes * 60.0))))
+}
+
+/// Indices are 1-based for users.
+fn parse_index(idx: &str) -> Result<usize, String> {
+    match idx.parse::<usize>() {
+        Ok(idx) if idx > 0 => Ok(idx - 1),
+        _ => Err(format!("invalid index '{idx}', indices start at 1")),
+    }
+}
+
+fn check_index<B: AudioBackend>(playlist: &Playlist<B>, idx: usize) -> Result<(), String> {
+    if idx >= playlist.len() {
+        return Err(format!(
+            "there is no song #{}, the playlist has {}",
+            idx + 1,
+            playlist.len()
+        ));
+    }
+    Ok(())
+}
+
+pub fn json_string(value: &str) -> String {
+    let mut str = String::with_capacity(value.len() + 2);
+    str.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => str.push_str("\\\""),
+            '\\' => str.push_str("\\\\"),
+            '\n' => str.push_str("\\n"),
+            '\r' => str.push_str("\\r"),
+            '\t' => str.push_str("\\t"),
+            c if (c as u32) < 0x20 => str.push_str(&format!("\\u{:04x}", c as u32)),
+            c => str.push(c),
+        }
+    }
+    str.push('"');
+    str
+}
+
+pub fn c_vec_to_string(vec: &[u8]) -> String {
+    String::from_utf8_lossy(vec.strip_suffix(&[0]).unwrap_or(vec)).into_owned()
+}
+
+/// The player state as a single-line JSON object.
+pub fn status_json<B: AudioBackend>(playlist: &Playlist<B>, audio: &B) -> String {
+    let state = if !playlist.has_music_stream() {
+        "stopped"
+    } else if playlist.is_music_playing(audio) {
+        "playing"
+    } else {
+        "paused"
+    };
+
+    let mut str = format!(
+        "{{\"state\":\"{state}\",\"volume\":{:.0},\"repeat\":\"{}\",\"shuffle\":\"{}\",\"crossfade\":{},\"speed\":{},\"length\":{}",
+        audio.master_volume() * 100.0,
+        playlist.repeat_behavior.as_str(),
+        playlist.shuffle_behavior().as_str(),
+        playlist.crossfade,
+        playlist.speed(),
+        playlist.len(),
+    );
+    if let Some(remaining) = playlist
+        .sleep_timer()
+        .and_then(|timer| timer.remaining(playlist, audio))
+    {
+        str.push_str(&format!(",\"sleep\":{remaining:.0}"));
+    }
+    if let Some(idx) = playlist.currently_playing_id() {
+        let path = playlist
+            .get_songs()
+            .get(idx)
+            .map(|song| song.path().to_string_lossy().into_owned())
+            .unwrap_or_default();
+        str.push_str(&format!(
+            ",\"index\":{},\"title\":{},\"author\":{},\"path\":{},\"position\":{:.3},\"duration\":{:.3}",
+            idx + 1,
+            json_string(&playlist.filename_vec().map(|v| c_vec_to_string(v)).unwrap_or_default()),
+            json_string(&playlist.author_vec().map(|v| c_vec_to_string(v)).unwrap_or_default()),
+            json_string(&path),
+            playlist.music_length_played(audio),
+            playlist.music_length_total(audio),
+        ));
+    }
+    str.push('}');
+    str
+}
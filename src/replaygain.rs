@@ -0,0 +1,932 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// Tags bigger than this (usually because of embedded cover art) are not read.
+const MAX_TAG_SIZE: usize = 16 * 1024 * 1024;
+/// Free space left in written tags, so they can be changed later without moving the audio data.
+const PADDING: usize = 1024;
+
+const KEYS: [&str; 4] = [
+    "REPLAYGAIN_TRACK_GAIN",
+    "REPLAYGAIN_TRACK_PEAK",
+    "REPLAYGAIN_ALBUM_GAIN",
+    "REPLAYGAIN_ALBUM_PEAK",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReplayGainMode {
+    Off,
+    Track,
+    Album,
+}
+
+impl ReplayGainMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Track => "track",
+            Self::Album => "album",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(Self::Off),
+            "track" => Some(Self::Track),
+            "album" => Some(Self::Album),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ReplayGainOptions {
+    pub mode: ReplayGainMode,
+    /// dB added to the gain of songs that have ReplayGain tags
+    pub preamp: f32,
+    /// lower the gain so the peak of the song doesn't go above full scale
+    pub prevent_clipping: bool,
+}
+
+impl Default for ReplayGainOptions {
+    fn default() -> Self {
+        Self {
+            mode: ReplayGainMode::Off,
+            preamp: 0.0,
+            prevent_clipping: true,
+        }
+    }
+}
+
+impl ReplayGainOptions {
+    /// The volume (1 is unchanged) the song at `path` should be played with. Songs without tags
+    /// use `scanned` instead, or are played unchanged if there is none.
+    pub fn volume(&self, path: &Path, scanned: Option<&ReplayGain>) -> f32 {
+        if self.mode == ReplayGainMode::Off {
+            return 1.0;
+        }
+        match (ReplayGain::read(path), scanned) {
+            (Ok(replay_gain), _) if !replay_gain.is_empty() => self.volume_for(&replay_gain),
+            (_, Some(scanned)) => self.volume_for(scanned),
+            _ => 1.0,
+        }
+    }
+
+    pub fn volume_for(&self, replay_gain: &ReplayGain) -> f32 {
+        let (gain, peak) = match self.mode {
+            ReplayGainMode::Off => return 1.0,
+            ReplayGainMode::Track => (
+                replay_gain.track_gain.or(replay_gain.album_gain),
+                replay_gain.track_peak.or(replay_gain.album_peak),
+            ),
+            ReplayGainMode::Album => (
+                replay_gain.album_gain.or(replay_gain.track_gain),
+                replay_gain.album_peak.or(replay_gain.track_peak),
+            ),
+        };
+        let Some(gain) = gain else {
+            return 1.0;
+        };
+        let volume = 10f32.powf((gain + self.preamp) / 20.0);
+        match peak {
+            Some(peak) if self.prevent_clipping && peak > 0.0 => volume.min(1.0 / peak),
+            _ => volume,
+        }
+    }
+}
+
+/// The `REPLAYGAIN_*` tags of a song. Gains are in dB, peaks relative to full scale.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct ReplayGain {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl ReplayGain {
+    /// Reads the ID3v2 tag of mp3s or the Vorbis comments of FLAC and Ogg Vorbis files. Other
+    /// formats have no tags.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0; 4];
+        file.read_exact(&mut magic)?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let tags = match &magic {
+            [b'I', b'D', b'3', _] => read_id3(&mut file)?,
+            b"fLaC" => read_flac(&mut file)?,
+            b"OggS" => read_ogg(&mut file)?,
+            _ => vec![],
+        };
+        Ok(Self::from_tags(tags))
+    }
+
+    /// Whether there is no gain at all, peaks alone are useless.
+    pub fn is_empty(&self) -> bool {
+        self.track_gain.is_none() && self.album_gain.is_none()
+    }
+
+    /// Writes the tags into an mp3 (ID3v2.3 or 2.4) or FLAC file, replacing existing ReplayGain
+    /// tags. The new file replaces the old one, so streams that have it open keep working.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let data = fs::read(path)?;
+        let tags: Vec<(&str, String)> = KEYS
+            .iter()
+            .zip([self.track_gain, self.track_peak, self.album_gain, self.album_peak])
+            .enumerate()
+            .filter_map(|(i, (key, value))| {
+                // gains in dB, peaks as a factor
+                let value = if i % 2 == 0 {
+                    format!("{:.2} dB", value?)
+                } else {
+                    format!("{:.6}", value?)
+                };
+                Some((*key, value))
+            })
+            .collect();
+
+        let is_mp3 = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("mp3"));
+        let new = if data.starts_with(b"fLaC") {
+            write_flac(&data, &tags)?
+        } else if data.starts_with(b"ID3") || is_mp3 {
+            write_id3(&data, &tags)?
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "tags can only be written to mp3 and flac files",
+            ));
+        };
+
+        let mut tmp_name = std::ffi::OsString::from(".");
+        tmp_name.push(path.file_name().unwrap_or_default());
+        tmp_name.push(".tmp");
+        let tmp = path.with_file_name(tmp_name);
+        let result = fs::write(&tmp, new)
+            .and_then(|_| fs::set_permissions(&tmp, fs::metadata(path)?.permissions()))
+            .and_then(|_| fs::rename(&tmp, path));
+        if result.is_err() {
+            _ = fs::remove_file(&tmp);
+        }
+        result
+    }
+
+    fn from_tags(tags: Vec<(String, String)>) -> Self {
+        let mut me = Self::default();
+        for (key, value) in tags {
+            let field = match key.to_ascii_uppercase().as_str() {
+                "REPLAYGAIN_TRACK_GAIN" => &mut me.track_gain,
+                "REPLAYGAIN_TRACK_PEAK" => &mut me.track_peak,
+                "REPLAYGAIN_ALBUM_GAIN" => &mut me.album_gain,
+                "REPLAYGAIN_ALBUM_PEAK" => &mut me.album_peak,
+                _ => continue,
+            };
+            // gains look like "-6.54 dB"
+            let value = value.trim();
+            let value = value
+                .strip_suffix("dB")
+                .or_else(|| value.strip_suffix("db"))
+                .unwrap_or(value);
+            if let Ok(value) = value.trim().parse::<f32>() {
+                *field = Some(value).filter(|v| v.is_finite());
+            }
+        }
+        me
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_vec<R: Read>(file: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    if len > MAX_TAG_SIZE {
+        return Err(invalid("tag too big"));
+    }
+    let mut data = vec![0; len];
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |acc, b| (acc << 7) | (*b & 0x7f) as usize)
+}
+
+fn big_endian(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |acc, b| (acc << 8) | *b as usize)
+}
+
+/// Undoes the ID3 unsynchronisation, which inserts a 0 after every 0xff.
+fn remove_unsync(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for (i, b) in data.iter().enumerate() {
+        if *b == 0 && i > 0 && data[i - 1] == 0xff {
+            continue;
+        }
+        out.push(*b);
+    }
+    out
+}
+
+/// Returns the description and value of all TXXX frames.
+fn read_id3<R: Read>(file: &mut R) -> io::Result<Vec<(String, String)>> {
+    let mut header = [0; 10];
+    file.read_exact(&mut header)?;
+    let version = header[3];
+    let flags = header[5];
+    let mut body = read_vec(file, syncsafe(&header[6..10]))?;
+    if flags & 0x80 != 0 && version < 4 {
+        body = remove_unsync(&body);
+    }
+
+    let mut pos = 0;
+    if flags & 0x40 != 0 && version >= 3 {
+        // extended header, its size includes the size field only in v2.4
+        let Some(size) = body.get(0..4) else {
+            return Ok(vec![]);
+        };
+        pos = if version == 3 {
+            big_endian(size) + 4
+        } else {
+            syncsafe(size)
+        };
+    }
+
+    let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+    let mut tags = vec![];
+    while pos + header_len <= body.len() {
+        let frame = &body[pos..pos + header_len];
+        if frame[0] == 0 {
+            // padding
+            break;
+        }
+        let size = match version {
+            2 => big_endian(&frame[3..6]),
+            3 => big_endian(&frame[4..8]),
+            _ => syncsafe(&frame[4..8]),
+        };
+        let start = pos + header_len;
+        let Some(data) = body.get(start..start + size) else {
+            break;
+        };
+        pos = start + size;
+
+        if &frame[..id_len] != b"TXXX" && &frame[..id_len] != b"TXX" {
+            continue;
+        }
+        let mut data = data.to_vec();
+        if version == 3 {
+            if frame[9] & 0xc0 != 0 {
+                // compressed or encrypted
+                continue;
+            }
+            if frame[9] & 0x20 != 0 {
+                data.drain(..1.min(data.len()));
+            }
+        } else if version >= 4 {
+            if frame[9] & 0x0c != 0 {
+                continue;
+            }
+            if frame[9] & 0x02 != 0 || flags & 0x80 != 0 {
+                data = remove_unsync(&data);
+            }
+            if frame[9] & 0x01 != 0 {
+                // data length indicator
+                data.drain(..4.min(data.len()));
+            }
+        }
+        if let Some(tag) = parse_txxx(&data) {
+            tags.push(tag);
+        }
+    }
+    Ok(tags)
+}
+
+fn parse_txxx(data: &[u8]) -> Option<(String, String)> {
+    let (encoding, text) = data.split_first()?;
+    let wide = matches!(encoding, 1 | 2);
+    let split = if wide {
+        (0..text.len().saturating_sub(1))
+            .step_by(2)
+            .find(|&i| text[i] == 0 && text[i + 1] == 0)?
+    } else {
+        text.iter().position(|b| *b == 0)?
+    };
+    let value_start = split + if wide { 2 } else { 1 };
+    Some((
+        decode_id3_text(*encoding, &text[..split]),
+        decode_id3_text(*encoding, &text[value_start..]),
+    ))
+}
+
+fn decode_id3_text(encoding: u8, text: &[u8]) -> String {
+    let text = match encoding {
+        1 | 2 => {
+            let mut big_endian = encoding == 2;
+            let mut text = text;
+            if let Some(rest) = text.strip_prefix(&[0xfe, 0xff]) {
+                big_endian = true;
+                text = rest;
+            } else if let Some(rest) = text.strip_prefix(&[0xff, 0xfe]) {
+                big_endian = false;
+                text = rest;
+            }
+            let units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|c| {
+                    if big_endian {
+                        u16::from_be_bytes([c[0], c[1]])
+                    } else {
+                        u16::from_le_bytes([c[0], c[1]])
+                    }
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        3 => String::from_utf8_lossy(text).into_owned(),
+        // latin-1
+        _ => text.iter().map(|b| *b as char).collect(),
+    };
+    text.trim_end_matches('\0').to_string()
+}
+
+fn read_flac<R: Read>(file: &mut R) -> io::Result<Vec<(String, String)>> {
+    let mut magic = [0; 4];
+    file.read_exact(&mut magic)?;
+    loop {
+        let mut header = [0; 4];
+        file.read_exact(&mut header)?;
+        let last = header[0] & 0x80 != 0;
+        let len = big_endian(&header[1..4]);
+        if header[0] & 0x7f == 4 {
+            return Ok(parse_vorbis_comments(&read_vec(file, len)?));
+        }
+        // skip other blocks, pictures can be big so this doesn't use read_vec
+        io::copy(&mut file.take(len as u64), &mut io::sink())?;
+        if last {
+            return Ok(vec![]);
+        }
+    }
+}
+
+/// Reads the second packet of the first logical stream, the comment header of Ogg Vorbis.
+fn read_ogg<R: Read>(file: &mut R) -> io::Result<Vec<(String, String)>> {
+    let mut packets = 0;
+    let mut packet = vec![];
+    loop {
+        let mut header = [0; 27];
+        file.read_exact(&mut header)?;
+        if &header[..4] != b"OggS" {
+            return Err(invalid("not an ogg page"));
+        }
+        let segments = read_vec(file, header[26] as usize)?;
+        for segment in segments {
+            let data = read_vec(file, segment as usize)?;
+            if packets == 1 {
+                packet.extend_from_slice(&data);
+                if packet.len() > MAX_TAG_SIZE {
+                    return Err(invalid("tag too big"));
+                }
+            }
+            // a segment shorter than 255 bytes ends the packet
+            if segment < 255 {
+                packets += 1;
+                if packets == 2 {
+                    let Some(comments) = packet.strip_prefix(b"\x03vorbis") else {
+                        return Ok(vec![]);
+                    };
+                    return Ok(parse_vorbis_comments(comments));
+                }
+            }
+        }
+    }
+}
+
+/// The vendor string and the comments of a Vorbis comment block as they are stored, `None` if
+/// the block is cut off.
+fn split_vorbis_comments(data: &[u8]) -> Option<(&[u8], Vec<&[u8]>)> {
+    let mut pos = 0;
+    let mut next = |len: usize| {
+        let bytes = data.get(pos..pos + len)?;
+        pos += len;
+        Some(bytes)
+    };
+    let read_u32 = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+    let vendor_len = next(4).map(read_u32)?;
+    let vendor = next(vendor_len as usize)?;
+    let count = next(4).map(read_u32)?;
+    let mut comments = vec![];
+    for _ in 0..count {
+        let len = next(4).map(read_u32)?;
+        comments.push(next(len as usize)?);
+    }
+    Some((vendor, comments))
+}
+
+fn parse_vorbis_comments(data: &[u8]) -> Vec<(String, String)> {
+    let Some((_, comments)) = split_vorbis_comments(data) else {
+        return vec![];
+    };
+    comments
+        .into_iter()
+        .filter_map(|comment| {
+            let comment = String::from_utf8_lossy(comment);
+            let (key, value) = comment.split_once('=')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+fn is_replay_gain_key(key: &str) -> bool {
+    KEYS.iter().any(|k| k.eq_ignore_ascii_case(key))
+}
+
+/// Whether a raw `KEY=value` Vorbis comment is one of the ReplayGain tags.
+fn is_replay_gain_comment(comment: &[u8]) -> bool {
+    let Some(end) = comment.iter().position(|b| *b == b'=') else {
+        return false;
+    };
+    std::str::from_utf8(&comment[..end]).is_ok_and(is_replay_gain_key)
+}
+
+fn to_syncsafe(n: usize) -> [u8; 4] {
+    [(n >> 21) as u8 & 0x7f, (n >> 14) as u8 & 0x7f, (n >> 7) as u8 & 0x7f, n as u8 & 0x7f]
+}
+
+fn write_id3(data: &[u8], tags: &[(&str, String)]) -> io::Result<Vec<u8>> {
+    let mut version = 4;
+    let mut frames = vec![];
+    let mut audio = data;
+    if data.starts_with(b"ID3") {
+        let header = data.get(..10).ok_or_else(|| invalid("ID3 header too short"))?;
+        version = header[3];
+        if !matches!(version, 3 | 4) || header[5] != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only ID3v2.3 and 2.4 tags without flags can be changed",
+            ));
+        }
+        let end = 10 + syncsafe(&header[6..10]);
+        let body = data.get(10..end).ok_or_else(|| invalid("ID3 tag too short"))?;
+        audio = &data[end..];
+
+        let mut pos = 0;
+        while pos + 10 <= body.len() && body[pos] != 0 {
+            let size = match version {
+                3 => big_endian(&body[pos + 4..pos + 8]),
+                _ => syncsafe(&body[pos + 4..pos + 8]),
+            };
+            let Some(frame) = body.get(pos..pos + 10 + size) else {
+                return Err(invalid("ID3 frame too long"));
+            };
+            pos += 10 + size;
+            let is_replay_gain = &frame[..4] == b"TXXX"
+                && frame[8..10] == [0, 0]
+                && parse_txxx(&frame[10..]).is_some_and(|(key, _)| is_replay_gain_key(&key));
+            if !is_replay_gain {
+                frames.extend_from_slice(frame);
+            }
+        }
+    }
+
+    for (key, value) in tags {
+        // latin-1, which is fine for these
+        let mut content = vec![0];
+        content.extend_from_slice(key.as_bytes());
+        content.push(0);
+        content.extend_from_slice(value.as_bytes());
+        frames.extend_from_slice(b"TXXX");
+        if version == 3 {
+            frames.extend_from_slice(&(content.len() as u32).to_be_bytes());
+        } else {
+            frames.extend_from_slice(&to_syncsafe(content.len()));
+        }
+        frames.extend_from_slice(&[0, 0]);
+        frames.extend_from_slice(&content);
+    }
+
+    let size = frames.len() + PADDING;
+    if size >= 1 << 28 {
+        return Err(invalid("ID3 tag too big"));
+    }
+    let mut out = Vec::with_capacity(10 + size + audio.len());
+    out.extend_from_slice(&[b'I', b'D', b'3', version, 0, 0]);
+    out.extend_from_slice(&to_syncsafe(size));
+    out.extend_from_slice(&frames);
+    out.resize(out.len() + PADDING, 0);
+    out.extend_from_slice(audio);
+    Ok(out)
+}
+
+fn write_flac(data: &[u8], tags: &[(&str, String)]) -> io::Result<Vec<u8>> {
+    let mut blocks = vec![];
+    let mut pos = 4;
+    loop {
+        let header = data
+            .get(pos..pos + 4)
+            .ok_or_else(|| invalid("FLAC metadata too short"))?;
+        let len = big_endian(&header[1..4]);
+        let block = data
+            .get(pos + 4..pos + 4 + len)
+            .ok_or_else(|| invalid("FLAC metadata too short"))?;
+        pos += 4 + len;
+        // padding is added again at the end
+        if header[0] & 0x7f != 1 {
+            blocks.push((header[0] & 0x7f, block.to_vec()));
+        }
+        if header[0] & 0x80 != 0 {
+            break;
+        }
+    }
+
+    let existing = blocks.iter().position(|(kind, _)| *kind == 4);
+    // the other comments are kept as they are, they don't have to be valid UTF-8
+    let (vendor, mut comments) = match existing {
+        Some(i) => {
+            let (vendor, comments) = split_vorbis_comments(&blocks[i].1)
+                .ok_or_else(|| invalid("FLAC comments too short"))?;
+            let comments: Vec<Vec<u8>> = comments
+                .into_iter()
+                .filter(|comment| !is_replay_gain_comment(comment))
+                .map(<[u8]>::to_vec)
+                .collect();
+            (vendor.to_vec(), comments)
+        }
+        None => (b"mp3-player".to_vec(), vec![]),
+    };
+    comments.extend(
+        tags.iter()
+            .map(|(key, value)| format!("{key}={value}").into_bytes()),
+    );
+
+    let mut block = vec![];
+    block.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    block.extend_from_slice(&vendor);
+    block.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for comment in comments {
+        block.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        block.extend_from_slice(&comment);
+    }
+    match existing {
+        Some(i) => blocks[i].1 = block,
+        // right after the stream info, which has to be first
+        None => blocks.insert(1.min(blocks.len()), (4, block)),
+    }
+    blocks.push((1, vec![0; PADDING]));
+
+    let mut out = b"fLaC".to_vec();
+    let count = blocks.len();
+    for (i, (kind, block)) in blocks.into_iter().enumerate() {
+        if block.len() > 0xff_ffff {
+            return Err(invalid("FLAC metadata block too big"));
+        }
+        let last = if i + 1 == count { 0x80 } else { 0 };
+        out.push(kind | last);
+        out.extend_from_slice(&(block.len() as u32).to_be_bytes()[1..]);
+        out.extend_from_slice(&block);
+    }
+    out.extend_from_slice(&data[pos..]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    const AUDIO: &[u8] = b"\xff\xfb\x90\x64\x00\x0f\xf0 not really audio \xff\x00";
+
+    /// A file in the temp folder, removed again when this is dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, data: &[u8]) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("mp3-player-test-{}-{name}", std::process::id()));
+            fs::write(&path, data).unwrap();
+            Self(path)
+        }
+
+        fn data(&self) -> Vec<u8> {
+            fs::read(&self.0).unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn frame(version: u8, id: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut frame = id.to_vec();
+        if version == 3 {
+            frame.extend_from_slice(&(content.len() as u32).to_be_bytes());
+        } else {
+            frame.extend_from_slice(&to_syncsafe(content.len()));
+        }
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(content);
+        frame
+    }
+
+    fn latin1_txxx(version: u8, key: &str, value: &str) -> Vec<u8> {
+        frame(version, b"TXXX", format!("\0{key}\0{value}").as_bytes())
+    }
+
+    fn id3(version: u8, frames: &[Vec<u8>]) -> Vec<u8> {
+        let body = frames.concat();
+        let mut tag = vec![b'I', b'D', b'3', version, 0, 0];
+        tag.extend_from_slice(&to_syncsafe(body.len() + 16));
+        tag.extend_from_slice(&body);
+        tag.resize(tag.len() + 16, 0);
+        tag
+    }
+
+    fn vorbis_comments(comments: &[&[u8]]) -> Vec<u8> {
+        let mut block = vec![];
+        block.extend_from_slice(&6u32.to_le_bytes());
+        block.extend_from_slice(b"vendor");
+        block.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            block.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            block.extend_from_slice(comment);
+        }
+        block
+    }
+
+    fn flac(comments: &[&[u8]]) -> Vec<u8> {
+        let mut data = b"fLaC".to_vec();
+        // stream info, the contents don't matter here
+        data.extend_from_slice(&[0, 0, 0, 34]);
+        data.extend_from_slice(&[7; 34]);
+        let block = vorbis_comments(comments);
+        data.push(0x84);
+        data.extend_from_slice(&(block.len() as u32).to_be_bytes()[1..]);
+        data.extend_from_slice(&block);
+        data
+    }
+
+    fn gain() -> ReplayGain {
+        ReplayGain {
+            track_gain: Some(-6.5),
+            track_peak: Some(0.95),
+            album_gain: Some(-7.25),
+            album_peak: Some(1.0),
+        }
+    }
+
+    fn contains(data: &[u8], part: &[u8]) -> bool {
+        data.windows(part.len()).any(|window| window == part)
+    }
+
+    fn check_id3_round_trip(version: u8) {
+        let title = frame(version, b"TIT2", b"\x03T\xc3\xaftle");
+        let other = latin1_txxx(version, "MusicBrainz Album Id", "1234");
+        let old_gain = latin1_txxx(version, "replaygain_track_gain", "+1.00 dB");
+        let mut data = id3(version, &[title.clone(), old_gain, other.clone()]);
+        data.extend_from_slice(AUDIO);
+        let file = TempFile::new(&format!("id3v2.{version}.mp3"), &data);
+
+        gain().write(&file.0).unwrap();
+        gain().write(&file.0).unwrap();
+
+        let data = file.data();
+        assert_eq!(data[3], version);
+        assert!(contains(&data, &title));
+        assert!(contains(&data, &other));
+        let tag_end = 10 + syncsafe(&data[6..10]);
+        assert_eq!(&data[tag_end..], AUDIO);
+
+        let tags = read_id3(&mut &data[..]).unwrap();
+        for key in KEYS {
+            let count = tags
+                .iter()
+                .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+                .count();
+            assert_eq!(count, 1, "{key}");
+        }
+        assert!(tags.contains(&("MusicBrainz Album Id".to_string(), "1234".to_string())));
+        assert_eq!(ReplayGain::read(&file.0).unwrap(), gain());
+    }
+
+    #[test]
+    fn writes_id3v23_tags() {
+        check_id3_round_trip(3);
+    }
+
+    #[test]
+    fn writes_id3v24_tags() {
+        check_id3_round_trip(4);
+    }
+
+    #[test]
+    fn writes_id3_tags_into_untagged_mp3s() {
+        let file = TempFile::new("untagged.mp3", AUDIO);
+        gain().write(&file.0).unwrap();
+        let data = file.data();
+        assert_eq!(&data[10 + syncsafe(&data[6..10])..], AUDIO);
+        assert_eq!(ReplayGain::read(&file.0).unwrap(), gain());
+    }
+
+    #[test]
+    fn writes_flac_comments() {
+        // not valid UTF-8, and one without a value
+        let title: &[u8] = b"TITLE=caf\xe9";
+        let broken: &[u8] = b"NOT A TAG";
+        let artist: &[u8] = b"ARTIST=Someone";
+        let mut data = flac(&[title, b"replaygain_album_gain=+2.00 dB", broken, artist]);
+        data.extend_from_slice(AUDIO);
+        let file = TempFile::new("comments.flac", &data);
+
+        gain().write(&file.0).unwrap();
+        gain().write(&file.0).unwrap();
+
+        let data = file.data();
+        let mut pos = 4;
+        let mut comments = None;
+        loop {
+            let header = &data[pos..pos + 4];
+            let len = big_endian(&header[1..4]);
+            if header[0] & 0x7f == 4 {
+                let (vendor, raw) = split_vorbis_comments(&data[pos + 4..pos + 4 + len]).unwrap();
+                assert_eq!(vendor, b"vendor");
+                comments = Some(raw.into_iter().map(<[u8]>::to_vec).collect::<Vec<_>>());
+            }
+            pos += 4 + len;
+            if header[0] & 0x80 != 0 {
+                break;
+            }
+        }
+        assert_eq!(&data[pos..], AUDIO);
+
+        let comments = comments.unwrap();
+        assert_eq!(
+            &comments[..3],
+            &[title.to_vec(), broken.to_vec(), artist.to_vec()]
+        );
+        for key in KEYS {
+            let count = comments
+                .iter()
+                .filter(|comment| comment.starts_with(format!("{key}=").as_bytes()))
+                .count();
+            assert_eq!(count, 1, "{key}");
+        }
+        assert_eq!(comments.len(), 3 + KEYS.len());
+        assert_eq!(ReplayGain::read(&file.0).unwrap(), gain());
+    }
+
+    fn utf16(text: &str) -> Vec<u8> {
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        bytes
+    }
+
+    fn tag(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn parses_latin1_txxx() {
+        let parsed = parse_txxx(b"\0REPLAYGAIN_TRACK_GAIN\0-6.5 dB\0");
+        assert_eq!(parsed, Some(tag("REPLAYGAIN_TRACK_GAIN", "-6.5 dB")));
+        let parsed = parse_txxx(b"\0caf\xe9\0cr\xe8me");
+        assert_eq!(parsed, Some(tag("caf\u{e9}", "cr\u{e8}me")));
+    }
+
+    #[test]
+    fn parses_utf16_txxx() {
+        let mut data = vec![1];
+        data.extend(utf16("replaygain_album_peak"));
+        data.extend([0, 0]);
+        data.extend(utf16("0.988"));
+        assert_eq!(
+            parse_txxx(&data),
+            Some(tag("replaygain_album_peak", "0.988"))
+        );
+
+        // big endian without a BOM
+        let mut data = vec![2];
+        data.extend("k\u{e9}y".encode_utf16().flat_map(u16::to_be_bytes));
+        data.extend([0, 0]);
+        data.extend("1".encode_utf16().flat_map(u16::to_be_bytes));
+        assert_eq!(parse_txxx(&data), Some(tag("k\u{e9}y", "1")));
+    }
+
+    #[test]
+    fn garbage_txxx_is_ignored() {
+        assert_eq!(parse_txxx(b""), None);
+        assert_eq!(parse_txxx(b"\0no terminator"), None);
+        assert_eq!(parse_txxx(&[1, b'k', 0, b'v']), None);
+    }
+
+    #[test]
+    fn reads_frame_sizes_of_both_versions() {
+        // more than 127 bytes, so a syncsafe and a big endian size differ
+        let value = format!("-6.5 dB{}", " ".repeat(200));
+        for version in [3, 4] {
+            let title = frame(version, b"TIT2", &[b'x'; 150]);
+            let gain = latin1_txxx(version, "REPLAYGAIN_TRACK_GAIN", &value);
+            let data = id3(version, &[title, gain]);
+            let tags = read_id3(&mut &data[..]).unwrap();
+            assert_eq!(
+                tags,
+                vec![tag("REPLAYGAIN_TRACK_GAIN", &value)],
+                "v2.{version}"
+            );
+            assert_eq!(ReplayGain::from_tags(tags).track_gain, Some(-6.5));
+        }
+    }
+
+    #[test]
+    fn broken_id3_tags_do_not_panic() {
+        let gain = latin1_txxx(4, "REPLAYGAIN_TRACK_GAIN", "-6.5 dB");
+        let mut data = id3(4, std::slice::from_ref(&gain));
+        // a frame that claims to be longer than the tag
+        data.extend_from_slice(b"TXXX\x7f\x7f\x7f\x7f\0\0\0");
+        let body_len = data.len() - 10;
+        data[6..10].copy_from_slice(&to_syncsafe(body_len));
+        let tags = read_id3(&mut &data[..]).unwrap();
+        assert_eq!(tags, vec![tag("REPLAYGAIN_TRACK_GAIN", "-6.5 dB")]);
+
+        // the tag itself is cut off
+        assert!(read_id3(&mut &data[..20]).is_err());
+
+        let mut garbage = b"ID3\x04\x00\x40\0\0\0\x08".to_vec();
+        garbage.extend_from_slice(&[0xff; 8]);
+        assert_eq!(read_id3(&mut &garbage[..]).unwrap(), vec![]);
+        for len in 0..gain.len() {
+            let data = id3(3, &[gain[..len].to_vec()]);
+            _ = read_id3(&mut &data[..]);
+        }
+    }
+
+    #[test]
+    fn reads_flac_comments() {
+        let data = flac(&[
+            b"title=Song",
+            b"replaygain_track_gain=-6.5 dB",
+            b"Replaygain_Track_Peak=0.5",
+        ]);
+        let tags = read_flac(&mut &data[..]).unwrap();
+        assert_eq!(tags.len(), 3);
+        let gain = ReplayGain::from_tags(tags);
+        assert_eq!(gain.track_gain, Some(-6.5));
+        assert_eq!(gain.track_peak, Some(0.5));
+    }
+
+    #[test]
+    fn reads_ogg_vorbis_comments() {
+        // long enough that the comment packet needs two lacing values
+        let long = format!("description={}", "x".repeat(300));
+        let mut comments = b"\x03vorbis".to_vec();
+        comments.extend(vorbis_comments(&[
+            long.as_bytes(),
+            b"replaygain_album_gain=+1.25 dB",
+        ]));
+        let ident = [b"\x01vorbis".as_slice(), &[0; 23]].concat();
+
+        let mut data = b"OggS".to_vec();
+        data.resize(26, 0);
+        let mut lacing = vec![ident.len() as u8];
+        lacing.extend(std::iter::repeat_n(255, comments.len() / 255));
+        lacing.push((comments.len() % 255) as u8);
+        data.push(lacing.len() as u8);
+        data.extend(lacing);
+        data.extend(ident);
+        data.extend(comments);
+
+        let tags = read_ogg(&mut &data[..]).unwrap();
+        assert_eq!(tags[0].1.len(), 300);
+        assert_eq!(ReplayGain::from_tags(tags).album_gain, Some(1.25));
+    }
+
+    #[test]
+    fn parses_gain_values() {
+        let gain = ReplayGain::from_tags(vec![
+            tag("REPLAYGAIN_TRACK_GAIN", "-6.5 dB"),
+            tag("replaygain_album_gain", " +2.00dB "),
+            tag("REPLAYGAIN_TRACK_PEAK", "0.988"),
+            tag("REPLAYGAIN_ALBUM_PEAK", "loud"),
+        ]);
+        assert_eq!(gain.track_gain, Some(-6.5));
+        assert_eq!(gain.album_gain, Some(2.0));
+        assert_eq!(gain.track_peak, Some(0.988));
+        assert_eq!(gain.album_peak, None);
+        assert_eq!(
+            ReplayGain::from_tags(vec![tag("REPLAYGAIN_TRACK_GAIN", "-3 db")]).track_gain,
+            Some(-3.0)
+        );
+        assert_eq!(
+            ReplayGain::from_tags(vec![tag("REPLAYGAIN_TRACK_GAIN", "NaN dB")]).track_gain,
+            None
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! Positions in long files like audiobooks and mixes, so they continue where they were left off
+//! instead of starting over.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::files::write_atomic;
+
+/// Positions this close to the start aren't worth remembering.
+const MIN_POSITION: f32 = 10.0;
+/// Files stopped this close to their end count as finished and start from the beginning again.
+const FINISHED_MARGIN: f32 = 30.0;
+
+#[derive(Default)]
+pub struct ResumePositions {
+    /// only files at least this many seconds long are remembered, 0 turns it off
+    pub min_length: f32,
+    positions: HashMap<PathBuf, f32>,
+}
+
+impl ResumePositions {
+    pub fn get(&self, path: &Path) -> Option<f32> {
+        self.positions.get(path).copied()
+    }
+
+    /// Remembers where a file of `length` seconds stopped playing, or forgets it when it's
+    /// finished or barely started.
+    pub fn remember(&mut self, path: &Path, position: f32, length: f32) {
+        if self.min_length <= 0.0 || length < self.min_length {
+            return;
+        }
+        if position < MIN_POSITION || position > length - FINISHED_MARGIN {
+            self.positions.remove(path);
+        } else {
+            self.positions.insert(path.to_path_buf(), position);
+        }
+    }
+
+    pub fn forget(&mut self, path: &Path) {
+        self.positions.remove(path);
+    }
+
+    /// Reads positions written by `save`, invalid lines are skipped. `min_length` stays 0.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut me = Self::default();
+        for line in fs::read_to_string(path)?.lines() {
+            let Some((position, file)) = line.split_once('\t') else {
+                continue;
+            };
+            if let Some(position) = position.parse::<f32>().ok().filter(|v| v.is_finite()) {
+                me.positions.insert(PathBuf::from(file), position);
+            }
+        }
+        Ok(me)
+    }
+
+    /// Writes one `position<tab>path` line per file.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut contents = String::with_capacity(self.positions.len() * 60);
+        for (file, position) in &self.positions {
+            let Some(file) = file.to_str().filter(|file| !file.contains('\n')) else {
+                continue;
+            };
+            contents.push_str(&format!("{position}\t{file}\n"));
+        }
+
+        write_atomic(path, contents)
+    }
+}
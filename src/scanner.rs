@@ -0,0 +1,325 @@
+//! Measures the loudness of songs without ReplayGain tags in a background thread, so they can be
+//! played with a gain as well.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread,
+};
+
+use crate::{
+    audio::AudioBackend,
+    files::{modified, write_atomic, DecodeFn},
+    loudness::{Analysis, Analyzer},
+    replaygain::ReplayGain,
+    song::Playlist,
+};
+
+enum Event {
+    Scanned(Vec<(PathBuf, ReplayGain)>),
+    Failed(PathBuf, String),
+}
+
+/// Runs the analysis and hands the results to the playlist, see `update`.
+pub struct LoudnessScanner {
+    jobs: Sender<PathBuf>,
+    events: Receiver<Event>,
+    queued: HashSet<PathBuf>,
+    done: Arc<AtomicUsize>,
+    playlist_version: Option<u64>,
+}
+
+impl LoudnessScanner {
+    /// Starts the background thread. `decode` is usually the one of the `AudioBackend` in use,
+    /// results are cached in the file at `cache_path`. With `write_tags`, the results are also
+    /// written into the songs as ReplayGain tags.
+    pub fn spawn(decode: DecodeFn, cache_path: Option<PathBuf>, write_tags: bool) -> Self {
+        let (jobs, job_receiver) = mpsc::channel();
+        let (event_sender, events) = mpsc::channel();
+        let done = Arc::new(AtomicUsize::new(0));
+
+        let mut worker = Worker {
+            decode,
+            cache: Cache::load(cache_path),
+            write_tags,
+            albums: HashMap::new(),
+            unwritten: BTreeSet::new(),
+            written: HashMap::new(),
+            events: event_sender,
+            done: done.clone(),
+        };
+        thread::spawn(move || worker.run(job_receiver));
+
+        Self {
+            jobs,
+            events,
+            queued: HashSet::new(),
+            done,
+            playlist_version: None,
+        }
+    }
+
+    /// Queues the songs that weren't queued before.
+    pub fn scan<'a, I: IntoIterator<Item = &'a Path>>(&mut self, paths: I) {
+        for path in paths {
+            if self.queued.insert(path.to_path_buf()) {
+                _ = self.jobs.send(path.to_path_buf());
+            }
+        }
+    }
+
+    /// Queues new songs of the playlist and stores finished results in it. Returns the errors
+    /// since the last call.
+    pub fn update<B: AudioBackend>(&mut self, playlist: &mut Playlist<B>) -> Vec<String> {
+        if self.playlist_version != Some(playlist.version()) {
+            self.playlist_version = Some(playlist.version());
+            let songs = playlist.get_songs();
+            self.scan(songs.iter().map(|song| song.path()));
+        }
+
+        let mut errors = vec![];
+        for event in self.events.try_iter() {
+            match event {
+                Event::Scanned(gains) => playlist.scanned_replay_gain.extend(gains),
+                Event::Failed(path, err) => {
+                    errors.push(format!("Failed to analyze {}: {err}", path.display()))
+                }
+            }
+        }
+        errors
+    }
+
+    /// Analyzed and queued songs, as long as there are songs left.
+    pub fn progress(&self) -> Option<(usize, usize)> {
+        let done = self.done.load(Ordering::Relaxed);
+        if done < self.queued.len() {
+            Some((done, self.queued.len()))
+        } else {
+            None
+        }
+    }
+}
+
+struct Worker {
+    decode: DecodeFn,
+    cache: Cache,
+    write_tags: bool,
+    /// all analyzed songs by folder, the album gain is calculated over each folder
+    albums: HashMap<PathBuf, Vec<(PathBuf, Analysis)>>,
+    /// folders whose tags are written once nothing is queued anymore, see `write_album_tags`
+    unwritten: BTreeSet<PathBuf>,
+    /// the gains last written into each song
+    written: HashMap<PathBuf, ReplayGain>,
+    events: Sender<Event>,
+    done: Arc<AtomicUsize>,
+}
+
+impl Worker {
+    fn run(&mut self, jobs: Receiver<PathBuf>) {
+        // take everything that's queued at once, so songs of the same album are grouped
+        let mut next = jobs.recv().ok();
+        while let Some(path) = next {
+            let mut by_folder: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+            for path in std::iter::once(path).chain(jobs.try_iter()) {
+                let folder = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                by_folder.entry(folder).or_default().push(path);
+            }
+
+            for (folder, paths) in by_folder {
+                let mut changed = false;
+                for path in paths {
+                    match self.analyze(&path) {
+                        Ok(Some(analysis)) => {
+                            let album = self.albums.entry(folder.clone()).or_default();
+                            album.retain(|(other, _)| *other != path);
+                            album.push((path, analysis));
+                            changed = true;
+                        }
+                        Ok(None) => {}
+                        Err(err) => _ = self.events.send(Event::Failed(path, err)),
+                    }
+                    self.done.fetch_add(1, Ordering::Relaxed);
+                }
+                if changed {
+                    self.finish_album(&folder);
+                }
+            }
+
+            next = jobs.try_recv().ok();
+            if next.is_none() {
+                // the rest of an album may still have been on its way
+                self.write_album_tags();
+            }
+            if let Err(err) = self.cache.save() {
+                eprintln!("Failed to save the loudness cache: {err}");
+            }
+            if next.is_none() {
+                next = jobs.recv().ok();
+            }
+        }
+    }
+
+    /// `None` for songs that don't need to be analyzed because they are tagged, or that are
+    /// silent.
+    fn analyze(&mut self, path: &Path) -> Result<Option<Analysis>, String> {
+        if ReplayGain::read(path).is_ok_and(|tags| !tags.is_empty()) {
+            return Ok(None);
+        }
+        let mtime = modified(path).map_err(|err| err.to_string())?;
+        if let Some((cached_mtime, analysis)) = self.cache.entries.get(path) {
+            if *cached_mtime == mtime {
+                return Ok(Some(analysis.clone()));
+            }
+        }
+
+        let mut analyzer = Analyzer::new();
+        (self.decode)(path, &mut analyzer).map_err(|err| err.to_string())?;
+        let Some(analysis) = analyzer.finish() else {
+            return Ok(None);
+        };
+        self.cache.insert(path, mtime, analysis.clone());
+        Ok(Some(analysis))
+    }
+
+    /// The gains of the analyzed songs in `folder`.
+    fn album_gains(&self, folder: &Path) -> Vec<(PathBuf, ReplayGain)> {
+        let songs = &self.albums[folder];
+        let album = Analysis::combine(songs.iter().map(|(_, analysis)| analysis));
+        songs
+            .iter()
+            .map(|(path, analysis)| {
+                let gain = ReplayGain {
+                    track_gain: Some(analysis.gain() as f32),
+                    track_peak: Some(analysis.peak as f32),
+                    album_gain: album.as_ref().map(|album| album.gain() as f32),
+                    album_peak: album.as_ref().map(|album| album.peak as f32),
+                };
+                (path.clone(), gain)
+            })
+            .collect()
+    }
+
+    fn finish_album(&mut self, folder: &Path) {
+        if self.write_tags {
+            self.unwritten.insert(folder.to_path_buf());
+        }
+        _ = self.events.send(Event::Scanned(self.album_gains(folder)));
+    }
+
+    /// Writes the gains of the songs in the folders that changed since the last call as tags.
+    /// Each new song changes the album gain, so this waits until the queue is empty instead of
+    /// rewriting the whole album after every song. Songs whose gains stayed the same are skipped.
+    fn write_album_tags(&mut self) {
+        for folder in std::mem::take(&mut self.unwritten) {
+            for (path, gain) in self.album_gains(&folder) {
+                if self.written.get(&path) == Some(&gain) {
+                    continue;
+                }
+                let written = gain.write(&path).and_then(|_| modified(&path));
+                match written {
+                    // the file changed, but the analysis didn't
+                    Ok(mtime) => {
+                        if let Some((_, analysis)) = self.cache.entries.remove(&path) {
+                            self.cache.insert(&path, mtime, analysis);
+                        }
+                        self.written.insert(path, gain);
+                    }
+                    Err(err) => {
+                        let err = format!("failed to write tags: {err}");
+                        _ = self.events.send(Event::Failed(path, err));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Analyses by path and modification time, stored as lines of
+/// `mtime<tab>loudness<tab>peak<tab>histogram<tab>path` where the histogram is a list of
+/// `bin:count` separated by spaces.
+struct Cache {
+    path: Option<PathBuf>,
+    entries: HashMap<PathBuf, (u64, Analysis)>,
+    changed: bool,
+}
+
+impl Cache {
+    fn load(path: Option<PathBuf>) -> Self {
+        let mut entries = HashMap::new();
+        let contents = path.as_ref().and_then(|path| fs::read_to_string(path).ok());
+        for line in contents.as_deref().unwrap_or_default().lines() {
+            if let Some((song, entry)) = Self::parse_line(line) {
+                entries.insert(song, entry);
+            }
+        }
+        Self {
+            path,
+            entries,
+            changed: false,
+        }
+    }
+
+    fn parse_line(line: &str) -> Option<(PathBuf, (u64, Analysis))> {
+        let mut fields = line.splitn(5, '\t');
+        let mtime = fields.next()?.parse().ok()?;
+        let loudness = fields.next()?.parse().ok()?;
+        let peak = fields.next()?.parse().ok()?;
+        let histogram = fields
+            .next()?
+            .split_whitespace()
+            .map(|bin| {
+                let (bin, count) = bin.split_once(':')?;
+                Some((bin.parse().ok()?, count.parse().ok()?))
+            })
+            .collect::<Option<_>>()?;
+        let path = PathBuf::from(fields.next()?);
+        let analysis = Analysis {
+            loudness,
+            peak,
+            histogram,
+        };
+        Some((path, (mtime, analysis)))
+    }
+
+    fn insert(&mut self, path: &Path, mtime: u64, analysis: Analysis) {
+        self.entries.insert(path.to_path_buf(), (mtime, analysis));
+        self.changed = true;
+    }
+
+    fn save(&mut self) -> io::Result<()> {
+        let Some(ref path) = self.path else {
+            return Ok(());
+        };
+        if !self.changed {
+            return Ok(());
+        }
+
+        let mut contents = String::new();
+        for (song, (mtime, analysis)) in &self.entries {
+            let Some(song) = song.to_str().filter(|song| !song.contains('\n')) else {
+                continue;
+            };
+            let histogram: Vec<String> = analysis
+                .histogram
+                .iter()
+                .map(|(bin, count)| format!("{bin}:{count}"))
+                .collect();
+            contents.push_str(&format!(
+                "{mtime}\t{:?}\t{:?}\t{}\t{song}\n",
+                analysis.loudness,
+                analysis.peak,
+                histogram.join(" ")
+            ));
+        }
+
+        write_atomic(path, contents)?;
+        self.changed = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use mp3_player::{
+    audio::AudioBackend,
+    channels::ChannelSettings,
+    files::write_atomic,
+    song::{Playlist, RepeatBehavior, ShuffleBehavior},
+};
+
+use crate::dirs;
+
+/// Everything needed to continue where the player was closed.
+///
+/// It's stored as `key=value` lines, with one `song=` line per playlist entry:
+/// ```text
+/// volume=0.8
+/// speed=1.25
+/// repeat=all
+/// shuffle=on
+/// balance=-0.2
+/// mono=false
+/// swap=false
+/// invert_left=false
+/// invert_right=false
+/// current=3
+/// position=12.5
+/// playing=true
+/// scroll=-120
+/// selected=3
+/// song=/home/user/Music/author/name.mp3
+/// ```
+pub struct Session {
+    pub songs: Vec<PathBuf>,
+    pub current: Option<usize>,
+    pub position: f32,
+    pub playing: bool,
+    pub volume: f32,
+    pub speed: f32,
+    pub repeat_behavior: RepeatBehavior,
+    pub shuffle_behavior: ShuffleBehavior,
+    pub channels: ChannelSettings,
+    pub scroll_index: f32,
+    pub current_selected: usize,
+}
+
+/// `$XDG_STATE_HOME/mp3-player/equalizer`, see `EqualizerSettings::save`.
+pub fn equalizer_path() -> Option<PathBuf> {
+    Some(dirs::state_home()?.join("mp3-player").join("equalizer"))
+}
+
+/// `$XDG_STATE_HOME/mp3-player/resume`, see `ResumePositions::save`.
+pub fn resume_path() -> Option<PathBuf> {
+    Some(dirs::state_home()?.join("mp3-player").join("resume"))
+}
+
+/// `$XDG_STATE_HOME/mp3-player/plays`, see `PlayStats::save`.
+pub fn plays_path() -> Option<PathBuf> {
+    Some(dirs::state_home()?.join("mp3-player").join("plays"))
+}
+
+impl Session {
+    /// `$XDG_STATE_HOME/mp3-player/session`
+    pub fn path() -> Option<PathBuf> {
+        Some(dirs::state_home()?.join("mp3-player").join("session"))
+    }
+
+    pub fn from_playlist<B: AudioBackend>(playlist: &Playlist<B>, audio: &B) -> Self {
+        Self {
+            songs: playlist
+                .get_songs()
+                .iter()
+                .map(|song| song.path().to_path_buf())
+                .collect(),
+            current: playlist.currently_playing_id(),
+            position: playlist.music_length_played(audio),
+            playing: playlist.is_music_playing(audio),
+            // don't save the volume halfway through the sleep timer's fade out
+            volume: playlist
+                .sleep_timer()
+                .and_then(|timer| timer.volume_before_fade())
+                .unwrap_or(audio.master_volume()),
+            speed: playlist.speed(),
+            repeat_behavior: playlist.repeat_behavior,
+            shuffle_behavior: playlist.shuffle_behavior(),
+            channels: playlist.channels(),
+            scroll_index: playlist.__render_scroll_index,
+            current_selected: playlist.__render_current_selected,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut str = String::with_capacity(self.songs.len() * 40 + 100);
+        str.push_str(&format!("volume={}\n", self.volume));
+        str.push_str(&format!("speed={}\n", self.speed));
+        str.push_str(&format!("repeat={}\n", self.repeat_behavior.as_str()));
+        str.push_str(&format!("shuffle={}\n", self.shuffle_behavior.as_str()));
+        str.push_str(&format!("balance={}\n", self.channels.balance));
+        str.push_str(&format!("mono={}\n", self.channels.mono));
+        str.push_str(&format!("swap={}\n", self.channels.swap));
+        str.push_str(&format!("invert_left={}\n", self.channels.invert_left));
+        str.push_str(&format!("invert_right={}\n", self.channels.invert_right));
+        if let Some(current) = self.current {
+            str.push_str(&format!("current={current}\n"));
+            str.push_str(&format!("position={}\n", self.position));
+            str.push_str(&format!("playing={}\n", self.playing));
+        }
+        str.push_str(&format!("scroll={}\n", self.scroll_index));
+        str.push_str(&format!("selected={}\n", self.current_selected));
+        for song in &self.songs {
+            match song.to_str() {
+                Some(song) if !song.contains('\n') => {
+                    str.push_str("song=");
+                    str.push_str(song);
+                    str.push('\n');
+                }
+                _ => {}
+            }
+        }
+
+        write_atomic(path, str)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let str = fs::read_to_string(path)?;
+        let mut me = Self {
+            songs: vec![],
+            current: None,
+            position: 0.0,
+            playing: true,
+            volume: 1.0,
+            speed: 1.0,
+            repeat_behavior: RepeatBehavior::Normal,
+            shuffle_behavior: ShuffleBehavior::Normal,
+            channels: ChannelSettings::default(),
+            scroll_index: 0.0,
+            current_selected: 0,
+        };
+
+        // unknown keys and invalid values are skipped, so older and newer versions can read it
+        for line in str.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            match key {
+                "volume" => {
+                    if let Ok(volume) = value.parse::<f32>() {
+                        me.volume = volume.clamp(0.0, 1.0);
+                    }
+                }
+                "speed" => {
+                    if let Some(speed) = value.parse::<f32>().ok().filter(|v| v.is_finite()) {
+                        me.speed = speed;
+                    }
+                }
+                "repeat" => {
+                    if let Ok(repeat_behavior) = value.parse() {
+                        me.repeat_behavior = repeat_behavior;
+                    }
+                }
+                "shuffle" => me.shuffle_behavior = value.parse().unwrap_or(ShuffleBehavior::Normal),
+                "balance" => {
+                    if let Some(balance) = value.parse::<f32>().ok().filter(|v| v.is_finite()) {
+                        me.channels.balance = balance.clamp(-1.0, 1.0);
+                    }
+                }
+                "mono" => me.channels.mono = value == "true",
+                "swap" => me.channels.swap = value == "true",
+                "invert_left" => me.channels.invert_left = value == "true",
+                "invert_right" => me.channels.invert_right = value == "true",
+                "current" => me.current = value.parse().ok(),
+                "position" => me.position = value.parse().unwrap_or(0.0),
+                "playing" => me.playing = value != "false",
+                "scroll" => me.scroll_index = value.parse().unwrap_or(0.0),
+                "selected" => me.current_selected = value.parse().unwrap_or(0),
+                "song" => me.songs.push(PathBuf::from(value)),
+                _ => {}
+            }
+        }
+
+        Ok(me)
+    }
+
+    /// Only the volume, speed, repeat and shuffle mode and channel settings, for when something
+    /// else decides what to play.
+    pub fn restore_settings<B: AudioBackend>(&self, playlist: &mut Playlist<B>, audio: &mut B) {
+        audio.set_master_volume(self.volume);
+        playlist.set_speed(self.speed, audio);
+        playlist.repeat_behavior = self.repeat_behavior;
+        playlist.set_shuffle_behavior(self.shuffle_behavior);
+        playlist.set_channels(self.channels, audio);
+    }
+
+    /// Fills the playlist and continues playing where the session left off. Songs that don't
+    /// exist anymore are left out.
+    pub fn restore<B: AudioBackend>(
+        &self,
+        playlist: &mut Playlist<B>,
+        audio: &mut B,
+        screen_height: i32,
+    ) {
+        self.restore_settings(playlist, audio);
+
+        let mut current = None;
+        for (idx, song) in self.songs.iter().enumerate() {
+            let len = playlist.len();
+            if playlist.add_song_by_path(song).is_err() || playlist.len() == len {
+                continue;
+            }
+            if self.current == Some(idx) {
+                current = Some(len);
+            }
+        }
+
+        if let Some(current) = current {
+            playlist.play_ignore_err(current, audio, screen_height);
+            playlist.seek(self.position, audio);
+            if !self.playing {
+                playlist.pause(audio);
+            }
+        }
+        // after adding the songs and starting the current one, so the shuffled order has all of
+        // them and starts with the current song
+        playlist.set_shuffle_behavior(self.shuffle_behavior);
+
+        if playlist.len() > 0 {
+            playlist.__render_current_selected = self.current_selected.min(playlist.len() - 1);
+        }
+        playlist.__render_scroll_index = self.scroll_index;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mp3_player::audio_null::NullBackend;
+
+    use super::*;
+
+    #[test]
+    fn settings_are_restored_without_the_songs() {
+        let path = std::env::temp_dir()
+            .join(format!("mp3-player-test-{}-session", std::process::id()))
+            .join("session");
+        let mut session = Session {
+            songs: vec![PathBuf::from("/nonexistent/song.mp3")],
+            current: Some(0),
+            position: 12.5,
+            playing: true,
+            volume: 0.5,
+            speed: 1.25,
+            repeat_behavior: RepeatBehavior::RepeatSingle,
+            shuffle_behavior: ShuffleBehavior::Smart,
+            channels: ChannelSettings::default(),
+            scroll_index: 0.0,
+            current_selected: 0,
+        };
+        session.channels.mono = true;
+        session.save(&path).unwrap();
+        let session = Session::load(&path).unwrap();
+        _ = fs::remove_dir_all(path.parent().unwrap());
+
+        let mut playlist: Playlist<NullBackend> = Playlist::default();
+        let mut audio = NullBackend::new();
+        session.restore_settings(&mut playlist, &mut audio);
+        assert_eq!(playlist.len(), 0);
+        assert_eq!(audio.master_volume(), 0.5);
+        assert_eq!(playlist.speed(), 1.25);
+        assert!(playlist.repeat_behavior == RepeatBehavior::RepeatSingle);
+        assert!(playlist.shuffle_behavior() == ShuffleBehavior::Smart);
+        assert!(playlist.channels().mono);
+    }
+}
@@ -0,0 +1,367 @@
+//! Finds the silence at the start and end of songs in a background thread, so it can be skipped.
+//!
+//! Only the current and the next song are analyzed, and the results are cached, so there's
+//! usually no wait even for the first song of a session.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+use crate::{
+    audio::{AudioBackend, SampleSink},
+    files::{modified, write_atomic, DecodeFn},
+    song::Playlist,
+};
+
+/// Silence shorter than this is left alone, it's more likely a pause than a gap.
+const MIN_SILENCE: f32 = 0.5;
+/// Playback starts this many seconds before the first sound and ends this many after the last,
+/// so quiet attacks and decays aren't cut off.
+const MARGIN: f32 = 0.1;
+
+/// Seconds of silence at the start and end of a song.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Silence {
+    pub leading: f32,
+    pub trailing: f32,
+}
+
+/// Finds the first and last sample louder than the threshold.
+pub struct SilenceDetector {
+    /// linear amplitude
+    threshold: f32,
+    sample_rate: u32,
+    channels: usize,
+    frames: u64,
+    first_sound: Option<u64>,
+    last_sound: u64,
+}
+
+impl SilenceDetector {
+    /// `threshold` is in dB relative to full scale, like -50.
+    pub fn new(threshold: f32) -> Self {
+        Self {
+            threshold: 10f32.powf(threshold / 20.0),
+            sample_rate: 44100,
+            channels: 2,
+            frames: 0,
+            first_sound: None,
+            last_sound: 0,
+        }
+    }
+
+    /// `None` if the song is silent all the way through.
+    pub fn finish(self) -> Option<Silence> {
+        let first_sound = self.first_sound?;
+        let seconds = |frames: u64| frames as f32 / self.sample_rate as f32;
+        let skip = |silence: f32| {
+            if silence < MIN_SILENCE {
+                0.0
+            } else {
+                silence - MARGIN
+            }
+        };
+        Some(Silence {
+            leading: skip(seconds(first_sound)),
+            trailing: skip(seconds(self.frames - self.last_sound - 1)),
+        })
+    }
+}
+
+impl SampleSink for SilenceDetector {
+    fn format(&mut self, sample_rate: u32, channels: u32) {
+        self.sample_rate = sample_rate.max(1);
+        self.channels = (channels as usize).max(1);
+    }
+
+    fn samples(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            if frame.iter().any(|sample| sample.abs() > self.threshold) {
+                self.first_sound.get_or_insert(self.frames);
+                self.last_sound = self.frames;
+            }
+            self.frames += 1;
+        }
+    }
+}
+
+enum Event {
+    Found(PathBuf, Silence),
+    Failed(PathBuf, String),
+}
+
+/// Runs the detection and hands the results to the playlist, see `update`.
+pub struct SilenceScanner {
+    jobs: Sender<PathBuf>,
+    events: Receiver<Event>,
+    queued: HashSet<PathBuf>,
+}
+
+impl SilenceScanner {
+    /// Starts the background thread. `decode` is usually the one of the `AudioBackend` in use,
+    /// `threshold` is in dB relative to full scale and results are cached in the file at
+    /// `cache_path`.
+    pub fn spawn(decode: DecodeFn, threshold: f32, cache_path: Option<PathBuf>) -> Self {
+        let (jobs, job_receiver) = mpsc::channel();
+        let (event_sender, events) = mpsc::channel();
+
+        let mut worker = Worker {
+            decode,
+            threshold,
+            cache: Cache::load(cache_path),
+            events: event_sender,
+        };
+        thread::spawn(move || worker.run(job_receiver));
+
+        Self {
+            jobs,
+            events,
+            queued: HashSet::new(),
+        }
+    }
+
+    /// Queues the current and the next song if they weren't queued before and stores finished
+    /// results in the playlist. Returns the errors since the last call.
+    pub fn update<B: AudioBackend>(&mut self, playlist: &mut Playlist<B>) -> Vec<String> {
+        let songs = [playlist.currently_playing_id(), playlist.next_song_id()];
+        for idx in songs.into_iter().flatten() {
+            let Some(song) = playlist.get_songs().get(idx) else {
+                continue;
+            };
+            if self.queued.insert(song.path().to_path_buf()) {
+                _ = self.jobs.send(song.path().to_path_buf());
+            }
+        }
+
+        let mut errors = vec![];
+        for event in self.events.try_iter() {
+            match event {
+                Event::Found(path, silence) => _ = playlist.silences.insert(path, silence),
+                Event::Failed(path, err) => {
+                    errors.push(format!("Failed to find the silence in {}: {err}", path.display()))
+                }
+            }
+        }
+        errors
+    }
+}
+
+struct Worker {
+    decode: DecodeFn,
+    threshold: f32,
+    cache: Cache,
+    events: Sender<Event>,
+}
+
+impl Worker {
+    fn run(&mut self, jobs: Receiver<PathBuf>) {
+        while let Ok(path) = jobs.recv() {
+            match self.detect(&path) {
+                Ok(Some(silence)) => _ = self.events.send(Event::Found(path, silence)),
+                Ok(None) => {}
+                Err(err) => _ = self.events.send(Event::Failed(path, err)),
+            }
+            if let Err(err) = self.cache.save() {
+                eprintln!("Failed to save the silence cache: {err}");
+            }
+        }
+    }
+
+    fn detect(&mut self, path: &Path) -> Result<Option<Silence>, String> {
+        let mtime = modified(path).map_err(|err| err.to_string())?;
+        if let Some(entry) = self.cache.entries.get(path) {
+            if entry.mtime == mtime && entry.threshold == self.threshold {
+                return Ok(entry.silence);
+            }
+        }
+
+        let mut detector = SilenceDetector::new(self.threshold);
+        (self.decode)(path, &mut detector).map_err(|err| err.to_string())?;
+        let silence = detector.finish();
+        let entry = CacheEntry {
+            mtime,
+            threshold: self.threshold,
+            silence,
+        };
+        self.cache.entries.insert(path.to_path_buf(), entry);
+        self.cache.changed = true;
+        Ok(silence)
+    }
+}
+
+struct CacheEntry {
+    mtime: u64,
+    /// the threshold it was detected with, changing it detects again
+    threshold: f32,
+    /// `None` for silent songs
+    silence: Option<Silence>,
+}
+
+/// Results by path, stored as lines of `mtime<tab>threshold<tab>leading<tab>trailing<tab>path`.
+/// Silent songs have `-` for leading and trailing.
+struct Cache {
+    path: Option<PathBuf>,
+    entries: HashMap<PathBuf, CacheEntry>,
+    changed: bool,
+}
+
+impl Cache {
+    fn load(path: Option<PathBuf>) -> Self {
+        let mut entries = HashMap::new();
+        let contents = path.as_ref().and_then(|path| fs::read_to_string(path).ok());
+        for line in contents.as_deref().unwrap_or_default().lines() {
+            if let Some((song, entry)) = Self::parse_line(line) {
+                entries.insert(song, entry);
+            }
+        }
+        Self {
+            path,
+            entries,
+            changed: false,
+        }
+    }
+
+    fn parse_line(line: &str) -> Option<(PathBuf, CacheEntry)> {
+        let mut fields = line.splitn(5, '\t');
+        let mtime = fields.next()?.parse().ok()?;
+        let threshold = fields.next()?.parse().ok()?;
+        let silence = match (fields.next()?, fields.next()?) {
+            ("-", "-") => None,
+            (leading, trailing) => Some(Silence {
+                leading: leading.parse().ok()?,
+                trailing: trailing.parse().ok()?,
+            }),
+        };
+        let path = PathBuf::from(fields.next()?);
+        let entry = CacheEntry {
+            mtime,
+            threshold,
+            silence,
+        };
+        Some((path, entry))
+    }
+
+    /// The line `parse_line` reads back, `None` for paths that can't be stored.
+    fn format_line(song: &Path, entry: &CacheEntry) -> Option<String> {
+        let song = song.to_str().filter(|song| !song.contains('\n'))?;
+        let (leading, trailing) = match entry.silence {
+            Some(silence) => (silence.leading.to_string(), silence.trailing.to_string()),
+            None => ("-".to_string(), "-".to_string()),
+        };
+        Some(format!(
+            "{}\t{}\t{leading}\t{trailing}\t{song}",
+            entry.mtime, entry.threshold
+        ))
+    }
+
+    fn save(&mut self) -> io::Result<()> {
+        let Some(ref path) = self.path else {
+            return Ok(());
+        };
+        if !self.changed {
+            return Ok(());
+        }
+
+        let mut contents = String::new();
+        for (song, entry) in &self.entries {
+            if let Some(line) = Self::format_line(song, entry) {
+                contents.push_str(&line);
+                contents.push('\n');
+            }
+        }
+
+        write_atomic(path, contents)?;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 1000;
+
+    /// Runs stereo frames through a detector, `parts` are seconds with their amplitude.
+    fn detect(parts: &[(f32, f32)]) -> Option<Silence> {
+        let mut detector = SilenceDetector::new(-50.0);
+        detector.format(RATE, 2);
+        for &(seconds, amplitude) in parts {
+            let frames = (seconds * RATE as f32) as usize;
+            // in odd chunks, like a decoder would hand them over
+            for chunk in vec![amplitude; frames * 2].chunks(346) {
+                detector.samples(chunk);
+            }
+        }
+        detector.finish()
+    }
+
+    fn assert_silence(silence: Option<Silence>, leading: f32, trailing: f32) {
+        let silence = silence.unwrap();
+        assert!((silence.leading - leading).abs() < 0.01, "{silence:?}");
+        assert!((silence.trailing - trailing).abs() < 0.01, "{silence:?}");
+    }
+
+    #[test]
+    fn finds_leading_and_trailing_silence() {
+        let silence = detect(&[(2.0, 0.0), (3.0, 0.5), (4.0, 0.001)]);
+        // the margin is left in front of and after the sound
+        assert_silence(silence, 2.0 - MARGIN, 4.0 - MARGIN);
+        assert_silence(detect(&[(3.0, -0.5), (1.0, 0.0)]), 0.0, 1.0 - MARGIN);
+    }
+
+    #[test]
+    fn short_gaps_are_left_alone() {
+        let silence = detect(&[
+            (MIN_SILENCE / 2.0, 0.0),
+            (3.0, 0.5),
+            (MIN_SILENCE / 2.0, 0.0),
+        ]);
+        assert_silence(silence, 0.0, 0.0);
+    }
+
+    #[test]
+    fn silent_songs_have_no_sound_to_skip_to() {
+        assert_eq!(detect(&[(5.0, 0.0)]), None);
+        assert_eq!(detect(&[]), None);
+    }
+
+    #[test]
+    fn cache_lines_round_trip() {
+        let path = Path::new("/music/some artist/song\twith tab.mp3");
+        for silence in [
+            Some(Silence {
+                leading: 1.25,
+                trailing: 0.1,
+            }),
+            None,
+        ] {
+            let entry = CacheEntry {
+                mtime: 1_700_000_000,
+                threshold: -50.0,
+                silence,
+            };
+            let line = Cache::format_line(path, &entry).unwrap();
+            let (parsed_path, parsed) = Cache::parse_line(&line).unwrap();
+            assert_eq!(parsed_path, path);
+            assert_eq!(parsed.mtime, entry.mtime);
+            assert_eq!(parsed.threshold, entry.threshold);
+            assert_eq!(parsed.silence, entry.silence);
+        }
+
+        assert!(Cache::format_line(
+            Path::new("/music/two\nlines.mp3"),
+            &CacheEntry {
+                mtime: 0,
+                threshold: -50.0,
+                silence: None,
+            }
+        )
+        .is_none());
+        assert!(Cache::parse_line("not a cache line").is_none());
+    }
+}
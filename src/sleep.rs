@@ -0,0 +1,138 @@
+//! The sleep timer, which pauses the player after a while and fades the volume out before that.
+
+use std::time::{Duration, Instant};
+
+use crate::{audio::AudioBackend, song::Playlist};
+
+/// The volume goes down over this many seconds before pausing.
+pub const FADE_OUT: f32 = 30.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SleepAfter {
+    Duration(Duration),
+    /// at the end of the n-th song from now, 1 is the end of the current song
+    Songs(u64),
+}
+
+/// Started with `Playlist::set_sleep_timer`, the playlist runs it in `Playlist::update`.
+pub struct SleepTimer {
+    after: SleepAfter,
+    target: Target,
+    /// master volume from before the fade, restored after pausing
+    volume: Option<f32>,
+    /// whether the playlist was told to pause at the end of the last song
+    armed: bool,
+}
+
+enum Target {
+    Time(Instant),
+    /// pause at the end of the song with this `Playlist::songs_started` number
+    Song(u64),
+}
+
+impl SleepTimer {
+    pub(crate) fn new<B: AudioBackend>(after: SleepAfter, playlist: &Playlist<B>) -> Self {
+        let target = match after {
+            SleepAfter::Duration(duration) => Target::Time(Instant::now() + duration),
+            SleepAfter::Songs(songs) => {
+                // without a current song, the first song to start is the first to count
+                let current = playlist.songs_started() + !playlist.has_music_stream() as u64;
+                Target::Song(current + songs.max(1) - 1)
+            }
+        };
+        Self {
+            after,
+            target,
+            volume: None,
+            armed: false,
+        }
+    }
+
+    pub fn after(&self) -> SleepAfter {
+        self.after
+    }
+
+    /// The master volume from before the fade out started, which is restored after pausing.
+    pub fn volume_before_fade(&self) -> Option<f32> {
+        self.volume
+    }
+
+    /// Seconds until the player pauses, `None` while that depends on songs that didn't start yet.
+    pub fn remaining<B: AudioBackend>(&self, playlist: &Playlist<B>, audio: &B) -> Option<f32> {
+        match self.target {
+            Target::Time(deadline) => {
+                Some(deadline.saturating_duration_since(Instant::now()).as_secs_f32())
+            }
+            Target::Song(last) if playlist.songs_started() >= last => {
+                playlist.time_until_song_end(audio)
+            }
+            Target::Song(_) => None,
+        }
+    }
+
+    /// Songs that still start or play before pausing, including the current one.
+    pub fn songs_left<B: AudioBackend>(&self, playlist: &Playlist<B>) -> Option<u64> {
+        match self.target {
+            Target::Time(_) => None,
+            Target::Song(last) => Some((last + 1).saturating_sub(playlist.songs_started())),
+        }
+    }
+
+    /// Fades out and pauses. Returns true once the timer is done.
+    pub(crate) fn update<B: AudioBackend>(
+        &mut self,
+        playlist: &mut Playlist<B>,
+        audio: &mut B,
+    ) -> bool {
+        if let Target::Song(last) = self.target {
+            if self.armed && !playlist.pause_at_song_end {
+                // `handle_song_end` paused
+                self.finish(playlist, audio);
+                return true;
+            }
+            if playlist.songs_started() >= last {
+                // pausing at the song end has to happen in `handle_song_end`, before the next
+                // song starts. Skipping the last song makes the new one the last.
+                playlist.pause_at_song_end = true;
+                self.armed = true;
+                self.target = Target::Song(playlist.songs_started());
+            }
+        }
+
+        match self.remaining(playlist, audio) {
+            Some(remaining) if remaining <= 0.0 && matches!(self.target, Target::Time(_)) => {
+                self.finish(playlist, audio);
+                true
+            }
+            Some(remaining) if remaining < FADE_OUT => {
+                if playlist.is_music_playing(audio) {
+                    let volume = *self.volume.get_or_insert(audio.master_volume());
+                    audio.set_master_volume(volume * remaining / FADE_OUT);
+                }
+                false
+            }
+            _ => {
+                // seeked back or skipped to another song
+                if let Some(volume) = self.volume.take() {
+                    audio.set_master_volume(volume);
+                }
+                false
+            }
+        }
+    }
+
+    fn finish<B: AudioBackend>(&mut self, playlist: &mut Playlist<B>, audio: &mut B) {
+        playlist.pause(audio);
+        self.cancel(playlist, audio);
+    }
+
+    /// Undoes the fade, without pausing.
+    pub(crate) fn cancel<B: AudioBackend>(&mut self, playlist: &mut Playlist<B>, audio: &mut B) {
+        if let Some(volume) = self.volume.take() {
+            audio.set_master_volume(volume);
+        }
+        if self.armed {
+            playlist.pause_at_song_end = false;
+        }
+    }
+}
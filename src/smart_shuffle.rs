@@ -0,0 +1,214 @@
+//! Smart shuffle, a random order that spreads out artists and albums and plays recently played
+//! songs last.
+//!
+//! The order is built one song at a time, first from the songs that weren't played recently and
+//! then from the ones that were. For each place a few random candidates are compared and one of
+//! them is picked, less likely the more it has in common with the songs right before it. Only the
+//! artist of the song right before is ruled out, as long as other artists are left, so a playlist
+//! of a single artist still shuffles.
+
+use crate::{plays::PlayStats, rng::Rng, song::SongEntry};
+
+/// Songs played within this many seconds count as recently played.
+const RECENT: u64 = 4 * 60 * 60;
+/// How many of the songs before a place count for spreading out artists and albums.
+const SPREAD: usize = 3;
+/// How many random candidates are compared for each place.
+const CANDIDATES: usize = 12;
+
+const SAME_ARTIST: f32 = 0.05;
+const SAME_ALBUM: f32 = 0.1;
+
+/// The play order of all `songs`, starting with `first`. With `favorites`, often played songs
+/// are picked earlier.
+pub(crate) fn order(
+    songs: &[SongEntry],
+    first: Option<usize>,
+    plays: &PlayStats,
+    favorites: bool,
+    now: u64,
+    rng: &mut Rng,
+) -> Vec<usize> {
+    let shuffle = SmartShuffle {
+        songs,
+        plays,
+        favorites,
+    };
+    let mut order = Vec::with_capacity(songs.len());
+    order.extend(first.filter(|&idx| idx < songs.len()));
+    let (recent, rest): (Vec<usize>, Vec<usize>) = (0..songs.len())
+        .filter(|&idx| Some(idx) != first)
+        .partition(|&idx| {
+            let last_played = plays.get(songs[idx].path()).last_played;
+            last_played > 0 && now.saturating_sub(last_played) < RECENT
+        });
+    shuffle.extend(&mut order, rest, rng);
+    shuffle.extend(&mut order, recent, rng);
+    order
+}
+
+struct SmartShuffle<'a> {
+    songs: &'a [SongEntry],
+    plays: &'a PlayStats,
+    favorites: bool,
+}
+
+impl SmartShuffle<'_> {
+    /// Adds the songs at `remaining` to the end of `order`.
+    fn extend(&self, order: &mut Vec<usize>, mut remaining: Vec<usize>, rng: &mut Rng) {
+        let songs = self.songs;
+        let mut candidates = Vec::with_capacity(CANDIDATES + 1);
+        while !remaining.is_empty() {
+            let before = &order[order.len().saturating_sub(SPREAD)..];
+            candidates.clear();
+            for _ in 0..CANDIDATES.min(remaining.len()) {
+                let position = rng.value_up_to(remaining.len() - 1);
+                candidates.push((position, self.weight(remaining[position], before)));
+            }
+
+            let last_author = order
+                .last()
+                .map(|&idx| songs[idx].author())
+                .filter(|author| !author.is_empty());
+            if let Some(author) = last_author {
+                let other = |position: usize| songs[remaining[position]].author() != author;
+                if !candidates.iter().any(|&(position, _)| other(position)) {
+                    // the candidates may have missed the other artists that are left
+                    let start = rng.value_up_to(remaining.len() - 1);
+                    let found = (start..remaining.len()).chain(0..start).find(|&p| other(p));
+                    if let Some(position) = found {
+                        candidates.push((position, self.weight(remaining[position], before)));
+                    }
+                }
+                if candidates.iter().any(|&(position, _)| other(position)) {
+                    candidates.retain(|&(position, _)| other(position));
+                }
+            }
+
+            let total: f32 = candidates.iter().map(|(_, weight)| weight).sum();
+            let mut pick = rng.next_f32() * total;
+            let mut picked = candidates[candidates.len() - 1].0;
+            for &(position, weight) in &candidates {
+                if pick < weight {
+                    picked = position;
+                    break;
+                }
+                pick -= weight;
+            }
+            order.push(remaining.swap_remove(picked));
+        }
+    }
+
+    /// How likely the song at `idx` is picked to play after the songs at `before`.
+    fn weight(&self, idx: usize, before: &[usize]) -> f32 {
+        let songs = self.songs;
+        let song = &songs[idx];
+        let mut weight = if self.favorites {
+            1.0 + (self.plays.get(song.path()).count as f32).ln_1p()
+        } else {
+            1.0
+        };
+        let author = song.author();
+        if !author.is_empty() && before.iter().any(|&idx| songs[idx].author() == author) {
+            weight *= SAME_ARTIST;
+        }
+        // songs of an album are usually in the same folder
+        let album = song
+            .path()
+            .parent()
+            .filter(|album| !album.as_os_str().is_empty());
+        if album.is_some()
+            && before
+                .iter()
+                .any(|&idx| songs[idx].path().parent() == album)
+        {
+            weight *= SAME_ALBUM;
+        }
+        weight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    const NOW: u64 = 1_000_000;
+
+    /// `count` songs by each of the artists, in folders named after them.
+    fn songs(artists: &[&str], count: usize) -> Vec<SongEntry> {
+        let mut songs = vec![];
+        for artist in artists {
+            for idx in 0..count {
+                let path = PathBuf::from(format!("/music/{artist}/song {idx}.mp3"));
+                songs.push(SongEntry::new(path).unwrap());
+            }
+        }
+        songs
+    }
+
+    #[test]
+    fn artists_are_spread_out() {
+        let songs = songs(&["a", "b", "c", "d"], 5);
+        for seed in 0..20 {
+            let order = order(
+                &songs,
+                Some(0),
+                &PlayStats::default(),
+                false,
+                NOW,
+                &mut Rng::new(seed),
+            );
+            assert_eq!(order[0], 0);
+            let mut sorted = order.clone();
+            sorted.sort();
+            assert_eq!(sorted, (0..songs.len()).collect::<Vec<_>>());
+
+            for (place, pair) in order.windows(2).enumerate() {
+                let author = songs[pair[0]].author();
+                let others_left = order[place + 1..]
+                    .iter()
+                    .any(|&idx| songs[idx].author() != author);
+                if others_left {
+                    assert_ne!(songs[pair[1]].author(), author, "seed {seed}: {order:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_single_artist_still_shuffles() {
+        let songs = songs(&["a"], 10);
+        let order = order(
+            &songs,
+            None,
+            &PlayStats::default(),
+            false,
+            NOW,
+            &mut Rng::new(1),
+        );
+        assert_ne!(order, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn recently_played_songs_come_last() {
+        let songs = songs(&["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"], 1);
+        let mut plays = PlayStats::default();
+        let recent = [2, 5, 7];
+        for idx in recent {
+            plays.record(songs[idx].path(), NOW - 60);
+        }
+        // long ago doesn't count
+        plays.record(songs[0].path(), NOW - 2 * RECENT);
+
+        for seed in 0..20 {
+            let order = order(&songs, None, &plays, false, NOW, &mut Rng::new(seed));
+            let last = &order[order.len() - recent.len()..];
+            assert!(
+                recent.iter().all(|idx| last.contains(idx)),
+                "seed {seed}: {order:?}"
+            );
+        }
+    }
+}
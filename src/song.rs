@@ -1,14 +1,26 @@
 use std::{
+    collections::{HashMap, VecDeque},
     ffi::{CStr, OsStr},
+    fmt::Display,
     fs::{self, read_to_string, DirEntry},
     io,
-    ops::Deref,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
-use raylib::{
-    audio::{Music, RaylibAudio},
-    rstr, RaylibThread,
+pub use crate::audio::PlayError;
+use crate::{
+    audio::AudioBackend,
+    channels::ChannelSettings,
+    equalizer::EqualizerSettings,
+    plays::{self, PlayStats},
+    replaygain::{ReplayGain, ReplayGainOptions},
+    resume::ResumePositions,
+    rng::Rng,
+    silence::Silence,
+    sleep::{SleepAfter, SleepTimer},
+    smart_shuffle,
+    speed::{MAX_SPEED, MIN_SPEED},
 };
 
 #[derive(Clone)]
@@ -16,10 +28,11 @@ pub struct SongEntry {
     path: PathBuf,
     filename: Vec<u8>,
     author: Vec<u8>,
+    load_failed: bool,
 }
 
 impl SongEntry {
-    // arbitrary directories: _, unordered, any, unknown, random
+    // arbitrary directories (by default): _, unordered, any, unknown, random
 
     fn process_os_str_case_arbitrary_dir(filestem: &str) -> Option<(Vec<u8>, Vec<u8>)> {
         // case: /.../author - name.*
@@ -73,13 +86,12 @@ impl SongEntry {
         Some((vec_1, vec![0]))
     }
 
-    fn process_os_str(filestem: &str, parent: &OsStr) -> Option<(Vec<u8>, Vec<u8>)> {
-        if parent == "_"
-            || parent == "unordered"
-            || parent == "any"
-            || parent == "unknown"
-            || parent == "random"
-        {
+    fn process_os_str<S: AsRef<str>>(
+        filestem: &str,
+        parent: &OsStr,
+        arbitrary_dirs: &[S],
+    ) -> Option<(Vec<u8>, Vec<u8>)> {
+        if arbitrary_dirs.iter().any(|dir| parent == dir.as_ref()) {
             // case: /.../author - name.*
             // or: /.../name.* with unknown author
             Self::process_os_str_case_arbitrary_dir(filestem)
@@ -105,30 +117,66 @@ impl SongEntry {
     }
 
     pub fn new(path: PathBuf) -> Option<Self> {
+        Self::with_arbitrary_dirs(path, ARBITRARY_DIRS)
+    }
+
+    /// Like `new`, with a custom list of directory names that don't name the author.
+    pub fn with_arbitrary_dirs<S: AsRef<str>>(path: PathBuf, arbitrary_dirs: &[S]) -> Option<Self> {
         let (filename, author) = Self::process_os_str(
             path.file_stem()?.to_str()?,
             path.parent().map(|path| path.file_name()).flatten()?,
+            arbitrary_dirs,
         )?;
         return Some(Self {
             path,
             filename,
             author,
+            load_failed: false,
         });
     }
 
     pub fn file_name<'a>(&'a self) -> &'a CStr {
         unsafe { CStr::from_bytes_with_nul_unchecked(&self.filename) }
     }
+
+    pub fn author<'a>(&'a self) -> &'a CStr {
+        unsafe { CStr::from_bytes_with_nul_unchecked(&self.author) }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the last attempt to play this song failed.
+    pub fn load_failed(&self) -> bool {
+        self.load_failed
+    }
 }
 
-pub struct PlayingSong {
-    // path: PathBuf,
+pub struct PlayingSong<B: AudioBackend> {
+    path: PathBuf,
     filename: Vec<u8>,
     author: Vec<u8>,
-    music: Music,
+    music: B::Stream,
     idx: usize,
     pub lyrics: String,
     pub lyrics_dimensions: Option<(i32, i32)>,
+    /// ReplayGain volume, the fade volume is multiplied with it
+    gain: f32,
+    volume: f32,
+    fade: Option<Fade>,
+    /// where the trailing silence starts, the song counts as ended from there
+    end: Option<f32>,
+    /// whether `skip_silence` happened, it only does once
+    silence_skipped: bool,
+}
+
+/// A volume change over time, measured in playback time so it stops while paused.
+struct Fade {
+    from: f32,
+    to: f32,
+    start: f32,
+    duration: f32,
 }
 
 fn load_lyrics(path: &Path) -> String {
@@ -143,169 +191,605 @@ fn load_lyrics(path: &Path) -> String {
     read_to_string(&lyric_path).unwrap_or_else(|_| String::new())
 }
 
-impl PlayingSong {
-    pub fn new_play(
-        entry: &SongEntry,
-        idx: usize,
-        thread: &RaylibThread,
-        audio: &mut RaylibAudio,
-    ) -> Result<Self, PlayError> {
+impl<B: AudioBackend> PlayingSong<B> {
+    pub fn new_play(entry: &SongEntry, idx: usize, audio: &mut B) -> Result<Self, PlayError> {
+        let mut this = Self::load(entry, idx, audio)?;
+        this.play(audio);
+        Ok(this)
+    }
+
+    /// Loads the song without starting it, see `play`.
+    pub fn load(entry: &SongEntry, idx: usize, audio: &mut B) -> Result<Self, PlayError> {
         let lyrics = load_lyrics(&entry.path);
-        let mut this = Self {
+        Ok(Self {
             filename: entry.filename.clone(),
             author: entry.author.clone(),
-            // path: entry.path.clone(),
+            path: entry.path.clone(),
             idx,
-            music: Music::load_music_stream(
-                thread,
-                entry.path.to_str().ok_or(PlayError::FileNameInvalid)?,
-            )
-            .map_err(|err| PlayError::IoError(err))?,
+            music: audio.load_stream(&entry.path)?,
             lyrics,
             lyrics_dimensions: None,
-        };
-        this.music.looping = false;
-        audio.play_music_stream(&mut this.music);
+            gain: 1.0,
+            volume: 1.0,
+            fade: None,
+            end: None,
+            silence_skipped: false,
+        })
+    }
 
-        Ok(this)
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Starts the song from the beginning and fills its buffers right away.
+    pub fn play(&mut self, audio: &mut B) {
+        audio.play(&mut self.music);
+        audio.update(&mut self.music);
     }
 
-    pub fn is_playing(&self, audio: &RaylibAudio) -> bool {
-        audio.is_music_stream_playing(&self.music)
+    pub fn is_playing(&self, audio: &B) -> bool {
+        audio.is_playing(&self.music)
     }
 
-    pub fn pause(&mut self, audio: &mut RaylibAudio) {
-        audio.pause_music_stream(&mut self.music)
+    pub fn pause(&mut self, audio: &mut B) {
+        audio.pause(&mut self.music)
     }
 
-    pub fn resume(&mut self, audio: &mut RaylibAudio) {
-        audio.resume_music_stream(&mut self.music)
+    pub fn resume(&mut self, audio: &mut B) {
+        audio.resume(&mut self.music)
     }
 
-    pub fn seek(&mut self, seek_to: f32, _audio: &mut RaylibAudio) {
-        unsafe { raylib::ffi::SeekMusicStream(*(self.music).deref(), seek_to) }
+    pub fn seek(&mut self, seek_to: f32, audio: &mut B) {
+        audio.seek(&mut self.music, seek_to)
     }
 
-    pub fn progress(&self, audio: &RaylibAudio) -> f32 {
-        audio.get_music_time_played(&self.music) / audio.get_music_time_length(&self.music)
+    pub fn progress(&self, audio: &B) -> f32 {
+        audio.time_played(&self.music) / audio.time_length(&self.music)
     }
 
-    pub fn get_music_length(&self, audio: &RaylibAudio) -> f32 {
-        audio.get_music_time_length(&self.music)
+    pub fn get_music_length(&self, audio: &B) -> f32 {
+        audio.time_length(&self.music)
     }
 
-    pub fn get_music_length_played(&self, audio: &RaylibAudio) -> f32 {
-        audio.get_music_time_played(&self.music)
+    pub fn get_music_length_played(&self, audio: &B) -> f32 {
+        audio.time_played(&self.music)
     }
 
-    pub fn reached_end(&self, audio: &RaylibAudio) -> bool {
-        audio.get_music_time_length(&self.music) - 1.0 <= audio.get_music_time_played(&self.music)
+    pub fn reached_end(&self, audio: &B) -> bool {
+        audio.has_ended(&self.music)
+            || self
+                .end
+                .is_some_and(|end| audio.time_played(&self.music) >= end)
     }
 
-    pub fn update(&mut self, audio: &mut RaylibAudio) {
-        audio.update_music_stream(&mut self.music)
+    fn time_left(&self, audio: &B) -> f32 {
+        let end = self.end.unwrap_or(audio.time_length(&self.music));
+        end - audio.time_played(&self.music)
+    }
+
+    /// Skips the leading silence unless it's past that already, and ends the song where the
+    /// trailing silence starts.
+    fn skip_silence(&mut self, silence: Silence, audio: &mut B) {
+        self.silence_skipped = true;
+        let length = audio.time_length(&self.music);
+        if silence.trailing > 0.0 && silence.leading + silence.trailing < length {
+            self.end = Some(length - silence.trailing);
+        }
+        let played = audio.time_played(&self.music);
+        if played < silence.leading {
+            self.seek(silence.leading, audio);
+            // a fade in goes on from where it was
+            if let Some(ref mut fade) = self.fade {
+                fade.start += silence.leading - played;
+            }
+        }
+    }
+
+    pub fn update(&mut self, audio: &mut B) {
+        audio.update(&mut self.music);
+        if let Some(ref fade) = self.fade {
+            let elapsed = audio.time_played(&self.music) - fade.start;
+            let t = if fade.duration > 0.0 {
+                (elapsed / fade.duration).clamp(0.0, 1.0)
+            } else {
+                1.0
+            };
+            let volume = fade.from + (fade.to - fade.from) * t;
+            if t >= 1.0 {
+                self.fade = None;
+            }
+            self.set_volume(volume, audio);
+        }
+    }
+
+    /// Plays the song `speed` times as fast, keeping the pitch. Positions and lengths stay in
+    /// seconds of the song.
+    pub fn set_speed(&mut self, speed: f32, audio: &mut B) {
+        audio.set_speed(&mut self.music, speed);
+    }
+
+    fn set_volume(&mut self, volume: f32, audio: &mut B) {
+        self.volume = volume;
+        audio.set_volume(&mut self.music, volume * self.gain);
+    }
+
+    pub fn set_gain(&mut self, gain: f32, audio: &mut B) {
+        self.gain = gain;
+        self.set_volume(self.volume, audio);
+    }
+
+    /// Changes the volume to `volume` over the next `duration` seconds of playback.
+    pub fn fade_to(&mut self, volume: f32, duration: f32, audio: &B) {
+        self.fade = Some(Fade {
+            from: self.volume,
+            to: volume,
+            start: audio.time_played(&self.music),
+            duration,
+        });
+    }
+
+    pub fn is_fading(&self) -> bool {
+        self.fade.is_some()
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum RepeatBehavior {
     Normal,
     Repeat,
     RepeatSingle,
 }
 
-pub const ICON_REPEAT: &std::ffi::CStr = rstr!("#224#");
-pub const ICON_NO_REPEAT: &std::ffi::CStr = rstr!("#222#");
-pub const ICON_REPEAT_SINGLE: &std::ffi::CStr = rstr!("#223#");
-
 impl RepeatBehavior {
-    pub fn to_icon(&self) -> &std::ffi::CStr {
+    pub fn next(&mut self) {
         match self {
-            Self::Normal => ICON_NO_REPEAT,
-            Self::Repeat => ICON_REPEAT,
-            Self::RepeatSingle => ICON_REPEAT_SINGLE,
+            Self::Normal => *self = Self::Repeat,
+            Self::Repeat => *self = Self::RepeatSingle,
+            Self::RepeatSingle => *self = Self::Normal,
+        }
+    }
+
+    /// The name used on the command line, in the session and by remote control.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Normal => "none",
+            Self::Repeat => "all",
+            Self::RepeatSingle => "single",
+        }
+    }
+}
+
+impl FromStr for RepeatBehavior {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "none" => Ok(Self::Normal),
+            "all" => Ok(Self::Repeat),
+            "single" => Ok(Self::RepeatSingle),
+            _ => Err(format!(
+                "invalid repeat behavior '{name}', expected none, all or single"
+            )),
         }
     }
+}
 
+/// The order songs play in. Shuffling doesn't change the playlist itself, see
+/// `Playlist::set_shuffle_behavior`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ShuffleBehavior {
+    Normal,
+    Shuffle,
+    /// spreads out artists and albums and plays recently played songs last, see `smart_shuffle`
+    Smart,
+}
+
+impl ShuffleBehavior {
     pub fn next(&mut self) {
         match self {
-            Self::Normal => *self = Self::Repeat,
-            Self::Repeat => *self = Self::RepeatSingle,
-            Self::RepeatSingle => *self = Self::Normal,
+            Self::Normal => *self = Self::Shuffle,
+            Self::Shuffle => *self = Self::Smart,
+            Self::Smart => *self = Self::Normal,
+        }
+    }
+
+    /// The name used in the session and by remote control.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Normal => "off",
+            Self::Shuffle => "on",
+            Self::Smart => "smart",
+        }
+    }
+}
+
+impl FromStr for ShuffleBehavior {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "off" => Ok(Self::Normal),
+            "on" => Ok(Self::Shuffle),
+            "smart" => Ok(Self::Smart),
+            _ => Err(format!(
+                "invalid shuffle '{name}', expected on, off or smart"
+            )),
+        }
+    }
+}
+
+/// Which files get added to the playlist and how their author is determined.
+#[derive(Clone)]
+pub struct LibraryOptions {
+    /// file extensions (without the dot) that are added when adding a folder
+    pub supported_formats: Vec<String>,
+    /// directories whose name doesn't name the author, see `SongEntry`
+    pub arbitrary_dirs: Vec<String>,
+}
+
+impl Default for LibraryOptions {
+    fn default() -> Self {
+        Self {
+            supported_formats: SUPPORTED_FORMATS.iter().map(|v| v.to_string()).collect(),
+            arbitrary_dirs: ARBITRARY_DIRS.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+}
+
+impl LibraryOptions {
+    pub fn is_supported(&self, extension: &OsStr) -> bool {
+        self.supported_formats
+            .iter()
+            .any(|ext| extension.eq_ignore_ascii_case(ext))
+    }
+}
+
+/// A song that played, see `Playlist::history`.
+#[derive(Clone, Copy)]
+pub struct HistoryEntry {
+    pub idx: usize,
+    /// when it started, in seconds since the unix epoch
+    pub played_at: u64,
+}
+
+/// Something that went wrong while playing or adding songs. These are collected by the playlist
+/// until they're taken out with `Playlist::take_errors`.
+#[derive(Debug)]
+pub enum PlaylistError {
+    Play { path: PathBuf, error: PlayError },
+    Add { path: PathBuf, error: io::Error },
+}
+
+impl Display for PlaylistError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Play { path, error } => {
+                write!(f, "Failed to play {}: {error}", path.display())
+            }
+            Self::Add { path, error } => write!(f, "Failed to add {}: {error}", path.display()),
         }
     }
 }
 
-pub struct Playlist {
+/// How long before the end of a song the next one gets loaded.
+const PRELOAD_TIME: f32 = 10.0;
+/// How many songs `Playlist::history` keeps.
+const HISTORY_LENGTH: usize = 200;
+/// A-B loops have to be at least this many seconds long, so they can't get stuck seeking.
+const MIN_LOOP_LENGTH: f32 = 0.1;
+
+pub struct Playlist<B: AudioBackend> {
     songs: Vec<SongEntry>,
-    current_song: CurrentSong,
+    current_song: CurrentSong<B>,
+    /// the song that plays after the current one, loaded ahead of time so it can start without a
+    /// gap
+    next_song: CurrentSong<B>,
+    /// index and playlist version `next_song` was loaded for, also set when loading failed so
+    /// it isn't retried every frame
+    next_song_for: Option<(usize, u64)>,
+    /// songs that are still fading out after switching to another one
+    fading_out: Vec<PlayingSong<B>>,
+    /// indices of songs that play before going on with the playlist, see `enqueue`
+    queue: VecDeque<usize>,
+    errors: Vec<PlaylistError>,
+    rng: Rng,
+    version: u64,
+    pub library: LibraryOptions,
     pub repeat_behavior: RepeatBehavior,
+    /// see `set_shuffle_behavior`
+    shuffle_behavior: ShuffleBehavior,
+    /// indices of all songs in the order they play while shuffling, empty otherwise
+    order: Vec<usize>,
+    /// how often songs played, counted when they start
+    pub plays: PlayStats,
+    /// smart shuffle picks often played songs earlier
+    pub shuffle_favorites: bool,
+    /// seconds the old and the new song overlap when switching songs, 0 plays them back to back
+    pub crossfade: f32,
+    /// see `set_speed`
+    speed: f32,
+    /// A and B of the A-B loop in seconds of the current song, see `handle_ab_loop`
+    loop_a: Option<f32>,
+    loop_b: Option<f32>,
+    /// applied to songs when they are loaded
+    pub replay_gain: ReplayGainOptions,
+    /// gains measured by the loudness scanner, used for songs without ReplayGain tags
+    pub scanned_replay_gain: HashMap<PathBuf, ReplayGain>,
+    /// silence found by the silence scanner, it's skipped when songs start and end
+    pub silences: HashMap<PathBuf, Silence>,
+    /// applied whenever a song starts, call `apply_equalizer` after changing it
+    pub equalizer: EqualizerSettings,
+    /// see `set_channels`
+    channels: ChannelSettings,
+    /// where long songs stopped, they continue from there when they start again
+    pub resume: ResumePositions,
+    /// the position the current song continued from, see `start_over`
+    resumed_from: Option<f32>,
+    sleep_timer: Option<SleepTimer>,
+    /// set by the sleep timer, `handle_song_end` pauses instead of going on and resets it
+    pub(crate) pause_at_song_end: bool,
+    /// counts up whenever a song starts, so the sleep timer can count songs
+    songs_started: u64,
+    /// songs that started, the newest last
+    history: VecDeque<HistoryEntry>,
+    /// the entry of the current song after going back in the history, see `play_previous`
+    history_position: Option<usize>,
     pub __render_scroll_index: f32,
     pub __render_current_selected: usize,
 }
 
-pub enum PlayError {
-    IoError(String),
-    FileNameInvalid,
-}
-
-impl Default for Playlist {
+impl<B: AudioBackend> Default for Playlist<B> {
     fn default() -> Self {
         Self {
             current_song: None,
+            next_song: None,
+            next_song_for: None,
+            fading_out: vec![],
+            queue: VecDeque::new(),
+            errors: vec![],
+            rng: Rng::from_time(),
+            version: 0,
+            library: LibraryOptions::default(),
             __render_scroll_index: 0.0,
             __render_current_selected: 0,
             songs: vec![],
             repeat_behavior: RepeatBehavior::Normal,
+            shuffle_behavior: ShuffleBehavior::Normal,
+            order: vec![],
+            plays: PlayStats::default(),
+            shuffle_favorites: false,
+            crossfade: 0.0,
+            speed: 1.0,
+            loop_a: None,
+            loop_b: None,
+            replay_gain: ReplayGainOptions::default(),
+            scanned_replay_gain: HashMap::new(),
+            silences: HashMap::new(),
+            equalizer: EqualizerSettings::default(),
+            channels: ChannelSettings::default(),
+            resume: ResumePositions::default(),
+            resumed_from: None,
+            sleep_timer: None,
+            pause_at_song_end: false,
+            songs_started: 0,
+            history: VecDeque::new(),
+            history_position: None,
         }
     }
 }
 
-impl Playlist {
+impl<B: AudioBackend> Playlist<B> {
     pub fn play_invalidated_ids_no_err(
         &mut self,
         idx: usize,
-        thread: &RaylibThread,
-        audio: &mut RaylibAudio,
+        audio: &mut B,
         screen_height: i32,
     ) {
-        println!("playing song #{idx}");
 
-        let Some(song) = self.songs.get(idx) else {
-            return;
-        };
-        let Ok(song) = PlayingSong::new_play(song, idx, thread, audio) else {
+        if idx >= self.songs.len() {
             return;
+        }
+        let mut song = match self.load_song(idx, audio) {
+            Ok(song) => song,
+            Err(err) => return self.record_play_error(idx, err),
         };
+        song.play(audio);
+        self.remember_position(audio);
+        self.continue_song(&mut song, audio);
+        self.songs[idx].load_failed = false;
         self.current_song = Some(song);
+        self.song_started(idx);
+        self.clear_ab_loop();
+        self.apply_equalizer(audio);
         self.adjust_center_song(idx, screen_height);
     }
 
+    pub fn channels(&self) -> ChannelSettings {
+        self.channels
+    }
+
+    /// Changes balance, mono and so on. Unlike the equalizer this is the same for all songs.
+    pub fn set_channels(&mut self, channels: ChannelSettings, audio: &mut B) {
+        self.channels = channels;
+        audio.set_channels(channels);
+    }
+
+    /// Sets the equalizer to the bands of the current song.
+    pub fn apply_equalizer(&self, audio: &mut B) {
+        let song = self
+            .currently_playing_id()
+            .and_then(|idx| self.songs.get(idx))
+            .map(SongEntry::path);
+        audio.set_equalizer(self.equalizer.bands_for(song));
+    }
+
     pub fn play_ignore_err(
         &mut self,
         idx: usize,
-        thread: &RaylibThread,
-        audio: &mut RaylibAudio,
+        audio: &mut B,
         screen_height: i32,
     ) {
-        println!("playing song #{idx}");
-        if let Some(mut song) = self.current_song.take() {
+        if let Some(ref mut song) = self.current_song {
             if song.idx == idx {
                 song.seek(0.1, audio);
-                self.current_song = Some(song);
                 return;
+            }
+        }
+        self.load_and_start(idx, audio, screen_height);
+    }
+
+    /// Loads the song at `idx` with its ReplayGain applied.
+    fn load_song(&self, idx: usize, audio: &mut B) -> Result<PlayingSong<B>, PlayError> {
+        let entry = &self.songs[idx];
+        let mut song = PlayingSong::load(entry, idx, audio)?;
+        let scanned = self.scanned_replay_gain.get(&entry.path);
+        song.set_gain(self.replay_gain.volume(&entry.path, scanned), audio);
+        if self.speed != 1.0 {
+            song.set_speed(self.speed, audio);
+        }
+        Ok(song)
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Plays all songs `speed` times as fast (0.5 to 3), keeping the pitch.
+    pub fn set_speed(&mut self, speed: f32, audio: &mut B) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+        let songs = self
+            .current_song
+            .iter_mut()
+            .chain(self.next_song.iter_mut())
+            .chain(self.fading_out.iter_mut());
+        for song in songs {
+            song.set_speed(self.speed, audio);
+        }
+    }
+
+    /// The crossfade in seconds of the songs, which pass faster when playing faster.
+    fn crossfade_song_time(&self) -> f32 {
+        self.crossfade * self.speed
+    }
+
+    fn load_and_start(&mut self, idx: usize, audio: &mut B, screen_height: i32) {
+        if idx >= self.songs.len() {
+            return self.stop_playing(audio);
+        }
+        match self.load_song(idx, audio) {
+            Ok(song) => self.start_song(song, audio, screen_height),
+            Err(err) => {
+                self.stop_playing(audio);
+                self.record_play_error(idx, err);
+            }
+        }
+    }
+
+    /// Replaces the current song with `song`, crossfading if the current one is still playing.
+    fn start_song(&mut self, mut song: PlayingSong<B>, audio: &mut B, screen_height: i32) {
+        let idx = song.idx;
+        self.remember_position(audio);
+        let mut fade_in = false;
+        if let Some(mut old) = self.current_song.take() {
+            if self.crossfade > 0.0 && old.is_playing(audio) {
+                let duration = self.crossfade_song_time().min(old.time_left(audio));
+                old.fade_to(0.0, duration, audio);
+                self.fading_out.push(old);
+                fade_in = true;
             } else {
-                song.pause(audio);
+                old.pause(audio);
             }
         }
 
-        if let Some(song) = self.songs.get(idx) {
-            if let Ok(song) = PlayingSong::new_play(song, idx, thread, audio) {
-                self.current_song = Some(song);
-                self.adjust_center_song(idx, screen_height);
+        if fade_in {
+            song.set_volume(0.0, audio);
+        }
+        song.play(audio);
+        // before fading in, fades are measured in positions of the song
+        self.continue_song(&mut song, audio);
+        if fade_in {
+            song.fade_to(1.0, self.crossfade_song_time(), audio);
+        }
+        self.songs[idx].load_failed = false;
+        self.current_song = Some(song);
+        self.song_started(idx);
+        self.clear_ab_loop();
+        self.apply_equalizer(audio);
+        self.adjust_center_song(idx, screen_height);
+    }
+
+    /// Seeks `song` to where it stopped last time, if it's long enough to be remembered.
+    fn continue_song(&mut self, song: &mut PlayingSong<B>, audio: &mut B) {
+        self.resumed_from = self.resume.get(&song.path);
+        if let Some(position) = self.resumed_from {
+            song.seek(position, audio);
+        }
+        if let Some(silence) = self.silences.get(&song.path) {
+            song.skip_silence(*silence, audio);
+        }
+    }
+
+    /// Remembers the position of the current song in `resume`. This happens by itself when it
+    /// stops playing, but not when the player is closed.
+    pub fn remember_position(&mut self, audio: &B) {
+        if let Some(ref song) = self.current_song {
+            self.resume.remember(
+                &song.path,
+                song.get_music_length_played(audio),
+                song.get_music_length(audio),
+            );
+        }
+    }
+
+    /// Where the current song continued from, `None` if it started at the beginning.
+    pub fn resumed_from(&self) -> Option<f32> {
+        self.resumed_from
+    }
+
+    /// Plays the current song from the beginning and forgets where it stopped.
+    pub fn start_over(&mut self, audio: &mut B) {
+        if let Some(ref mut song) = self.current_song {
+            self.resume.forget(&song.path);
+            song.seek(0.0, audio);
+        }
+        self.resumed_from = None;
+    }
+
+    /// Forgets where the current song stopped and goes on with the song after it, as if it
+    /// played to the end.
+    pub fn mark_finished(&mut self, audio: &mut B, screen_height: i32) {
+        let Some(ref song) = self.current_song else {
+            return;
+        };
+        let (idx, path) = (song.idx, song.path.clone());
+        match self.next_song_idx(idx) {
+            Some(next) => {
+                self.take_queued(next);
+                self.play_ignore_err(next, audio, screen_height);
             }
+            None => self.stop_playing(audio),
+        }
+        // switching songs remembered its position again
+        self.resume.forget(&path);
+        if self.currently_playing_id() == Some(idx) {
+            self.start_over(audio);
         }
     }
 
+    fn record_play_error(&mut self, idx: usize, error: PlayError) {
+        let song = &mut self.songs[idx];
+        song.load_failed = true;
+        self.errors.push(PlaylistError::Play {
+            path: song.path.clone(),
+            error,
+        });
+    }
+
+    /// Takes all errors that happened since the last call.
+    pub fn take_errors(&mut self) -> Vec<PlaylistError> {
+        std::mem::take(&mut self.errors)
+    }
+
     pub fn adjust_center_song(&mut self, idx: usize, screen_height: i32) {
         let height = screen_height - 180;
         let offset_top = (height - 30) / 2;
@@ -313,73 +797,437 @@ impl Playlist {
         self.__render_scroll_index = -(y_coord - offset_top).max(0) as f32;
     }
 
-    pub fn pause(&mut self, audio: &mut RaylibAudio) {
+    pub fn pause(&mut self, audio: &mut B) {
         if let Some(ref mut song) = self.current_song {
             song.pause(audio)
         }
+        for song in &mut self.fading_out {
+            song.pause(audio)
+        }
     }
 
     #[allow(dead_code)]
-    pub fn resume(&mut self, audio: &mut RaylibAudio) {
+    pub fn resume(&mut self, audio: &mut B) {
         if let Some(ref mut song) = self.current_song {
             song.resume(audio)
         }
+        for song in &mut self.fading_out {
+            song.resume(audio)
+        }
     }
 
-    pub fn pause_resume(&mut self, audio: &mut RaylibAudio) {
-        if let Some(ref mut song) = self.current_song {
-            if song.is_playing(audio) {
-                song.pause(audio);
-            } else {
-                song.resume(audio);
-            }
+    pub fn pause_resume(&mut self, audio: &mut B) {
+        if self.is_music_playing(audio) {
+            self.pause(audio);
+        } else {
+            self.resume(audio);
         }
     }
 
-    pub fn seek(&mut self, seek_to: f32, audio: &mut RaylibAudio) {
+    pub fn seek(&mut self, seek_to: f32, audio: &mut B) {
         if let Some(ref mut song) = self.current_song {
             song.seek(seek_to, audio)
         }
     }
 
-    pub fn update(&mut self, audio: &mut RaylibAudio) {
+    pub fn update(&mut self, audio: &mut B) {
         if let Some(ref mut song) = self.current_song {
+            song.update(audio);
+            if !song.silence_skipped {
+                // found after the song started
+                if let Some(silence) = self.silences.get(&song.path) {
+                    song.skip_silence(*silence, audio);
+                }
+            }
+        }
+        for song in &mut self.fading_out {
             song.update(audio)
         }
+        self.fading_out
+            .retain(|song| song.is_fading() && !song.reached_end(audio));
+
+        if let Some(mut timer) = self.sleep_timer.take() {
+            if !timer.update(self, audio) {
+                self.sleep_timer = Some(timer);
+            }
+        }
     }
 
-    pub fn shuffle(&mut self) {
-        let len = self.songs.len();
-        if len < 1 {
-            return;
+    /// Starts the sleep timer, or turns it off with `None`. A running timer is replaced and its
+    /// fade out undone.
+    pub fn set_sleep_timer(&mut self, after: Option<SleepAfter>, audio: &mut B) {
+        if let Some(mut timer) = self.sleep_timer.take() {
+            timer.cancel(self, audio);
         }
-        let mut tmp_song = self.songs[0].clone();
-        for _ in 0..len {
-            let max_random_index = (self.songs.len() - 1) as i32;
-            let idx_old = unsafe { raylib::ffi::GetRandomValue(0, max_random_index) as usize };
-            let idx_new = unsafe { raylib::ffi::GetRandomValue(0, max_random_index) as usize };
-            if idx_old == idx_new {
-                continue;
+        self.sleep_timer = after.map(|after| SleepTimer::new(after, self));
+    }
+
+    pub fn sleep_timer(&self) -> Option<&SleepTimer> {
+        self.sleep_timer.as_ref()
+    }
+
+    pub(crate) fn songs_started(&self) -> u64 {
+        self.songs_started
+    }
+
+    /// Seconds of wall time until the current song ends.
+    pub fn time_until_song_end(&self, audio: &B) -> Option<f32> {
+        let song = self.current_song.as_ref()?;
+        Some(song.time_left(audio) / self.speed)
+    }
+
+    /// Plays the next song according to the repeat behavior once the current one reached its end.
+    /// Shortly before that, the next song is loaded so it can start in the same frame.
+    ///
+    /// Returns true if the song ended and there was no song after it, so playback stopped.
+    pub fn handle_song_end(&mut self, audio: &mut B, screen_height: i32) -> bool {
+        let Some(idx) = self.currently_playing_id() else {
+            return false;
+        };
+        if self.ab_loop().is_some() {
+            // the song doesn't end while looping, see `handle_ab_loop`
+            return false;
+        }
+        let next = self.next_song_idx(idx);
+        // pausing for the sleep timer doesn't crossfade, the song plays to its end
+        let crossfade_due =
+            next.is_some() && !self.pause_at_song_end && self.crossfade_due(audio);
+        if !self.music_has_reached_the_end(audio) && !crossfade_due {
+            self.preload_next_song(audio);
+            return false;
+        }
+        match next {
+            Some(next) => {
+                self.take_queued(next);
+                self.play_preloaded(next, audio, screen_height);
             }
+            None => self.stop_playing(audio),
+        }
+        if self.pause_at_song_end {
+            // the next song is ready to be resumed
+            self.pause_at_song_end = false;
+            self.pause(audio);
+        }
+        next.is_none()
+    }
 
-            if let Some(ref mut song) = self.current_song {
-                if song.idx == idx_old {
-                    song.idx = idx_new;
-                } else if song.idx == idx_new {
-                    song.idx = idx_old;
+    /// Sets A of the A-B loop to `position` of the current song. B is dropped if it isn't after
+    /// A anymore.
+    pub fn set_loop_a(&mut self, position: f32) {
+        self.loop_a = Some(position);
+        if self.loop_b.is_some_and(|b| b < position + MIN_LOOP_LENGTH) {
+            self.loop_b = None;
+        }
+    }
+
+    /// Sets B of the A-B loop, which starts looping. Without A, the loop starts at the beginning
+    /// of the song. A and B are swapped if B is before A.
+    pub fn set_loop_b(&mut self, position: f32) {
+        let a = self.loop_a.unwrap_or(0.0);
+        if (position - a).abs() < MIN_LOOP_LENGTH {
+            return;
+        }
+        self.loop_a = Some(a.min(position));
+        self.loop_b = Some(a.max(position));
+    }
+
+    pub fn clear_ab_loop(&mut self) {
+        self.loop_a = None;
+        self.loop_b = None;
+    }
+
+    /// A and B as far as they are set, for showing them.
+    pub fn loop_points(&self) -> (Option<f32>, Option<f32>) {
+        (self.loop_a, self.loop_b)
+    }
+
+    /// A and B once the loop is complete.
+    pub fn ab_loop(&self) -> Option<(f32, f32)> {
+        Some((self.loop_a?, self.loop_b?))
+    }
+
+    /// Seeks back to A once the current song played past B. Has to be called once a frame, before
+    /// `handle_song_end`.
+    pub fn handle_ab_loop(&mut self, audio: &mut B) {
+        let Some((a, b)) = self.ab_loop() else {
+            return;
+        };
+        let Some(ref mut song) = self.current_song else {
+            return;
+        };
+        if song.reached_end(audio) {
+            // B was right at the end, so the stream stopped before getting there
+            song.play(audio);
+            song.seek(a, audio);
+        } else if song.get_music_length_played(audio) >= b {
+            song.seek(a, audio);
+        }
+    }
+
+    /// The song that plays when the song at `idx` ends.
+    fn song_after(&self, idx: usize) -> Option<usize> {
+        let position = self.order_position(idx);
+        match self.repeat_behavior {
+            RepeatBehavior::Normal => self.song_at(position + 1),
+            RepeatBehavior::Repeat if self.len() > 0 => self.song_at((position + 1) % self.len()),
+            RepeatBehavior::Repeat => None,
+            RepeatBehavior::RepeatSingle => Some(idx),
+        }
+    }
+
+    pub fn shuffle_behavior(&self) -> ShuffleBehavior {
+        self.shuffle_behavior
+    }
+
+    /// Switches between playing the songs in playlist order and in a random order. Each time
+    /// shuffling is turned on, there's a new order that starts with the current song. The
+    /// playlist itself stays the way it is. Smart shuffle takes `plays` into account, so its
+    /// order only knows about the plays up to when it was made.
+    pub fn set_shuffle_behavior(&mut self, shuffle_behavior: ShuffleBehavior) {
+        self.shuffle_behavior = shuffle_behavior;
+        self.order.clear();
+        let current = self.currently_playing_id();
+        match shuffle_behavior {
+            ShuffleBehavior::Normal => {}
+            ShuffleBehavior::Shuffle => {
+                self.order.extend(0..self.songs.len());
+                self.rng.shuffle(&mut self.order);
+                if let Some(position) = self.order.iter().position(|idx| Some(*idx) == current) {
+                    self.order.swap(0, position);
                 }
             }
+            ShuffleBehavior::Smart => {
+                self.order = smart_shuffle::order(
+                    &self.songs,
+                    current,
+                    &self.plays,
+                    self.shuffle_favorites,
+                    plays::now(),
+                    &mut self.rng,
+                );
+            }
+        }
+    }
+
+    /// Makes the shuffled orders reproducible, mostly useful for testing. A current order is
+    /// replaced by one from the new seed.
+    pub fn seed_shuffle(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+        self.set_shuffle_behavior(self.shuffle_behavior);
+    }
+
+    /// Where the song at `idx` is in the play order.
+    fn order_position(&self, idx: usize) -> usize {
+        match self.shuffle_behavior {
+            ShuffleBehavior::Normal => idx,
+            ShuffleBehavior::Shuffle | ShuffleBehavior::Smart => self
+                .order
+                .iter()
+                .position(|queued| *queued == idx)
+                .unwrap_or(0),
+        }
+    }
+
+    /// The song at `position` of the play order.
+    fn song_at(&self, position: usize) -> Option<usize> {
+        match self.shuffle_behavior {
+            ShuffleBehavior::Normal => Some(position).filter(|&idx| idx < self.len()),
+            ShuffleBehavior::Shuffle | ShuffleBehavior::Smart => {
+                self.order.get(position).copied()
+            }
+        }
+    }
+
+    /// The song that plays first when starting from nothing.
+    pub fn first_song(&self) -> usize {
+        self.song_at(0).unwrap_or(0)
+    }
+
+    /// The song that plays after the song at `idx`, the queue goes first.
+    fn next_song_idx(&self, idx: usize) -> Option<usize> {
+        self.queue.front().copied().or_else(|| self.song_after(idx))
+    }
+
+    /// The song that plays when the current one ends.
+    pub fn next_song_id(&self) -> Option<usize> {
+        self.next_song_idx(self.currently_playing_id()?)
+    }
+
+    /// Removes `idx` from the front of the queue when it's about to play.
+    fn take_queued(&mut self, idx: usize) {
+        if self.queue.front() == Some(&idx) {
+            self.queue.pop_front();
+        }
+    }
+
+    /// Counts the play and adds it to the history, unless it's going back and forth in there.
+    fn song_started(&mut self, idx: usize) {
+        let now = plays::now();
+        self.songs_started += 1;
+        self.plays.record(&self.songs[idx].path, now);
+        if self
+            .history_position
+            .is_some_and(|position| self.history[position].idx == idx)
+        {
+            return;
+        }
+        self.history_position = None;
+        self.history.push_back(HistoryEntry {
+            idx,
+            played_at: now,
+        });
+        if self.history.len() > HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+    }
+
+    /// Recently played songs, the newest last.
+    pub fn history(&self) -> &VecDeque<HistoryEntry> {
+        &self.history
+    }
+
+    /// The entry of the current song in `history`.
+    pub fn history_position(&self) -> Option<usize> {
+        let current = self.currently_playing_id()?;
+        self.history_position.or_else(|| {
+            let last = self.history.len().checked_sub(1)?;
+            Some(last).filter(|&last| self.history[last].idx == current)
+        })
+    }
+
+    pub fn queue(&self) -> &VecDeque<usize> {
+        &self.queue
+    }
+
+    /// Adds the song at `idx` to the end of the queue.
+    pub fn enqueue(&mut self, idx: usize) {
+        if idx < self.songs.len() {
+            self.queue.push_back(idx);
+        }
+    }
+
+    /// Puts the song at `idx` at the front of the queue, so it plays after the current one.
+    pub fn queue_next(&mut self, idx: usize) {
+        if idx < self.songs.len() {
+            self.queue.push_front(idx);
+        }
+    }
+
+    /// Removes the entry at `position` of the queue.
+    pub fn dequeue(&mut self, position: usize) {
+        self.queue.remove(position);
+    }
+
+    /// Moves the entry at `from` of the queue to `to`.
+    pub fn move_queued(&mut self, from: usize, to: usize) {
+        if to < self.queue.len() {
+            if let Some(idx) = self.queue.remove(from) {
+                self.queue.insert(to, idx);
+            }
+        }
+    }
+
+    pub fn clear_queue(&mut self) {
+        self.queue.clear();
+    }
+
+    /// Whether the current song is close enough to its end to start fading into the next one.
+    fn crossfade_due(&self, audio: &B) -> bool {
+        let Some(ref song) = self.current_song else {
+            return false;
+        };
+        // short songs get half of their length at most
+        let duration = self.crossfade_song_time().min(song.get_music_length(audio) / 2.0);
+        self.crossfade > 0.0 && song.is_playing(audio) && song.time_left(audio) <= duration
+    }
+
+    fn preload_next_song(&mut self, audio: &mut B) {
+        let Some(ref song) = self.current_song else {
+            return;
+        };
+        if song.time_left(audio) > (PRELOAD_TIME + self.crossfade) * self.speed {
+            return;
+        }
+        let Some(next) = self.next_song_idx(song.idx) else {
+            self.next_song = None;
+            self.next_song_for = None;
+            return;
+        };
+        if self.next_song_for == Some((next, self.version)) {
+            return;
+        }
+        self.next_song_for = Some((next, self.version));
+        // errors are recorded when the song actually gets played
+        self.next_song = self.load_song(next, audio).ok();
+    }
+
+    /// Starts the preloaded song if it is the one at `idx`, otherwise loads it now.
+    fn play_preloaded(&mut self, idx: usize, audio: &mut B, screen_height: i32) {
+        let preloaded = self.next_song.take();
+        let valid = self.next_song_for.take() == Some((idx, self.version));
+        match preloaded.filter(|_| valid) {
+            Some(song) => self.start_song(song, audio, screen_height),
+            None => self.load_and_start(idx, audio, screen_height),
+        }
+    }
+
+    /// Plays the first queued song, or the song after the current one, wrapping around at the end
+    /// of the playlist.
+    pub fn play_next(&mut self, audio: &mut B, screen_height: i32) {
+        let forward = self
+            .history_position
+            .map(|position| position + 1)
+            .filter(|&position| position < self.history.len());
+        if let Some(idx) = self.queue.pop_front() {
+            self.play_ignore_err(idx, audio, screen_height);
+        } else if let Some(position) = forward {
+            // after going back with `play_previous`
+            self.history_position = Some(position);
+            self.play_ignore_err(self.history[position].idx, audio, screen_height);
+        } else if let Some(idx) = self.currently_playing_id() {
+            let position = self.order_position(idx) + 1;
+            let next = self.song_at(position).unwrap_or(self.first_song());
+            self.play_ignore_err(next, audio, screen_height);
+        } else {
+            self.play_ignore_err(self.first_song(), audio, screen_height);
+        }
+    }
 
-            std::mem::swap(&mut self.songs[idx_new], &mut tmp_song);
-            std::mem::swap(&mut self.songs[idx_old], &mut tmp_song);
-            std::mem::swap(&mut self.songs[idx_new], &mut tmp_song);
+    /// Plays the song that played before the current one, going back further each time.
+    /// `play_next` goes forward again. Without earlier history, it's the song before the current
+    /// one in play order.
+    pub fn play_previous(&mut self, audio: &mut B, screen_height: i32) {
+        let position = self.history_position.unwrap_or_else(|| {
+            // the current song is the last entry, a stopped one isn't
+            match (self.history.back(), self.currently_playing_id()) {
+                (Some(last), Some(current)) if last.idx == current => self.history.len() - 1,
+                _ => self.history.len(),
+            }
+        });
+        if let Some(position) = position.checked_sub(1) {
+            self.history_position = Some(position);
+            self.play_ignore_err(self.history[position].idx, audio, screen_height);
+            return;
         }
+
+        let position = self
+            .currently_playing_id()
+            .map_or(0, |idx| self.order_position(idx));
+        let idx = self
+            .song_at(position.saturating_sub(1))
+            .unwrap_or(self.len().saturating_sub(1));
+        self.play_ignore_err(idx, audio, screen_height);
     }
 
     pub fn len(&self) -> usize {
         self.songs.len()
     }
 
+    /// Changes whenever songs are added, removed or reordered.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
     pub fn currently_playing_id(&self) -> Option<usize> {
         match self.current_song {
             Some(ref song) => Some(song.idx),
@@ -387,16 +1235,22 @@ impl Playlist {
         }
     }
 
-    pub fn stop_playing(&mut self, audio: &mut RaylibAudio) {
+    pub fn stop_playing(&mut self, audio: &mut B) {
+        self.remember_position(audio);
+        self.resumed_from = None;
         self.pause(audio);
         self.current_song = None;
+        self.next_song = None;
+        self.next_song_for = None;
+        self.fading_out.clear();
+        self.clear_ab_loop();
     }
 
     pub fn has_music_stream(&self) -> bool {
         self.current_song.is_some()
     }
 
-    pub fn is_music_playing(&self, audio: &RaylibAudio) -> bool {
+    pub fn is_music_playing(&self, audio: &B) -> bool {
         if let Some(ref song) = self.current_song {
             song.is_playing(audio)
         } else {
@@ -404,7 +1258,7 @@ impl Playlist {
         }
     }
 
-    pub fn music_has_reached_the_end(&self, audio: &RaylibAudio) -> bool {
+    pub fn music_has_reached_the_end(&self, audio: &B) -> bool {
         if let Some(ref song) = self.current_song {
             song.reached_end(audio)
         } else {
@@ -412,21 +1266,21 @@ impl Playlist {
         }
     }
 
-    pub fn progress(&self, audio: &RaylibAudio) -> f32 {
+    pub fn progress(&self, audio: &B) -> f32 {
         match self.current_song {
             None => 0.0,
             Some(ref song) => song.progress(audio),
         }
     }
 
-    pub fn music_length_played(&self, audio: &RaylibAudio) -> f32 {
+    pub fn music_length_played(&self, audio: &B) -> f32 {
         match self.current_song {
             None => 0.0,
             Some(ref song) => song.get_music_length_played(audio),
         }
     }
 
-    pub fn music_length_total(&self, audio: &RaylibAudio) -> f32 {
+    pub fn music_length_total(&self, audio: &B) -> f32 {
         match self.current_song {
             None => 0.0,
             Some(ref song) => song.get_music_length(audio),
@@ -447,15 +1301,20 @@ impl Playlist {
         }
     }
 
-    pub fn currently_playing(&mut self) -> Option<&mut PlayingSong> {
+    pub fn currently_playing(&mut self) -> Option<&mut PlayingSong<B>> {
         match self.current_song {
             Some(ref mut v) => Some(v),
             None => None,
         }
     }
 
-    pub fn clear(&mut self, audio: &mut RaylibAudio) {
+    pub fn clear(&mut self, audio: &mut B) {
         self.songs.clear();
+        self.queue.clear();
+        self.order.clear();
+        self.history.clear();
+        self.history_position = None;
+        self.version += 1;
         self.stop_playing(audio);
     }
 
@@ -465,6 +1324,16 @@ impl Playlist {
 
     pub fn add_song(&mut self, entry: SongEntry) {
         self.songs.push(entry);
+        self.version += 1;
+        if self.shuffle_behavior != ShuffleBehavior::Normal {
+            // somewhere in what is still to come
+            let start = self
+                .currently_playing_id()
+                .map_or(0, |idx| self.order_position(idx) + 1)
+                .min(self.order.len());
+            let position = start + self.rng.value_up_to(self.order.len() - start);
+            self.order.insert(position, self.songs.len() - 1);
+        }
     }
 
     pub fn add_song_by_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
@@ -472,31 +1341,55 @@ impl Playlist {
         if metadata.is_dir() {
             load_dir_recursively_mut_vec(&path, self);
         } else if metadata.is_file() {
-            if let Some(extension) = path.as_ref().extension() {
-                if SUPPORTED_FORMATS
-                    .iter()
-                    .find(|&&ext| ext == extension)
-                    .is_some()
-                {
-                    SongEntry::new(path.as_ref().to_path_buf()).map(|entry| self.add_song(entry));
-                }
-            }
+            self.add_song_file(path.as_ref());
         }
 
         Ok(())
     }
 
+    /// Adds a song or loads a playlist, depending on the extension of `path`. Files with other
+    /// extensions are ignored.
+    fn add_song_file(&mut self, path: &Path) {
+        let Some(extension) = path.extension() else {
+            return;
+        };
+        if self.library.is_supported(extension) {
+            if let Some(entry) =
+                SongEntry::with_arbitrary_dirs(path.to_path_buf(), &self.library.arbitrary_dirs)
+            {
+                self.add_song(entry);
+            }
+        } else if extension == "m3u" {
+            if let Err(error) = self.load_from_file(path) {
+                self.errors.push(PlaylistError::Add {
+                    path: path.to_path_buf(),
+                    error,
+                });
+            }
+        }
+    }
+
     pub fn remove_song(
         &mut self,
         idx: usize,
-        thread: &RaylibThread,
-        audio: &mut RaylibAudio,
+        audio: &mut B,
         screen_height: i32,
     ) {
         if idx >= self.songs.len() {
             return;
         }
         self.songs.remove(idx);
+        self.version += 1;
+        self.queue.retain(|queued| *queued != idx);
+        self.order.retain(|song| *song != idx);
+        self.history.retain(|entry| entry.idx != idx);
+        self.history_position = None;
+        let played = self.history.iter_mut().map(|entry| &mut entry.idx);
+        for queued in self.queue.iter_mut().chain(self.order.iter_mut()).chain(played) {
+            if *queued > idx {
+                *queued -= 1;
+            }
+        }
         let len = self.len();
         if self.__render_current_selected > len && len > 0 {
             self.__render_current_selected = len - 1;
@@ -507,10 +1400,10 @@ impl Playlist {
                 // play the next one or the previous one, whichever exists (or stop the music)
                 if song_index >= len && len > 0 {
                     // the next one doesnt exist and the previous one does
-                    self.play_invalidated_ids_no_err(song_index - 1, thread, audio, screen_height);
+                    self.play_invalidated_ids_no_err(song_index - 1, audio, screen_height);
                 } else if len > 0 && song_index < len {
                     // the next one does exist
-                    self.play_invalidated_ids_no_err(song_index, thread, audio, screen_height);
+                    self.play_invalidated_ids_no_err(song_index, audio, screen_height);
                 } else {
                     self.stop_playing(audio);
                 }
@@ -543,37 +1436,51 @@ impl Playlist {
     }
 
     #[allow(dead_code)]
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Self {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
         let mut me = Self::default();
-        me.load_from_file(path);
-        me
+        me.load_from_file(path)?;
+        Ok(me)
     }
 
-    pub fn load_from_file<P: AsRef<Path>>(&mut self, path: P) {
-        match path.as_ref().extension() {
-            Some(ext) => {
-                if ext != "m3u" {
-                    return;
-                }
-            }
-            _ => return,
+    /// Adds all songs of an .m3u playlist. Entries that can't be added are collected as errors
+    /// (see `take_errors`), only failing to read the playlist itself is returned.
+    pub fn load_from_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        if path.as_ref().extension().is_none_or(|ext| ext != "m3u") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not an .m3u playlist",
+            ));
         }
 
-        let str = match fs::read_to_string(path) {
-            Ok(v) => v,
-            _ => return,
-        };
-        for file in str.split('\n') {
-            let _ = self.add_song_by_path(file);
+        let str = fs::read_to_string(&path)?;
+        let base = path.as_ref().parent().unwrap_or(Path::new(""));
+        for file in str.lines() {
+            let file = file.trim();
+            // skip empty lines and comments/extended m3u directives
+            if file.is_empty() || file.starts_with('#') {
+                continue;
+            }
+            // relative paths are relative to the playlist, not the working directory
+            let file = base.join(file);
+            if let Err(error) = self.add_song_by_path(&file) {
+                self.errors.push(PlaylistError::Add { path: file, error });
+            }
         }
+
+        Ok(())
     }
 }
 
-pub type CurrentSong = Option<PlayingSong>;
+pub type CurrentSong<B> = Option<PlayingSong<B>>;
 
 pub const SUPPORTED_FORMATS: &[&str] = &["mp3", "ogg", "wav", "qoa", "flac", "xm", "mod"];
+pub const ARBITRARY_DIRS: &[&str] = &["_", "unordered", "any", "unknown", "random"];
 
-fn process_entry(path: &dyn AsRef<Path>, entry: &DirEntry, playlist: &mut Playlist) {
+fn process_entry<B: AudioBackend>(
+    path: &dyn AsRef<Path>,
+    entry: &DirEntry,
+    playlist: &mut Playlist<B>,
+) {
     let typ = match entry.file_type() {
         Ok(v) => v,
         _ => return,
@@ -585,24 +1492,14 @@ fn process_entry(path: &dyn AsRef<Path>, entry: &DirEntry, playlist: &mut Playli
             playlist,
         );
     } else if typ.is_file() {
-        let file_path = entry.file_name();
-        let file_path = Path::new(&file_path);
-        if let Some(extension) = file_path.extension() {
-            if SUPPORTED_FORMATS
-                .iter()
-                .find(|&&ext| ext == extension)
-                .is_some()
-            {
-                SongEntry::new(Path::join(path.as_ref(), entry.file_name()))
-                    .map(|entry| playlist.add_song(entry));
-            } else if extension == "m3u" {
-                playlist.load_from_file(Path::join(path.as_ref(), entry.file_name()))
-            }
-        }
+        playlist.add_song_file(&Path::join(path.as_ref(), entry.file_name()));
     }
 }
 
-fn load_dir_recursively_mut_vec(path: &dyn AsRef<Path>, vec: &mut Playlist) -> Option<()> {
+fn load_dir_recursively_mut_vec<B: AudioBackend>(
+    path: &dyn AsRef<Path>,
+    vec: &mut Playlist<B>,
+) -> Option<()> {
     for entry in fs::read_dir(path).ok()? {
         if let Ok(entry) = entry {
             process_entry(path, &entry, vec);
@@ -611,3 +1508,284 @@ fn load_dir_recursively_mut_vec(path: &dyn AsRef<Path>, vec: &mut Playlist) -> O
 
     Some(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_null::NullBackend;
+
+    /// Empty song files in a temporary folder, which is removed again when this is dropped. The
+    /// null backend only checks that they exist.
+    struct Songs(PathBuf);
+
+    impl Songs {
+        fn new(name: &str, count: usize) -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("mp3-player-test-{}-{name}", std::process::id()));
+            _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            for idx in 0..count {
+                fs::write(dir.join(format!("song {idx}.mp3")), "").unwrap();
+            }
+            Self(dir)
+        }
+
+        fn path(&self, idx: usize) -> PathBuf {
+            self.0.join(format!("song {idx}.mp3"))
+        }
+
+        fn playlist(&self) -> Playlist<NullBackend> {
+            let mut playlist = Playlist::default();
+            let mut idx = 0;
+            while self.path(idx).exists() {
+                playlist.add_song(SongEntry::new(self.path(idx)).unwrap());
+                idx += 1;
+            }
+            playlist
+        }
+    }
+
+    impl Drop for Songs {
+        fn drop(&mut self) {
+            _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Songs are `length` seconds long and play a second per update.
+    fn audio(length: f32) -> NullBackend {
+        NullBackend::new().with_step(1.0).with_default_length(length)
+    }
+
+    /// Updates the playlist like the GUI does every frame until the next song starts, and returns
+    /// that song. `None` if the playlist ran out instead.
+    fn play_to_end(playlist: &mut Playlist<NullBackend>, audio: &mut NullBackend) -> Option<usize> {
+        let started = playlist.songs_started();
+        for _ in 0..1000 {
+            playlist.update(audio);
+            if playlist.handle_song_end(audio, 0) {
+                return None;
+            }
+            if playlist.songs_started() != started {
+                return playlist.currently_playing_id();
+            }
+        }
+        panic!("the song didn't end");
+    }
+
+    #[test]
+    fn behaviors_round_trip_through_their_names() {
+        let mut repeat = RepeatBehavior::Normal;
+        let mut shuffle = ShuffleBehavior::Normal;
+        for _ in 0..3 {
+            assert!(repeat.as_str().parse() == Ok(repeat));
+            assert!(shuffle.as_str().parse() == Ok(shuffle));
+            repeat.next();
+            shuffle.next();
+        }
+        assert!("sometimes".parse::<RepeatBehavior>().is_err());
+        assert!("sometimes".parse::<ShuffleBehavior>().is_err());
+    }
+
+    #[test]
+    fn extensions_are_matched_in_any_case() {
+        let library = LibraryOptions::default();
+        assert!(library.is_supported(OsStr::new("mp3")));
+        assert!(library.is_supported(OsStr::new("MP3")));
+        assert!(library.is_supported(OsStr::new("Flac")));
+        assert!(!library.is_supported(OsStr::new("txt")));
+    }
+
+    #[test]
+    fn next_and_previous_follow_the_playlist() {
+        let songs = Songs::new("next-previous", 3);
+        let mut playlist = songs.playlist();
+        let mut audio = audio(30.0);
+
+        playlist.play_next(&mut audio, 0);
+        assert_eq!(playlist.currently_playing_id(), Some(0));
+        playlist.play_next(&mut audio, 0);
+        assert_eq!(playlist.currently_playing_id(), Some(1));
+        playlist.play_next(&mut audio, 0);
+        playlist.play_next(&mut audio, 0);
+        assert_eq!(playlist.currently_playing_id(), Some(0));
+
+        // without any history to go back to, it's the song before in the playlist
+        let mut playlist = songs.playlist();
+        playlist.play_ignore_err(2, &mut audio, 0);
+        playlist.play_previous(&mut audio, 0);
+        assert_eq!(playlist.currently_playing_id(), Some(1));
+    }
+
+    #[test]
+    fn songs_end_by_repeat_behavior() {
+        let songs = Songs::new("repeat", 3);
+        let mut playlist = songs.playlist();
+        let mut audio = audio(5.0);
+
+        playlist.play_ignore_err(0, &mut audio, 0);
+        assert_eq!(play_to_end(&mut playlist, &mut audio), Some(1));
+        assert_eq!(play_to_end(&mut playlist, &mut audio), Some(2));
+        assert_eq!(play_to_end(&mut playlist, &mut audio), None);
+        assert!(!playlist.has_music_stream());
+
+        playlist.repeat_behavior = RepeatBehavior::Repeat;
+        playlist.play_ignore_err(2, &mut audio, 0);
+        assert_eq!(play_to_end(&mut playlist, &mut audio), Some(0));
+
+        playlist.repeat_behavior = RepeatBehavior::RepeatSingle;
+        assert_eq!(play_to_end(&mut playlist, &mut audio), Some(0));
+        assert_eq!(play_to_end(&mut playlist, &mut audio), Some(0));
+    }
+
+    #[test]
+    fn queued_songs_play_first() {
+        let songs = Songs::new("queue", 4);
+        let mut playlist = songs.playlist();
+        let mut audio = audio(5.0);
+
+        playlist.play_ignore_err(0, &mut audio, 0);
+        playlist.enqueue(3);
+        playlist.enqueue(1);
+        assert_eq!(playlist.next_song_id(), Some(3));
+        assert_eq!(play_to_end(&mut playlist, &mut audio), Some(3));
+        assert_eq!(playlist.queue(), &[1]);
+        assert_eq!(play_to_end(&mut playlist, &mut audio), Some(1));
+        assert!(playlist.queue().is_empty());
+        // and then it goes on after the queued song
+        assert_eq!(play_to_end(&mut playlist, &mut audio), Some(2));
+
+        playlist.enqueue(3);
+        playlist.queue_next(0);
+        playlist.play_next(&mut audio, 0);
+        assert_eq!(playlist.currently_playing_id(), Some(0));
+        assert_eq!(playlist.queue(), &[3]);
+    }
+
+    #[test]
+    fn previous_walks_back_through_the_history() {
+        let songs = Songs::new("history", 5);
+        let mut playlist = songs.playlist();
+        let mut audio = audio(30.0);
+        let history = |playlist: &Playlist<NullBackend>| -> Vec<usize> {
+            playlist.history().iter().map(|entry| entry.idx).collect()
+        };
+
+        playlist.play_ignore_err(0, &mut audio, 0);
+        playlist.play_ignore_err(3, &mut audio, 0);
+        playlist.play_next(&mut audio, 0);
+        assert_eq!(history(&playlist), [0, 3, 4]);
+
+        playlist.play_previous(&mut audio, 0);
+        assert_eq!(playlist.currently_playing_id(), Some(3));
+        playlist.play_previous(&mut audio, 0);
+        assert_eq!(playlist.currently_playing_id(), Some(0));
+        assert_eq!(playlist.history_position(), Some(0));
+
+        // going forward again retraces the history without adding to it
+        playlist.play_next(&mut audio, 0);
+        assert_eq!(playlist.currently_playing_id(), Some(3));
+        playlist.play_next(&mut audio, 0);
+        assert_eq!(playlist.currently_playing_id(), Some(4));
+        assert_eq!(history(&playlist), [0, 3, 4]);
+        playlist.play_next(&mut audio, 0);
+        assert_eq!(playlist.currently_playing_id(), Some(0));
+        assert_eq!(history(&playlist), [0, 3, 4, 0]);
+
+        playlist.remove_song(3, &mut audio, 0);
+        assert_eq!(history(&playlist), [0, 3, 0]);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let songs = Songs::new("history-length", 2);
+        let mut playlist = songs.playlist();
+        let mut audio = audio(30.0);
+
+        for _ in 0..HISTORY_LENGTH + 10 {
+            playlist.play_next(&mut audio, 0);
+        }
+        assert_eq!(playlist.history().len(), HISTORY_LENGTH);
+        assert_eq!(playlist.history_position(), Some(HISTORY_LENGTH - 1));
+    }
+
+    #[test]
+    fn shuffle_starts_with_the_current_song() {
+        let songs = Songs::new("shuffle", 20);
+        let order = |seed| {
+            let mut audio = audio(30.0);
+            let mut playlist = songs.playlist();
+            playlist.play_ignore_err(7, &mut audio, 0);
+            playlist.set_shuffle_behavior(ShuffleBehavior::Shuffle);
+            playlist.seed_shuffle(seed);
+            playlist.order.clone()
+        };
+
+        let shuffled = order(1);
+        assert_eq!(shuffled, order(1));
+        assert_ne!(shuffled, order(2));
+        assert_eq!(shuffled[0], 7);
+        let mut sorted = shuffled.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..20).collect::<Vec<_>>());
+
+        // the playlist itself stays the same, next follows the order
+        let mut audio = audio(30.0);
+        let mut playlist = songs.playlist();
+        playlist.play_ignore_err(7, &mut audio, 0);
+        playlist.set_shuffle_behavior(ShuffleBehavior::Shuffle);
+        playlist.seed_shuffle(1);
+        assert_eq!(playlist.get_songs()[7].path(), songs.path(7));
+        playlist.play_next(&mut audio, 0);
+        assert_eq!(playlist.currently_playing_id(), Some(shuffled[1]));
+    }
+
+    #[test]
+    fn next_song_is_preloaded() {
+        let songs = Songs::new("preload", 2);
+        let mut playlist = songs.playlist();
+        let mut audio = audio(30.0);
+
+        playlist.play_ignore_err(0, &mut audio, 0);
+        for _ in 0..10 {
+            playlist.update(&mut audio);
+            playlist.handle_song_end(&mut audio, 0);
+        }
+        assert!(playlist.next_song.is_none());
+        for _ in 0..15 {
+            playlist.update(&mut audio);
+            playlist.handle_song_end(&mut audio, 0);
+        }
+        assert!(playlist.next_song.is_some());
+
+        // the preloaded stream is used, so the file isn't opened again
+        fs::remove_file(songs.path(1)).unwrap();
+        assert_eq!(play_to_end(&mut playlist, &mut audio), Some(1));
+        assert!(playlist.take_errors().is_empty());
+    }
+
+    #[test]
+    fn crossfade_starts_before_the_end() {
+        let songs = Songs::new("crossfade", 2);
+        let mut playlist = songs.playlist();
+        let mut audio = audio(10.0);
+        playlist.crossfade = 3.0;
+
+        playlist.play_ignore_err(0, &mut audio, 0);
+        assert_eq!(play_to_end(&mut playlist, &mut audio), Some(1));
+        assert_eq!(playlist.fading_out.len(), 1);
+        assert!(!playlist.fading_out[0].reached_end(&audio));
+        assert!(playlist.currently_playing().unwrap().is_fading());
+
+        for _ in 0..4 {
+            playlist.update(&mut audio);
+        }
+        assert!(playlist.fading_out.is_empty());
+        assert!(!playlist.currently_playing().unwrap().is_fading());
+
+        // without a crossfade, the old song plays to its end
+        playlist.crossfade = 0.0;
+        playlist.play_ignore_err(0, &mut audio, 0);
+        assert_eq!(play_to_end(&mut playlist, &mut audio), Some(1));
+        assert!(playlist.fading_out.is_empty());
+    }
+}
@@ -0,0 +1,302 @@
+//! Playback speed. Backends change the speed by resampling, which changes the pitch by the same
+//! factor. `PitchCorrector` shifts it back with WSOLA (waveform similarity overlap-add), so only
+//! the tempo changes.
+
+use std::f64::consts::PI;
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 3.0;
+/// What the speed button and the keyboard shortcuts step through.
+pub const SPEED_STEPS: [f32; 9] = [0.5, 0.75, 1.0, 1.25, 1.5, 1.75, 2.0, 2.5, 3.0];
+
+/// The next step above `speed`, or the highest one.
+pub fn faster(speed: f32) -> f32 {
+    SPEED_STEPS
+        .into_iter()
+        .find(|step| *step > speed + 0.01)
+        .unwrap_or(MAX_SPEED)
+}
+
+/// The next step below `speed`, or the lowest one.
+pub fn slower(speed: f32) -> f32 {
+    SPEED_STEPS
+        .into_iter()
+        .rev()
+        .find(|step| *step < speed - 0.01)
+        .unwrap_or(MIN_SPEED)
+}
+
+/// Grains are overlapped by half their length.
+const GRAIN_SECONDS: f64 = 0.04;
+/// How far a grain may be moved to line up with the previous one.
+const SEARCH_SECONDS: f64 = 0.01;
+/// Only every n-th sample is compared when searching, that's plenty to find the best position.
+const SEARCH_STRIDE: usize = 4;
+/// Input history in frames, a power of two. Buffers are processed in chunks of at most a quarter
+/// of it.
+const RING_FRAMES: usize = 1 << 16;
+const MAX_CHUNK: usize = RING_FRAMES / 4;
+
+/// Shifts the pitch of a stream of interleaved samples by `1 / speed` without changing its
+/// length, which undoes the pitch change of playing it `speed` times as fast by resampling.
+///
+/// Grains of the input are read at `1 / speed` times the rate and overlapped with a Hann window.
+/// Each grain is moved by up to `SEARCH_SECONDS` to where it is most similar to the continuation
+/// of the previous grain, so the overlaps don't cancel out. The output lags behind the input by a
+/// bit less than 100ms.
+pub struct PitchCorrector {
+    channels: usize,
+    sample_rate: u32,
+    speed: f32,
+    grain: usize,
+    hop: usize,
+    search: usize,
+    window: Vec<f32>,
+    ring: Vec<f32>,
+    /// input frames written so far
+    written: u64,
+    /// overlap-add of the grains, `grain` frames
+    accumulator: Vec<f32>,
+    /// finished output, `hop` frames, handed out from `ready_position` on
+    ready: Vec<f32>,
+    ready_position: usize,
+    /// input position of the next grain before it is moved
+    nominal: f64,
+    /// where the last grain started, `None` before the first one
+    previous: Option<f64>,
+    /// kept around so the audio thread doesn't allocate
+    target: Vec<f32>,
+}
+
+impl PitchCorrector {
+    pub fn new(speed: f32, sample_rate: u32, channels: usize) -> Self {
+        let grain = ((sample_rate as f64 * GRAIN_SECONDS) as usize / 2 * 2).max(4);
+        let hop = grain / 2;
+        let search = (sample_rate as f64 * SEARCH_SECONDS) as usize;
+        // a grain reads up to `grain / MIN_SPEED` frames ahead of where it starts
+        let delay = search + (grain as f32 / MIN_SPEED) as usize + 2;
+        let window = (0..grain)
+            .map(|n| (0.5 - 0.5 * (2.0 * PI * n as f64 / grain as f64).cos()) as f32)
+            .collect();
+        let channels = channels.max(1);
+        Self {
+            channels,
+            sample_rate,
+            speed: speed.clamp(MIN_SPEED, MAX_SPEED),
+            grain,
+            hop,
+            search,
+            window,
+            ring: vec![0.0; RING_FRAMES * channels],
+            written: 0,
+            accumulator: vec![0.0; grain * channels],
+            ready: vec![0.0; hop * channels],
+            ready_position: hop,
+            nominal: -(delay as f64),
+            previous: None,
+            target: Vec::with_capacity(hop / SEARCH_STRIDE + 1),
+        }
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    /// Shifts the pitch of `samples` in place. They have to start at a frame boundary and use the
+    /// channel count the corrector was created with.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for chunk in samples.chunks_mut(MAX_CHUNK * self.channels) {
+            self.process_chunk(chunk);
+        }
+    }
+
+    fn process_chunk(&mut self, samples: &mut [f32]) {
+        let channels = self.channels;
+        for frame in samples.chunks(channels) {
+            let start = (self.written as usize % RING_FRAMES) * channels;
+            self.ring[start..start + frame.len()].copy_from_slice(frame);
+            self.written += 1;
+        }
+        for frame in samples.chunks_mut(channels) {
+            if self.ready_position == self.hop {
+                self.add_grain();
+            }
+            let start = self.ready_position * channels;
+            frame.copy_from_slice(&self.ready[start..start + frame.len()]);
+            self.ready_position += 1;
+        }
+    }
+
+    fn add_grain(&mut self) {
+        let ratio = 1.0 / self.speed as f64;
+        let start = match self.previous {
+            Some(previous) => self.nominal + self.best_offset(previous, ratio) as f64,
+            None => self.nominal,
+        };
+
+        let channels = self.channels;
+        for n in 0..self.grain {
+            let position = start + ratio * n as f64;
+            for channel in 0..channels {
+                self.accumulator[n * channels + channel] +=
+                    self.window[n] * self.read(position, channel);
+            }
+        }
+
+        // the first hop doesn't get any more grains added to it
+        let hop_len = self.hop * channels;
+        self.ready.copy_from_slice(&self.accumulator[..hop_len]);
+        self.accumulator.copy_within(hop_len.., 0);
+        let len = self.accumulator.len();
+        self.accumulator[len - hop_len..].fill(0.0);
+        self.ready_position = 0;
+
+        self.previous = Some(start);
+        self.nominal += self.hop as f64;
+    }
+
+    /// The offset from the nominal position that lines the next grain up best with how the
+    /// previous grain would have continued.
+    fn best_offset(&mut self, previous: f64, ratio: f64) -> isize {
+        let continuation = previous + ratio * self.hop as f64;
+        let mut target = std::mem::take(&mut self.target);
+        target.clear();
+        target.extend(
+            (0..self.hop)
+                .step_by(SEARCH_STRIDE)
+                .map(|n| self.read_mono(continuation + ratio * n as f64)),
+        );
+
+        let score = |offset: isize| {
+            let start = self.nominal + offset as f64;
+            let (mut correlation, mut energy) = (0.0, 1e-9);
+            for (i, target) in target.iter().enumerate() {
+                let sample = self.read_mono(start + ratio * (i * SEARCH_STRIDE) as f64);
+                correlation += target * sample;
+                energy += sample * sample;
+            }
+            correlation / energy.sqrt()
+        };
+        let best = |offsets: &mut dyn Iterator<Item = isize>| {
+            offsets
+                .map(|offset| (offset, score(offset)))
+                .fold((0, f32::MIN), |best, next| if next.1 > best.1 { next } else { best })
+                .0
+        };
+        // coarse first, then around the best coarse offset
+        let search = self.search as isize;
+        let coarse = best(&mut (-search..=search).step_by(SEARCH_STRIDE));
+        let stride = SEARCH_STRIDE as isize;
+        let fine = best(&mut (coarse - stride + 1..coarse + stride).filter(|o| o.abs() <= search));
+        self.target = target;
+        fine
+    }
+
+    /// Linearly interpolated input sample, silence outside of what was written so far.
+    fn read(&self, position: f64, channel: usize) -> f32 {
+        let floor = position.floor();
+        let frac = (position - floor) as f32;
+        let a = self.frame(floor as i64, channel);
+        let b = self.frame(floor as i64 + 1, channel);
+        a + (b - a) * frac
+    }
+
+    fn read_mono(&self, position: f64) -> f32 {
+        let frame = position.round() as i64;
+        (0..self.channels).map(|channel| self.frame(frame, channel)).sum()
+    }
+
+    fn frame(&self, frame: i64, channel: usize) -> f32 {
+        if frame < 0 || frame as u64 >= self.written {
+            return 0.0;
+        }
+        if self.written - frame as u64 > RING_FRAMES as u64 {
+            return 0.0;
+        }
+        self.ring[(frame as usize % RING_FRAMES) * self.channels + channel]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+    const FREQUENCY: f64 = 440.0;
+
+    /// Two seconds of a stereo sine played `speed` times as fast, the way backends do it by
+    /// resampling, which raises the pitch as well.
+    fn resampled_sine(speed: f64) -> Vec<f32> {
+        let frames = (2.0 * SAMPLE_RATE as f64 / speed) as usize;
+        (0..frames)
+            .flat_map(|frame| {
+                let time = frame as f64 * speed / SAMPLE_RATE as f64;
+                let sample = (0.5 * (2.0 * PI * FREQUENCY * time).sin()) as f32;
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    /// The frequency of the left channel, by counting zero crossings.
+    fn frequency(samples: &[f32]) -> f64 {
+        let left: Vec<f32> = samples.iter().step_by(2).copied().collect();
+        let crossings = left
+            .windows(2)
+            .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+            .count();
+        crossings as f64 / 2.0 / (left.len() as f64 / SAMPLE_RATE as f64)
+    }
+
+    fn check_pitch_is_kept(speed: f32) {
+        let mut samples = resampled_sine(speed as f64);
+        assert!((frequency(&samples) / FREQUENCY - speed as f64).abs() < 0.01);
+
+        let mut corrector = PitchCorrector::new(speed, SAMPLE_RATE, 2);
+        for chunk in samples.chunks_mut(1024) {
+            corrector.process(chunk);
+        }
+        // the resampling changes the length, the corrector keeps it
+        assert_eq!(
+            samples.len() / 2,
+            (2.0 * SAMPLE_RATE as f32 / speed) as usize
+        );
+
+        // skip the delay at the start
+        let output = &samples[SAMPLE_RATE as usize / 5 * 2..];
+        let frequency = frequency(output);
+        assert!(
+            (frequency / FREQUENCY - 1.0).abs() < 0.03,
+            "{frequency} Hz at {speed}x"
+        );
+    }
+
+    #[test]
+    fn keeps_the_pitch_when_faster() {
+        check_pitch_is_kept(1.5);
+    }
+
+    #[test]
+    fn keeps_the_pitch_when_slower() {
+        check_pitch_is_kept(0.75);
+    }
+
+    #[test]
+    fn steps_stop_at_the_ends() {
+        assert_eq!(faster(1.0), 1.25);
+        assert_eq!(slower(1.0), 0.75);
+        assert_eq!(faster(2.9), MAX_SPEED);
+        assert_eq!(faster(MAX_SPEED), MAX_SPEED);
+        assert_eq!(slower(0.6), MIN_SPEED);
+        assert_eq!(slower(MIN_SPEED), MIN_SPEED);
+        assert_eq!(faster(SPEED_STEPS[SPEED_STEPS.len() - 2]), MAX_SPEED);
+        assert_eq!(slower(SPEED_STEPS[1]), MIN_SPEED);
+    }
+}